@@ -7,11 +7,22 @@ pub mod jwt_authentication {
     pub struct Jwt {
         shared_secret: String,
     }
+
+    // Claims identifies a user session. sub is the internal user id
+    // and sid the id of the server side session
     #[derive(Debug, Serialize, Deserialize)]
-    struct Claims {
-        sub: String,
-        company: String,
-        exp: usize,
+    pub struct Claims {
+        pub sub: String,
+        pub sid: String,
+        pub iat: usize,
+        pub exp: usize,
+    }
+
+    fn seconds_since_epoch(time: SystemTime) -> Result<usize, io::Error> {
+        match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(res) => Ok(res.as_secs() as usize),
+            Err(err) => Err(io::Error::new(io::ErrorKind::Other, err.to_string())),
+        }
     }
 
     impl Jwt {
@@ -21,22 +32,20 @@ pub mod jwt_authentication {
             }
         }
 
-        pub fn new_token(&self) -> Result<String, io::Error> {
-            let expiry_interval = Duration::new(60 * 60, 0);
-
-            let expiration_time_res = SystemTime::now()
-                .add(expiry_interval)
-                .duration_since(SystemTime::UNIX_EPOCH);
-
-            let expiration_time = match expiration_time_res {
-                Ok(res) => res.as_secs(),
-                Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err.to_string())),
-            };
-
+        // new_token issues a token for the session of a user that
+        // expires after the given interval
+        pub fn new_token(
+            &self,
+            sub: &str,
+            session_id: &str,
+            expiry_interval: Duration,
+        ) -> Result<String, io::Error> {
+            let now = SystemTime::now();
             let claims = Claims {
-                sub: String::from("ok"),
-                company: String::from("ok"),
-                exp: expiration_time as usize,
+                sub: sub.to_string(),
+                sid: session_id.to_string(),
+                iat: seconds_since_epoch(now)?,
+                exp: seconds_since_epoch(now.add(expiry_interval))?,
             };
             let jwt = match encode(
                 &Header::default(),
@@ -50,17 +59,18 @@ pub mod jwt_authentication {
             Ok(jwt)
         }
 
-        fn validate_token(&self, token: &str) -> Result<(), io::Error> {
+        // validate_token checks the signature and expiry of the token
+        // and returns its claims
+        pub fn validate_token(&self, token: &str) -> Result<Claims, io::Error> {
             let decoded_jwt = decode::<Claims>(
                 token,
                 &DecodingKey::from_secret(self.shared_secret.as_ref()),
                 &Validation::default(),
             );
-            let _ = match decoded_jwt {
-                Ok(res) => res,
-                Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err)),
-            };
-            Ok(())
+            match decoded_jwt {
+                Ok(res) => Ok(res.claims),
+                Err(err) => Err(io::Error::new(io::ErrorKind::PermissionDenied, err)),
+            }
         }
     }
 }
@@ -68,12 +68,18 @@ pub mod app {
             })
         }
 
-        pub fn authenticate_app(
-            &self,
-            installation_id: String,
-        ) -> Result<InstallationAccessToken, Error> {
+        // app_jwt signs the JWT that authenticates the GitHub App itself
+        pub fn app_jwt(&self) -> Result<String, Error> {
             let rsa_pem_u8: &[u8] = &self.rsa_pem;
-            let pem_encoding_key = jsonwebtoken::EncodingKey::from_rsa_pem(rsa_pem_u8).unwrap();
+            let pem_encoding_key = match jsonwebtoken::EncodingKey::from_rsa_pem(rsa_pem_u8) {
+                Ok(key) => key,
+                Err(err) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("invalid GitHub App private key: {}", err),
+                    ))
+                }
+            };
             let new_auth_claim = match self.new_claim() {
                 Ok(claims) => claims,
                 Err(err) => return Err(Error::new(ErrorKind::Other, err)),
@@ -81,11 +87,17 @@ pub mod app {
 
             let rs256_header = Header::new(jsonwebtoken::Algorithm::RS256);
 
-            let jwt_string =
-                match jsonwebtoken::encode(&rs256_header, &new_auth_claim, &pem_encoding_key) {
-                    Ok(jwt) => jwt,
-                    Err(err) => return Err(Error::new(ErrorKind::Other, err)),
-                };
+            match jsonwebtoken::encode(&rs256_header, &new_auth_claim, &pem_encoding_key) {
+                Ok(jwt) => Ok(jwt),
+                Err(err) => Err(Error::new(ErrorKind::Other, err)),
+            }
+        }
+
+        pub fn authenticate_app(
+            &self,
+            installation_id: String,
+        ) -> Result<InstallationAccessToken, Error> {
+            let jwt_string = self.app_jwt()?;
             let req_client = reqwest::blocking::Client::new();
             let res = match req_client
                 .post(format!(
@@ -12,5 +12,9 @@ pub mod errors {
                 message: message,
             }
         }
+
+        pub fn status(&self) -> usize {
+            self.status
+        }
     }
 }
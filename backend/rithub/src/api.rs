@@ -3,9 +3,33 @@ pub mod api {
     use reqwest;
     use reqwest::header::ACCEPT;
     use serde::{Deserialize, Serialize};
+    use std::fmt;
+    use std::sync::Arc;
+    // CallInfo describes a finished call to the GitHub API
+    pub struct CallInfo<'a> {
+        pub endpoint: &'a str,
+        pub status: u16,
+        // rate_limit_remaining is the number of calls left in the current
+        // rate limit window
+        pub rate_limit_remaining: Option<i64>,
+    }
+
+    // CallObserver is told about every call to the GitHub API, for
+    // example to record metrics
+    #[derive(Clone)]
+    pub struct CallObserver(pub Arc<dyn Fn(&CallInfo) + Send + Sync>);
+
+    impl fmt::Debug for CallObserver {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "CallObserver")
+        }
+    }
+
     #[derive(Debug, Clone)]
     pub struct Config {
         access_token: String,
+        request_id: Option<String>,
+        observer: Option<CallObserver>,
     }
 
     fn empty_string() -> String {
@@ -31,11 +55,110 @@ pub mod api {
         body: String,
     }
 
+    #[derive(Debug, Deserialize)]
+    struct CreatedComment {
+        id: usize,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct Login {
+        pub login: String,
+    }
+
+    // PullRequestReview is a submitted review. state is APPROVED,
+    // CHANGES_REQUESTED, COMMENTED or DISMISSED
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct PullRequestReview {
+        pub id: usize,
+        pub user: Login,
+        pub state: String,
+        pub body: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct Commit {
+        pub sha: String,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct Branch {
+        pub name: String,
+        pub commit: Commit,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct PullRequestFile {
+        pub filename: String,
+        pub additions: usize,
+        pub deletions: usize,
+        pub changes: usize,
+    }
+
+    // CollaboratorPermission is the permission of a user on a repository.
+    // role_name distinguishes maintain and triage from write and read
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct CollaboratorPermission {
+        pub permission: String,
+        #[serde(default = "empty_string")]
+        pub role_name: String,
+    }
+
+    // Account is the public profile of a GitHub account. account_type is
+    // User, Organization or Bot
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct Account {
+        pub login: String,
+        #[serde(rename = "type")]
+        pub account_type: String,
+        #[serde(default)]
+        pub site_admin: bool,
+        pub created_at: String,
+    }
+
     impl Config {
         pub fn new(access_token: &str) -> Config {
             Config {
                 access_token: access_token.to_string(),
+                request_id: None,
+                observer: None,
+            }
+        }
+
+        pub fn with_observer(mut self, observer: Option<CallObserver>) -> Config {
+            self.observer = observer;
+            self
+        }
+
+        fn observe(&self, endpoint: &str, res: &reqwest::blocking::Response) {
+            if let Some(observer) = &self.observer {
+                let rate_limit_remaining = res
+                    .headers()
+                    .get("X-RateLimit-Remaining")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<i64>().ok());
+                (observer.0)(&CallInfo {
+                    endpoint,
+                    status: res.status().as_u16(),
+                    rate_limit_remaining,
+                });
+            }
+        }
+
+        // with_request_id sends the id of the request that caused the
+        // calls along with them so they can be traced
+        pub fn with_request_id(mut self, request_id: Option<String>) -> Config {
+            self.request_id = request_id;
+            self
+        }
+
+        fn trace_headers(&self) -> reqwest::header::HeaderMap {
+            let mut headers = reqwest::header::HeaderMap::new();
+            if let Some(request_id) = &self.request_id {
+                if let Ok(value) = reqwest::header::HeaderValue::from_str(request_id) {
+                    headers.insert("X-Request-Id", value);
+                }
             }
+            headers
         }
 
         fn get_authorization_header(&self) -> String {
@@ -47,12 +170,14 @@ pub mod api {
             let req = client
                 .get("https://api.github.com/user")
                 .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
                 .header(reqwest::header::USER_AGENT, "request");
 
             let resp = match req.send() {
                 Ok(r) => r,
                 Err(err) => return Err(Error::new(501, err.to_string())),
             };
+            self.observe("user", &resp);
             if !(resp.status() == reqwest::StatusCode::OK) {
                 return Err(Error::new(501, String::from("failed to get user. Not OK")));
             }
@@ -66,12 +191,14 @@ pub mod api {
             let req = client
                 .get("https://api.github.com/")
                 .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
                 .header(reqwest::header::USER_AGENT, "request");
 
             let resp = match req.send() {
                 Ok(r) => r,
                 Err(err) => return Err(Error::new(501, err.to_string())),
             };
+            self.observe("authenticate", &resp);
             Ok(())
         }
 
@@ -80,13 +207,37 @@ pub mod api {
             webhook_data: &webhook::webhook::WebhookRequest,
             message: &str,
         ) -> Result<(), Error> {
+            self.comment_on_issue(
+                &webhook_data.repository.owner.login,
+                &webhook_data.repository.name,
+                webhook_data.pull_request.number,
+                message,
+            )
+        }
+
+        // comment_on_issue comments on an issue or pull request by number
+        pub fn comment_on_issue(
+            &self,
+            owner: &str,
+            repo: &str,
+            number: usize,
+            message: &str,
+        ) -> Result<(), Error> {
+            self.create_comment(owner, repo, number, message)
+                .map(|_| ())
+        }
+
+        // create_comment comments on an issue or pull request and returns
+        // the id of the comment
+        pub fn create_comment(
+            &self,
+            owner: &str,
+            repo: &str,
+            number: usize,
+            message: &str,
+        ) -> Result<usize, Error> {
             let authorization_header = self.get_authorization_header();
-            let issue_path = format!(
-                "repos/{}/{}/issues/{}/comments",
-                webhook_data.repository.owner.login,
-                webhook_data.repository.name,
-                webhook_data.pull_request.number
-            );
+            let issue_path = format!("repos/{}/{}/issues/{}/comments", owner, repo, number);
             let url = format!("https://api.github.com/{}", issue_path);
             let client = reqwest::blocking::Client::new();
             let issue_comment = IssueComment {
@@ -95,6 +246,7 @@ pub mod api {
             let res = match client
                 .post(url)
                 .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
                 .header(reqwest::header::USER_AGENT, "request")
                 .header(ACCEPT, "application/json")
                 .json(&issue_comment)
@@ -103,24 +255,78 @@ pub mod api {
                 Ok(res) => res,
                 Err(err) => return Err(Error::new(501, err.to_string())),
             };
+            self.observe("comment_issue", &res);
 
             if !res.status().is_success() {
                 return Err(Error::new(501, String::from("failed to comment issue")));
             }
-            return Ok(());
+            match res.json::<CreatedComment>() {
+                Ok(comment) => Ok(comment.id),
+                Err(err) => Err(Error::new(501, err.to_string())),
+            }
+        }
+
+        // update_comment replaces the body of an issue or pull request comment
+        pub fn update_comment(
+            &self,
+            owner: &str,
+            repo: &str,
+            comment_id: usize,
+            message: &str,
+        ) -> Result<(), Error> {
+            let authorization_header = self.get_authorization_header();
+            let url = format!(
+                "https://api.github.com/repos/{}/{}/issues/comments/{}",
+                owner, repo, comment_id
+            );
+            let client = reqwest::blocking::Client::new();
+            let issue_comment = IssueComment {
+                body: message.to_string(),
+            };
+            let res = match client
+                .patch(url)
+                .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
+                .header(reqwest::header::USER_AGENT, "request")
+                .header(ACCEPT, "application/json")
+                .json(&issue_comment)
+                .send()
+            {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(501, err.to_string())),
+            };
+            self.observe("update_comment", &res);
+
+            if !res.status().is_success() {
+                return Err(Error::new(
+                    res.status().as_u16() as usize,
+                    String::from("failed to update comment"),
+                ));
+            }
+            Ok(())
         }
 
         pub fn list_review_comments(
             &self,
             webhook_data: &webhook::webhook::WebhookRequest,
+        ) -> Result<Vec<ReviewComment>, Error> {
+            self.list_comments_of_review(webhook_data, webhook_data.review.id)
+        }
+
+        // list_comments_of_review lists the comments of a review of the
+        // pull request
+        pub fn list_comments_of_review(
+            &self,
+            webhook_data: &webhook::webhook::WebhookRequest,
+            review_id: usize,
         ) -> Result<Vec<ReviewComment>, Error> {
             let authorization_header = self.get_authorization_header();
             let issue_path = format!(
-                "repos/{}/{}/pulls/{}/reviews/{}/comments",
+                "repos/{}/{}/pulls/{}/reviews/{}/comments?per_page=100",
                 webhook_data.repository.owner.login,
                 webhook_data.repository.name,
                 webhook_data.pull_request.number,
-                webhook_data.review.id
+                review_id
             );
             let url = format!("https://api.github.com/{}", issue_path);
             let client = reqwest::blocking::Client::new();
@@ -128,6 +334,7 @@ pub mod api {
             let res = match client
                 .get(url)
                 .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
                 .header(reqwest::header::USER_AGENT, "request")
                 .header(ACCEPT, "application/json")
                 .send()
@@ -135,6 +342,7 @@ pub mod api {
                 Ok(res) => res,
                 Err(err) => return Err(Error::new(501, err.to_string())),
             };
+            self.observe("list_review_comments", &res);
 
             if !res.status().is_success() {
                 return Err(Error::new(
@@ -149,5 +357,294 @@ pub mod api {
 
             Ok(review_comments_vec)
         }
+
+        // list_reviews lists the reviews submitted on the pull request
+        pub fn list_reviews(
+            &self,
+            webhook_data: &webhook::webhook::WebhookRequest,
+        ) -> Result<Vec<PullRequestReview>, Error> {
+            let authorization_header = self.get_authorization_header();
+            let url = format!(
+                "https://api.github.com/repos/{}/{}/pulls/{}/reviews?per_page=100",
+                webhook_data.repository.owner.login,
+                webhook_data.repository.name,
+                webhook_data.pull_request.number
+            );
+            let client = reqwest::blocking::Client::new();
+
+            let res = match client
+                .get(url)
+                .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
+                .header(reqwest::header::USER_AGENT, "request")
+                .header(ACCEPT, "application/json")
+                .send()
+            {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(501, err.to_string())),
+            };
+            self.observe("list_reviews", &res);
+
+            if !res.status().is_success() {
+                return Err(Error::new(501, String::from("failed to list reviews")));
+            }
+
+            match res.json::<Vec<PullRequestReview>>() {
+                Ok(reviews) => Ok(reviews),
+                Err(err) => Err(Error::new(501, err.to_string())),
+            }
+        }
+
+        pub fn get_branch(&self, owner: &str, repo: &str, branch: &str) -> Result<Branch, Error> {
+            let authorization_header = self.get_authorization_header();
+            let url = format!(
+                "https://api.github.com/repos/{}/{}/branches/{}",
+                owner, repo, branch
+            );
+            let client = reqwest::blocking::Client::new();
+
+            let res = match client
+                .get(url)
+                .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
+                .header(reqwest::header::USER_AGENT, "request")
+                .header(ACCEPT, "application/json")
+                .send()
+            {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(501, err.to_string())),
+            };
+            self.observe("get_branch", &res);
+
+            if !res.status().is_success() {
+                return Err(Error::new(501, String::from("failed to get branch")));
+            }
+
+            match res.json::<Branch>() {
+                Ok(branch) => Ok(branch),
+                Err(err) => Err(Error::new(501, err.to_string())),
+            }
+        }
+
+        // get_contents returns the raw content of a file at the given ref.
+        // A missing file is not an error and returns None
+        pub fn get_contents(
+            &self,
+            owner: &str,
+            repo: &str,
+            path: &str,
+            git_ref: &str,
+        ) -> Result<Option<String>, Error> {
+            let authorization_header = self.get_authorization_header();
+            let url = format!(
+                "https://api.github.com/repos/{}/{}/contents/{}?ref={}",
+                owner, repo, path, git_ref
+            );
+            let client = reqwest::blocking::Client::new();
+
+            let res = match client
+                .get(url)
+                .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
+                .header(reqwest::header::USER_AGENT, "request")
+                .header(ACCEPT, "application/vnd.github.v3.raw")
+                .send()
+            {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(501, err.to_string())),
+            };
+            self.observe("get_contents", &res);
+
+            if res.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            if !res.status().is_success() {
+                return Err(Error::new(501, String::from("failed to get contents")));
+            }
+
+            match res.text() {
+                Ok(content) => Ok(Some(content)),
+                Err(err) => Err(Error::new(501, err.to_string())),
+            }
+        }
+
+        // get_collaborator_permission returns the permission of the user on
+        // the repository. Users that are not collaborators have permission none
+        pub fn get_collaborator_permission(
+            &self,
+            owner: &str,
+            repo: &str,
+            username: &str,
+        ) -> Result<CollaboratorPermission, Error> {
+            let authorization_header = self.get_authorization_header();
+            let url = format!(
+                "https://api.github.com/repos/{}/{}/collaborators/{}/permission",
+                owner, repo, username
+            );
+            let client = reqwest::blocking::Client::new();
+
+            let res = match client
+                .get(url)
+                .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
+                .header(reqwest::header::USER_AGENT, "request")
+                .header(ACCEPT, "application/json")
+                .send()
+            {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(501, err.to_string())),
+            };
+            self.observe("get_collaborator_permission", &res);
+
+            if res.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(CollaboratorPermission {
+                    permission: String::from("none"),
+                    role_name: String::from("none"),
+                });
+            }
+            if !res.status().is_success() {
+                return Err(Error::new(
+                    501,
+                    String::from("failed to get collaborator permission"),
+                ));
+            }
+
+            match res.json::<CollaboratorPermission>() {
+                Ok(permission) => Ok(permission),
+                Err(err) => Err(Error::new(501, err.to_string())),
+            }
+        }
+
+        // get_account returns the public profile of the account
+        pub fn get_account(&self, login: &str) -> Result<Account, Error> {
+            let authorization_header = self.get_authorization_header();
+            let url = format!("https://api.github.com/users/{}", login);
+            let client = reqwest::blocking::Client::new();
+
+            let res = match client
+                .get(url)
+                .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
+                .header(reqwest::header::USER_AGENT, "request")
+                .header(ACCEPT, "application/json")
+                .send()
+            {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(501, err.to_string())),
+            };
+            self.observe("get_account", &res);
+
+            if !res.status().is_success() {
+                return Err(Error::new(
+                    res.status().as_u16() as usize,
+                    String::from("failed to get account"),
+                ));
+            }
+
+            match res.json::<Account>() {
+                Ok(account) => Ok(account),
+                Err(err) => Err(Error::new(501, err.to_string())),
+            }
+        }
+
+        // closing_issue_numbers returns the issues the pull request closes
+        // when merged, as linked on GitHub. Only the GraphQL API has them
+        pub fn closing_issue_numbers(
+            &self,
+            owner: &str,
+            repo: &str,
+            number: usize,
+        ) -> Result<Vec<usize>, Error> {
+            let authorization_header = self.get_authorization_header();
+            let query = serde_json::json!({
+                "query": "query($owner: String!, $repo: String!, $number: Int!) {
+                    repository(owner: $owner, name: $repo) {
+                        pullRequest(number: $number) {
+                            closingIssuesReferences(first: 50) { nodes { number } }
+                        }
+                    }
+                }",
+                "variables": { "owner": owner, "repo": repo, "number": number },
+            });
+            let client = reqwest::blocking::Client::new();
+
+            let res = match client
+                .post("https://api.github.com/graphql")
+                .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
+                .header(reqwest::header::USER_AGENT, "request")
+                .json(&query)
+                .send()
+            {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(501, err.to_string())),
+            };
+            self.observe("closing_issue_numbers", &res);
+
+            if !res.status().is_success() {
+                return Err(Error::new(
+                    501,
+                    String::from("failed to list closing issues"),
+                ));
+            }
+
+            let body = match res.json::<serde_json::Value>() {
+                Ok(body) => body,
+                Err(err) => return Err(Error::new(501, err.to_string())),
+            };
+            let nodes = body
+                .pointer("/data/repository/pullRequest/closingIssuesReferences/nodes")
+                .and_then(|nodes| nodes.as_array());
+            match nodes {
+                Some(nodes) => Ok(nodes
+                    .iter()
+                    .filter_map(|node| node.get("number").and_then(|number| number.as_u64()))
+                    .map(|number| number as usize)
+                    .collect()),
+                None => Err(Error::new(
+                    501,
+                    String::from("failed to list closing issues"),
+                )),
+            }
+        }
+
+        pub fn list_pull_request_files(
+            &self,
+            webhook_data: &webhook::webhook::WebhookRequest,
+        ) -> Result<Vec<PullRequestFile>, Error> {
+            let authorization_header = self.get_authorization_header();
+            let url = format!(
+                "https://api.github.com/repos/{}/{}/pulls/{}/files?per_page=100",
+                webhook_data.repository.owner.login,
+                webhook_data.repository.name,
+                webhook_data.pull_request.number
+            );
+            let client = reqwest::blocking::Client::new();
+
+            let res = match client
+                .get(url)
+                .header(reqwest::header::AUTHORIZATION, authorization_header)
+                .headers(self.trace_headers())
+                .header(reqwest::header::USER_AGENT, "request")
+                .header(ACCEPT, "application/json")
+                .send()
+            {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(501, err.to_string())),
+            };
+            self.observe("list_pull_request_files", &res);
+
+            if !res.status().is_success() {
+                return Err(Error::new(
+                    501,
+                    String::from("failed to list pull request files"),
+                ));
+            }
+
+            match res.json::<Vec<PullRequestFile>>() {
+                Ok(files) => Ok(files),
+                Err(err) => Err(Error::new(501, err.to_string())),
+            }
+        }
     }
 }
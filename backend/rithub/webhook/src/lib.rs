@@ -46,6 +46,17 @@ pub mod webhook {
         pub closed_at: String,
         #[serde(default = "empty_string")]
         pub merged_at: String,
+        #[serde(default)]
+        pub labels: Vec<Label>,
+        #[serde(default)]
+        pub body: Option<String>,
+        #[serde(default)]
+        pub merged_by: Option<User>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Label {
+        pub name: String,
     }
 
     fn empty_pull_request() -> PullRequest {
@@ -63,6 +74,9 @@ pub mod webhook {
             updated_at: String::from(""),
             closed_at: String::from(""),
             merged_at: String::from(""),
+            labels: vec![],
+            body: None,
+            merged_by: None,
         }
     }
 
@@ -93,9 +107,11 @@ pub mod webhook {
         id: usize,
         node_id: String,
         pub name: String,
-        full_name: String,
+        pub full_name: String,
         private: bool,
         pub owner: User,
+        #[serde(default = "empty_string")]
+        pub default_branch: String,
     }
 
     #[derive(Deserialize, Debug)]
@@ -104,7 +120,7 @@ pub mod webhook {
         node_id: String,
         pub user: User,
         body: String,
-        submitted_at: String,
+        pub submitted_at: String,
         pub state: String,
     }
 
@@ -119,6 +135,47 @@ pub mod webhook {
         }
     }
 
+    #[derive(Deserialize, Debug)]
+    pub struct Issue {
+        pub number: usize,
+        pub user: User,
+        #[serde(default)]
+        pub state: String,
+        // pull_request is set when the issue is a pull request
+        #[serde(default)]
+        pub pull_request: Option<IssuePullRequest>,
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct IssuePullRequest {
+        #[serde(default = "empty_string")]
+        url: String,
+    }
+
+    fn empty_issue() -> Issue {
+        Issue {
+            number: 0,
+            user: empty_user(),
+            state: String::from(""),
+            pull_request: None,
+        }
+    }
+
+    #[derive(Deserialize, Debug)]
+    pub struct Comment {
+        pub id: usize,
+        pub user: User,
+        pub body: String,
+    }
+
+    fn empty_comment() -> Comment {
+        Comment {
+            id: 0,
+            user: empty_user(),
+            body: String::from(""),
+        }
+    }
+
     #[derive(Deserialize, Debug)]
     pub struct WebhookRequest {
         pub action: String,
@@ -128,15 +185,21 @@ pub mod webhook {
         pub repository: Repository,
         #[serde(default = "empty_review")]
         pub review: Review,
+        #[serde(default = "empty_issue")]
+        pub issue: Issue,
+        #[serde(default = "empty_comment")]
+        pub comment: Comment,
     }
 
     #[derive(Debug)]
     pub enum WebhookType {
         Open,
         Review,
+        ReviewDismissed,
         Approved,
         Closed,
         Merged,
+        IssueComment,
         Unknown,
     }
 
@@ -147,8 +210,18 @@ pub mod webhook {
         ) -> WebhookType {
             match header.event.as_str() {
                 "pull_request" => match data.pull_request.state.as_str() {
-                    "open" => return WebhookType::Open,
+                    "open" => match data.action.as_str() {
+                        "opened" | "reopened" | "synchronize" | "edited" => {
+                            return WebhookType::Open
+                        }
+                        _ => return WebhookType::Unknown,
+                    },
                     "closed" => {
+                        // edits, labels and assignments of a closed pull
+                        // request keep its state but are not a new close
+                        if data.action != "closed" {
+                            return WebhookType::Unknown;
+                        }
                         if data.pull_request.merged_at != "" {
                             return WebhookType::Merged;
                         }
@@ -157,12 +230,19 @@ pub mod webhook {
                     _ => return WebhookType::Unknown,
                 },
                 "pull_request_review" => {
+                    if data.action == "dismissed" {
+                        return WebhookType::ReviewDismissed;
+                    }
                     match data.review.state.as_str() {
                         "approved" => return WebhookType::Approved,
-                        "changes_requested" => return WebhookType::Review,
+                        "changes_requested" | "commented" => return WebhookType::Review,
                         _ => return WebhookType::Unknown,
                     };
                 }
+                "issue_comment" => match data.action.as_str() {
+                    "created" => return WebhookType::IssueComment,
+                    _ => return WebhookType::Unknown,
+                },
                 _ => return WebhookType::Unknown,
             }
         }
@@ -0,0 +1,313 @@
+pub mod api_key {
+    use crate::handlers;
+    use crate::lib::crypto::crypto::random_token;
+    use crate::lib::web_error::WebError::WebError;
+    use crate::session::session::SessionUser;
+    use crate::user::user::User;
+    use openssl::sha::sha256;
+    use rocket::http::Status;
+    use rocket::request::{FromRequest, Outcome, Request};
+    use rocket_contrib::databases::postgres;
+    use serde::Serialize;
+    use std::io::{Error, ErrorKind};
+
+    pub const SCOPE_REWARDS_READ: &str = "rewards:read";
+    pub const SCOPE_REWARDS_WRITE: &str = "rewards:write";
+    const REPO_SCOPE_PREFIX: &str = "repo:";
+    const KEY_PREFIX: &str = "oct_";
+
+    // validate_scope accepts rewards:read, rewards:write and repo:<owner>/<name>
+    pub fn validate_scope(scope: &str) -> Result<(), Error> {
+        if scope == SCOPE_REWARDS_READ || scope == SCOPE_REWARDS_WRITE {
+            return Ok(());
+        }
+        if let Some(repo) = scope.strip_prefix(REPO_SCOPE_PREFIX) {
+            if repo.split('/').filter(|part| !part.is_empty()).count() == 2 {
+                return Ok(());
+            }
+        }
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown scope {}", scope),
+        ))
+    }
+
+    fn hash_key(key: &str) -> String {
+        sha256(key.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    // ApiKey is a scoped credential for programmatic access. Only the
+    // hash of the key is stored, the key itself is shown once on creation
+    #[derive(Debug, Serialize)]
+    pub struct ApiKey {
+        pub id: i32,
+        #[serde(skip)]
+        pub user_id: i32,
+        pub name: String,
+        pub prefix: String,
+        pub scopes: Vec<String>,
+    }
+
+    impl ApiKey {
+        pub fn create(
+            db: &postgres::Connection,
+            user_id: i32,
+            name: &str,
+            scopes: Vec<String>,
+        ) -> Result<(ApiKey, String), Error> {
+            for scope in &scopes {
+                validate_scope(scope)?;
+            }
+            let key = format!("{}{}", KEY_PREFIX, random_token(24)?);
+            let prefix: String = key.chars().take(12).collect();
+            let rows = match db.query(
+                "
+            INSERT INTO api_key (UserID, Name, Prefix, KeyHash, Scopes)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING ID
+            ",
+                &[&user_id, &name, &prefix, &hash_key(&key), &scopes],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+
+            let mut id = 0;
+            for row in &rows {
+                id = row.get("ID");
+            }
+            let api_key = ApiKey {
+                id,
+                user_id,
+                name: name.to_string(),
+                prefix,
+                scopes,
+            };
+            Ok((api_key, key))
+        }
+
+        pub fn list_for_user(
+            db: &postgres::Connection,
+            user_id: i32,
+        ) -> Result<Vec<ApiKey>, Error> {
+            let rows = match db.query(
+                "
+            SELECT ID, UserID, Name, Prefix, Scopes
+            FROM api_key
+            WHERE UserID=$1 AND RevokedAt IS NULL
+            ORDER BY ID
+            ",
+                &[&user_id],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+
+            Ok(rows
+                .iter()
+                .map(|row| ApiKey {
+                    id: row.get("ID"),
+                    user_id: row.get("UserID"),
+                    name: row.get("Name"),
+                    prefix: row.get("Prefix"),
+                    scopes: row.get("Scopes"),
+                })
+                .collect())
+        }
+
+        // revoke revokes a key of the user. Keys of other users are not found
+        pub fn revoke(db: &postgres::Connection, user_id: i32, id: i32) -> Result<(), Error> {
+            let query_res = db.execute(
+                "
+            UPDATE api_key
+            SET RevokedAt=now()
+            WHERE ID=$1 AND UserID=$2 AND RevokedAt IS NULL
+            ",
+                &[&id, &user_id],
+            );
+
+            match query_res {
+                Ok(0) => Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("api key {} does not exist", id),
+                )),
+                Ok(_) => Ok(()),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        // find_by_key looks up an active key and records that it was used
+        pub fn find_by_key(db: &postgres::Connection, key: &str) -> Result<ApiKey, Error> {
+            let rows = match db.query(
+                "
+            UPDATE api_key
+            SET LastUsedAt=now()
+            WHERE KeyHash=$1 AND RevokedAt IS NULL
+            RETURNING ID, UserID, Name, Prefix, Scopes
+            ",
+                &[&hash_key(key)],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+
+            for row in &rows {
+                return Ok(ApiKey {
+                    id: row.get("ID"),
+                    user_id: row.get("UserID"),
+                    name: row.get("Name"),
+                    prefix: row.get("Prefix"),
+                    scopes: row.get("Scopes"),
+                });
+            }
+            Err(Error::new(
+                ErrorKind::PermissionDenied,
+                String::from("invalid api key"),
+            ))
+        }
+
+        // allows checks the scope and, when the key is limited to some
+        // repositories, that the repository is one of them
+        pub fn allows(&self, scope: &str, repository: Option<&str>) -> bool {
+            if !self.scopes.iter().any(|s| s == scope) {
+                return false;
+            }
+            let repos: Vec<&str> = self
+                .scopes
+                .iter()
+                .filter_map(|s| s.strip_prefix(REPO_SCOPE_PREFIX))
+                .collect();
+            match repository {
+                Some(repository) if !repos.is_empty() => repos
+                    .iter()
+                    .any(|repo| repo.eq_ignore_ascii_case(repository)),
+                _ => true,
+            }
+        }
+    }
+
+    // Principal is whoever makes the request, either a logged in user or
+    // an API key sent as `Authorization: Bearer <key>`
+    pub enum Principal {
+        Session(SessionUser),
+        ApiKey(User, ApiKey),
+    }
+
+    impl Principal {
+        pub fn user(&self) -> &User {
+            match self {
+                Principal::Session(session_user) => &session_user.user,
+                Principal::ApiKey(user, _) => user,
+            }
+        }
+
+        // require enforces the scope of API keys. Logged in users act
+        // with all scopes
+        pub fn require(&self, scope: &str, repository: Option<&str>) -> Result<(), Error> {
+            match self {
+                Principal::Session(_) => Ok(()),
+                Principal::ApiKey(_, api_key) => {
+                    if api_key.allows(scope, repository) {
+                        return Ok(());
+                    }
+                    Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!("api key is missing scope {}", scope),
+                    ))
+                }
+            }
+        }
+    }
+
+    impl<'r, 'a> FromRequest<'r, 'a> for Principal {
+        type Error = WebError;
+        fn from_request(req: &'r Request<'a>) -> Outcome<Self, Self::Error> {
+            let bearer = req
+                .headers()
+                .get_one("Authorization")
+                .and_then(|header| header.strip_prefix("Bearer "))
+                .map(|key| key.trim().to_string());
+            let key = match bearer {
+                Some(key) => key,
+                None => {
+                    return match req.guard::<SessionUser>() {
+                        Outcome::Success(session_user) => {
+                            Outcome::Success(Principal::Session(session_user))
+                        }
+                        Outcome::Failure(failure) => Outcome::Failure(failure),
+                        Outcome::Forward(forward) => Outcome::Forward(forward),
+                    }
+                }
+            };
+
+            let db = match req.guard::<handlers::MyPgDatabase>() {
+                Outcome::Success(db) => db,
+                _ => {
+                    return Outcome::Failure((
+                        Status::ServiceUnavailable,
+                        WebError::new(503, "database unavailable".to_string()),
+                    ))
+                }
+            };
+            let api_key = match ApiKey::find_by_key(&db, &key) {
+                Ok(api_key) => api_key,
+                Err(_) => {
+                    return Outcome::Failure((
+                        Status::Unauthorized,
+                        WebError::new(401, "invalid api key".to_string()),
+                    ))
+                }
+            };
+            match User::get_by_id(&db, api_key.user_id) {
+                Ok(user) => Outcome::Success(Principal::ApiKey(user, api_key)),
+                Err(_) => Outcome::Failure((
+                    Status::Unauthorized,
+                    WebError::new(401, "invalid api key".to_string()),
+                )),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn api_key(scopes: &[&str]) -> ApiKey {
+            ApiKey {
+                id: 1,
+                user_id: 1,
+                name: String::from("ci"),
+                prefix: String::from("oct_abcd"),
+                scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            }
+        }
+
+        #[test]
+        fn allows_requires_the_scope() {
+            let key = api_key(&[SCOPE_REWARDS_READ]);
+            assert!(key.allows(SCOPE_REWARDS_READ, None));
+            assert!(key.allows(SCOPE_REWARDS_READ, Some("acme/widgets")));
+            assert!(!key.allows(SCOPE_REWARDS_WRITE, None));
+        }
+
+        #[test]
+        fn allows_limits_keys_to_their_repositories() {
+            let key = api_key(&[SCOPE_REWARDS_WRITE, "repo:acme/widgets"]);
+            assert!(key.allows(SCOPE_REWARDS_WRITE, Some("acme/widgets")));
+            assert!(key.allows(SCOPE_REWARDS_WRITE, Some("ACME/Widgets")));
+            assert!(!key.allows(SCOPE_REWARDS_WRITE, Some("acme/gadgets")));
+            assert!(key.allows(SCOPE_REWARDS_WRITE, None));
+        }
+
+        #[test]
+        fn validate_scope_accepts_known_scopes() {
+            assert!(validate_scope(SCOPE_REWARDS_READ).is_ok());
+            assert!(validate_scope("repo:acme/widgets").is_ok());
+            assert!(validate_scope("repo:acme").is_err());
+            assert!(validate_scope("rewards:admin").is_err());
+        }
+    }
+}
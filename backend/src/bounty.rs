@@ -0,0 +1,353 @@
+pub mod bounty {
+    use crate::pool::pool::{ENTRY_BOUNTY, ENTRY_BOUNTY_REFUND};
+    use rocket_contrib::databases::postgres;
+    use std::io::{Error, ErrorKind};
+
+    pub const BOUNTY_OPEN: &str = "open";
+    pub const BOUNTY_PAID: &str = "paid";
+    pub const BOUNTY_REFUNDED: &str = "refunded";
+    pub const BOUNTY_EXPIRED: &str = "expired";
+
+    // BOUNTY_COLUMNS are the columns read by from_row
+    const BOUNTY_COLUMNS: &str = "ID, Repository, Issue, Amount, Funder, Status, PullRequest,
+            to_char(ExpiresAt AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS ExpiresAt";
+
+    // CLOSING_KEYWORDS link a pull request to the issues it closes
+    const CLOSING_KEYWORDS: [&str; 9] = [
+        "close", "closes", "closed", "fix", "fixes", "fixed", "resolve", "resolves", "resolved",
+    ];
+
+    // Bounty is OCT escrowed from the pool of a repository for an issue.
+    // It goes to the pull request that closes the issue, or back to the
+    // pool when it is refunded or expires
+    pub struct Bounty {
+        pub id: i32,
+        pub repository: String,
+        pub issue: i32,
+        pub amount: i64,
+        // funder is the maintainer or donor that funded the bounty, empty
+        // for anonymous donors
+        pub funder: String,
+        pub status: String,
+        pub expires_at: Option<String>,
+        pub pull_request: Option<i32>,
+    }
+
+    impl Bounty {
+        fn from_row(row: &postgres::rows::Row) -> Bounty {
+            Bounty {
+                id: row.get("ID"),
+                repository: row.get("Repository"),
+                issue: row.get("Issue"),
+                amount: row.get("Amount"),
+                funder: row.get("Funder"),
+                status: row.get("Status"),
+                expires_at: row.get("ExpiresAt"),
+                pull_request: row.get("PullRequest"),
+            }
+        }
+
+        // fund escrows the amount from the pool of the repository for the
+        // issue. It fails when the pool does not hold enough
+        pub fn fund(
+            db: &postgres::Connection,
+            repository: &str,
+            issue: i32,
+            amount: i64,
+            funder: &str,
+            expires_at: Option<&str>,
+        ) -> Result<Bounty, Error> {
+            if amount <= 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    String::from("amount must be positive"),
+                ));
+            }
+            let result = db.transaction().and_then(|tx| {
+                let id = escrow(&tx, repository, issue, amount, funder, expires_at, true)?;
+                tx.commit()?;
+                Ok(id)
+            });
+            match result {
+                Ok(Some(id)) => Bounty::get(db, id),
+                Ok(None) => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("the pool of {} does not hold enough funds", repository),
+                )),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        pub fn get(db: &postgres::Connection, id: i32) -> Result<Bounty, Error> {
+            let rows = match db.query(
+                &format!("SELECT {} FROM bounty WHERE ID=$1", BOUNTY_COLUMNS),
+                &[&id],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                return Ok(Bounty::from_row(&row));
+            }
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!("bounty {} not found", id),
+            ))
+        }
+
+        // list lists the bounties of a repository, optionally of a single
+        // issue or status, newest first
+        pub fn list(
+            db: &postgres::Connection,
+            repository: &str,
+            issue: Option<i32>,
+            status: Option<String>,
+        ) -> Result<Vec<Bounty>, Error> {
+            let rows = match db.query(
+                &format!(
+                    "
+            SELECT {}
+            FROM bounty
+            WHERE Repository=$1
+                AND ($2::INTEGER IS NULL OR Issue=$2)
+                AND ($3::VARCHAR IS NULL OR Status=$3)
+            ORDER BY ID DESC
+            ",
+                    BOUNTY_COLUMNS
+                ),
+                &[&repository, &issue, &status],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            Ok(rows.iter().map(|row| Bounty::from_row(&row)).collect())
+        }
+
+        // refund returns an open bounty to the pool of its repository
+        pub fn refund(
+            db: &postgres::Connection,
+            repository: &str,
+            id: i32,
+        ) -> Result<Bounty, Error> {
+            let rows = match db.query(
+                "
+            WITH refunded AS (
+                UPDATE bounty SET Status=$3, ClosedAt=now()
+                WHERE ID=$1 AND Repository=$2 AND Status=$4
+                RETURNING ID, Repository, Amount
+            )
+            INSERT INTO pool_entry (Repository, Amount, Kind, Reference)
+            SELECT Repository, Amount, $5::VARCHAR, ID FROM refunded
+            RETURNING Reference
+            ",
+                &[
+                    &id,
+                    &repository,
+                    &BOUNTY_REFUNDED,
+                    &BOUNTY_OPEN,
+                    &ENTRY_BOUNTY_REFUND,
+                ],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            if rows.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("bounty {} is not open", id),
+                ));
+            }
+            Bounty::get(db, id)
+        }
+
+        // expire returns the open bounties past their expiry to the pool
+        // of their repository
+        pub fn expire(db: &postgres::Connection) -> Result<u64, Error> {
+            match db.execute(
+                "
+            WITH expired AS (
+                UPDATE bounty SET Status=$1, ClosedAt=now()
+                WHERE Status=$2 AND ExpiresAt <= now()
+                RETURNING ID, Repository, Amount
+            )
+            INSERT INTO pool_entry (Repository, Amount, Kind, Reference)
+            SELECT Repository, Amount, $3::VARCHAR, ID FROM expired
+            ",
+                &[&BOUNTY_EXPIRED, &BOUNTY_OPEN, &ENTRY_BOUNTY_REFUND],
+            ) {
+                Ok(count) => Ok(count),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        // claim marks the open bounties of the issues as paid to the pull
+        // request and returns them. A bounty is claimed only once
+        pub fn claim(
+            db: &dyn postgres::GenericConnection,
+            repository: &str,
+            issues: &[i32],
+            pull_request: i32,
+        ) -> Result<Vec<Bounty>, Error> {
+            let issues = issues.to_vec();
+            let rows = match db.query(
+                &format!(
+                    "
+            UPDATE bounty SET Status=$1, PullRequest=$2, ClosedAt=now()
+            WHERE Repository=$3 AND Issue = ANY($4) AND Status=$5
+                AND (ExpiresAt IS NULL OR ExpiresAt > now())
+            RETURNING {}
+            ",
+                    BOUNTY_COLUMNS
+                ),
+                &[
+                    &BOUNTY_PAID,
+                    &pull_request,
+                    &repository,
+                    &issues,
+                    &BOUNTY_OPEN,
+                ],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            Ok(rows.iter().map(|row| Bounty::from_row(&row)).collect())
+        }
+    }
+
+    // escrow creates an open bounty and takes its amount out of the pool
+    // of the repository. With check_balance the pool must hold the amount,
+    // otherwise no bounty is created and None is returned
+    pub fn escrow(
+        tx: &postgres::transaction::Transaction,
+        repository: &str,
+        issue: i32,
+        amount: i64,
+        funder: &str,
+        expires_at: Option<&str>,
+        check_balance: bool,
+    ) -> Result<Option<i32>, postgres::Error> {
+        // Serialize spending from the pool of the repository
+        tx.execute("SELECT pg_advisory_xact_lock(hashtext($1))", &[&repository])?;
+        let rows = tx.query(
+            "
+        INSERT INTO bounty (Repository, Issue, Amount, Funder, Status, ExpiresAt)
+        SELECT $1::VARCHAR, $2::INTEGER, $3::BIGINT, $4::VARCHAR, $5::VARCHAR,
+            CAST($6::TEXT AS TIMESTAMPTZ)
+        WHERE NOT $7::BOOLEAN OR (
+            SELECT COALESCE(SUM(Amount), 0) FROM pool_entry WHERE Repository=$1::VARCHAR
+        ) >= $3::BIGINT
+        RETURNING ID
+        ",
+            &[
+                &repository,
+                &issue,
+                &amount,
+                &funder,
+                &BOUNTY_OPEN,
+                &expires_at,
+                &check_balance,
+            ],
+        )?;
+        let id: i32 = match rows.iter().next() {
+            Some(row) => row.get("ID"),
+            None => return Ok(None),
+        };
+        tx.execute(
+            "
+        INSERT INTO pool_entry (Repository, Amount, Kind, Reference)
+        VALUES ($1, $2, $3, $4)
+        ",
+            &[&repository, &-amount, &ENTRY_BOUNTY, &id],
+        )?;
+        Ok(Some(id))
+    }
+
+    // closing_issues finds the issues of the repository a pull request
+    // body closes with keywords like "fixes #12" or "closes owner/repo#12"
+    pub fn closing_issues(body: &str, full_name: &str) -> Vec<i32> {
+        let words: Vec<&str> = body.split_whitespace().collect();
+        let mut issues: Vec<i32> = vec![];
+        for pair in words.windows(2) {
+            let keyword = pair[0].trim_end_matches(':').to_lowercase();
+            if !CLOSING_KEYWORDS.contains(&keyword.as_str()) {
+                continue;
+            }
+            if let Some(issue) = issue_reference(pair[1], full_name) {
+                if !issues.contains(&issue) {
+                    issues.push(issue);
+                }
+            }
+        }
+        issues
+    }
+
+    // issue_reference parses #12, owner/repo#12 and issue URLs that point
+    // into the repository
+    fn issue_reference(reference: &str, full_name: &str) -> Option<i32> {
+        let reference = reference.trim_end_matches(|c: char| c == '.' || c == ',' || c == ')');
+        let number = if let Some(number) = reference.strip_prefix('#') {
+            number
+        } else if let Some((repository, number)) = reference.split_once('#') {
+            if !repository.eq_ignore_ascii_case(full_name) {
+                return None;
+            }
+            number
+        } else {
+            let path = reference.strip_prefix("https://github.com/")?;
+            let (repository, number) = path.split_once("/issues/")?;
+            if !repository.eq_ignore_ascii_case(full_name) {
+                return None;
+            }
+            number
+        };
+        number.parse().ok().filter(|number| *number > 0)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn closing_issues_reads_keywords() {
+            let body = "Fixes #12, closes: acme/widgets#7 and resolves https://github.com/acme/widgets/issues/3.";
+            assert_eq!(closing_issues(body, "acme/widgets"), vec![12, 7, 3]);
+        }
+
+        #[test]
+        fn closing_issues_ignores_mentions_and_duplicates() {
+            let body = "See #4, fixes #5 and FIXES #5";
+            assert_eq!(closing_issues(body, "acme/widgets"), vec![5]);
+        }
+
+        #[test]
+        fn closing_issues_ignores_other_repositories() {
+            let body = "fixes other/repo#9 closes https://github.com/other/repo/issues/10";
+            assert!(closing_issues(body, "acme/widgets").is_empty());
+        }
+
+        #[test]
+        fn issue_reference_parses_references() {
+            assert_eq!(issue_reference("#12", "acme/widgets"), Some(12));
+            assert_eq!(
+                issue_reference("ACME/Widgets#12)", "acme/widgets"),
+                Some(12)
+            );
+            assert_eq!(
+                issue_reference("https://github.com/acme/widgets/issues/12", "acme/widgets"),
+                Some(12)
+            );
+        }
+
+        #[test]
+        fn issue_reference_rejects_invalid_numbers() {
+            assert_eq!(issue_reference("#0", "acme/widgets"), None);
+            assert_eq!(issue_reference("#-3", "acme/widgets"), None);
+            assert_eq!(issue_reference("#abc", "acme/widgets"), None);
+            assert_eq!(issue_reference("12", "acme/widgets"), None);
+            assert_eq!(
+                issue_reference("https://github.com/acme/widgets/pull/12", "acme/widgets"),
+                None
+            );
+        }
+    }
+}
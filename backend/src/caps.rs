@@ -0,0 +1,191 @@
+pub mod caps {
+    use crate::ledger::ledger::Payout;
+    use rocket_contrib::databases::postgres;
+    use std::collections::HashMap;
+    use std::io::{Error, ErrorKind};
+
+    // Caps are the payout caps of a repository in base units
+    pub struct Caps {
+        pub per_reviewer: Option<i64>,
+        pub per_user_per_day: Option<i64>,
+        pub per_repository_per_week: Option<i64>,
+    }
+
+    // Limit is the cap a reward exceeds and how much of the reward fits
+    // under it
+    pub struct Limit {
+        pub cap: &'static str,
+        pub allowed: i64,
+    }
+
+    impl Caps {
+        // check checks a reward against the caps given what the user was
+        // paid today and the repository paid this week. The per reviewer
+        // cap only applies to review rewards. None when the reward fits
+        // under every cap
+        fn check(
+            &self,
+            amount: i64,
+            reviewer: bool,
+            paid_today: i64,
+            paid_this_week: i64,
+        ) -> Option<Limit> {
+            let mut limit: Option<Limit> = None;
+            let mut restrict = |cap: &'static str, allowed: i64| {
+                let allowed = allowed.max(0);
+                if allowed < amount && limit.as_ref().map_or(true, |limit| allowed < limit.allowed)
+                {
+                    limit = Some(Limit { cap, allowed });
+                }
+            };
+            if let (true, Some(max)) = (reviewer, self.per_reviewer) {
+                restrict("per reviewer", max);
+            }
+            if let Some(max) = self.per_user_per_day {
+                restrict("per user per day", max - paid_today);
+            }
+            if let Some(max) = self.per_repository_per_week {
+                restrict("per repository per week", max - paid_this_week);
+            }
+            limit
+        }
+    }
+
+    // Granted sums the rewards of a merge that passed the caps but are not
+    // in the ledger yet
+    #[derive(Default)]
+    pub struct Granted {
+        total: i64,
+        per_user: HashMap<String, i64>,
+    }
+
+    impl Granted {
+        pub fn add(&mut self, username: &str, amount: i64) {
+            self.total += amount;
+            *self.per_user.entry(username.to_string()).or_insert(0) += amount;
+        }
+
+        fn to_user(&self, username: &str) -> i64 {
+            self.per_user.get(username).copied().unwrap_or(0)
+        }
+    }
+
+    // limit checks a reward of a user against the caps, the payouts
+    // already in the ledger and the rewards granted earlier in the merge
+    pub fn limit(
+        db: &postgres::Connection,
+        caps: &Caps,
+        granted: &Granted,
+        repository: &str,
+        username: &str,
+        amount: i64,
+        reviewer: bool,
+    ) -> Result<Option<Limit>, Error> {
+        let paid_today = match caps.per_user_per_day {
+            Some(_) => {
+                Payout::sum_since(db, repository, Some(username), 1)? + granted.to_user(username)
+            }
+            None => 0,
+        };
+        let paid_this_week = match caps.per_repository_per_week {
+            Some(_) => Payout::sum_since(db, repository, None, 7)? + granted.total,
+            None => 0,
+        };
+        Ok(caps.check(amount, reviewer, paid_today, paid_this_week))
+    }
+
+    // CapsLock keeps concurrent merges of a repository from checking the
+    // caps against the same ledger sums. It is released when dropped
+    pub struct CapsLock<'a> {
+        db: &'a postgres::Connection,
+        key: String,
+    }
+
+    // lock takes the caps lock of the repository. It is held from checking
+    // the rewards of a merge until they are in the ledger
+    pub fn lock<'a>(db: &'a postgres::Connection, repository: &str) -> Result<CapsLock<'a>, Error> {
+        let key = format!("payout_caps:{}", repository);
+        match db.execute("SELECT pg_advisory_lock(hashtext($1))", &[&key]) {
+            Ok(_) => Ok(CapsLock { db, key }),
+            Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        }
+    }
+
+    impl<'a> Drop for CapsLock<'a> {
+        fn drop(&mut self) {
+            if let Err(err) = self
+                .db
+                .execute("SELECT pg_advisory_unlock(hashtext($1))", &[&self.key])
+            {
+                log::error!("caps.unlock.fail. Key: {} Cause: {}", self.key, err);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn caps() -> Caps {
+            Caps {
+                per_reviewer: Some(100),
+                per_user_per_day: Some(300),
+                per_repository_per_week: Some(1000),
+            }
+        }
+
+        #[test]
+        fn check_passes_rewards_under_every_cap() {
+            assert!(caps().check(50, true, 0, 0).is_none());
+            assert!(caps().check(100, true, 200, 900).is_none());
+        }
+
+        #[test]
+        fn check_picks_the_smallest_cap() {
+            let limit = caps().check(500, true, 250, 0).unwrap();
+            assert_eq!(limit.cap, "per user per day");
+            assert_eq!(limit.allowed, 50);
+
+            let limit = caps().check(500, true, 0, 980).unwrap();
+            assert_eq!(limit.cap, "per repository per week");
+            assert_eq!(limit.allowed, 20);
+        }
+
+        #[test]
+        fn check_clamps_allowed_at_zero() {
+            let limit = caps().check(10, false, 400, 0).unwrap();
+            assert_eq!(limit.cap, "per user per day");
+            assert_eq!(limit.allowed, 0);
+        }
+
+        #[test]
+        fn check_applies_the_reviewer_cap_to_reviewers_only() {
+            let limit = caps().check(150, true, 0, 0).unwrap();
+            assert_eq!(limit.cap, "per reviewer");
+            assert_eq!(limit.allowed, 100);
+            assert!(caps().check(150, false, 0, 0).is_none());
+        }
+
+        #[test]
+        fn granted_sums_per_user_and_in_total() {
+            let mut granted = Granted::default();
+            granted.add("alice", 100);
+            granted.add("bob", 50);
+            granted.add("alice", 25);
+            assert_eq!(granted.to_user("alice"), 125);
+            assert_eq!(granted.to_user("bob"), 50);
+            assert_eq!(granted.to_user("carol"), 0);
+            assert_eq!(granted.total, 175);
+        }
+
+        #[test]
+        fn check_without_caps_passes_everything() {
+            let caps = Caps {
+                per_reviewer: None,
+                per_user_per_day: None,
+                per_repository_per_week: None,
+            };
+            assert!(caps.check(i64::MAX, true, 0, 0).is_none());
+        }
+    }
+}
@@ -0,0 +1,114 @@
+pub mod command {
+    // COMMAND_PREFIX starts a command in an issue or pull request comment
+    const COMMAND_PREFIX: &str = "/oct";
+
+    // Command is a command given to the bot in a comment
+    #[derive(Debug, PartialEq)]
+    pub enum Command {
+        // Bounty escrows the amount of OCT for the issue, optionally for
+        // a number of days
+        Bounty {
+            amount: f64,
+            expires_in_days: Option<i32>,
+        },
+        // Approve approves the payout proposal of a merged pull request
+        Approve,
+    }
+
+    // parse reads the first command of a comment. Comments without a
+    // command return None, malformed commands return why they failed
+    pub fn parse(body: &str) -> Option<Result<Command, String>> {
+        let line = body
+            .lines()
+            .map(str::trim)
+            .find(|line| line.split_whitespace().next() == Some(COMMAND_PREFIX))?;
+        let args: Vec<&str> = line.split_whitespace().skip(1).collect();
+        Some(match args.as_slice() {
+            ["bounty", amount] => parse_bounty(amount, None),
+            ["bounty", amount, expiry] => parse_bounty(amount, Some(expiry)),
+            ["bounty", ..] => Err(String::from("usage: /oct bounty <amount> [<days>d]")),
+            ["approve"] => Ok(Command::Approve),
+            ["approve", ..] => Err(String::from("usage: /oct approve")),
+            [name, ..] => Err(format!("unknown command {}", name)),
+            [] => Err(String::from("missing command")),
+        })
+    }
+
+    fn parse_bounty(amount: &str, expiry: Option<&str>) -> Result<Command, String> {
+        let amount: f64 = match amount.parse() {
+            Ok(amount) if amount > 0.0 && f64::is_finite(amount) => amount,
+            _ => return Err(format!("{} is not a positive amount", amount)),
+        };
+        let expires_in_days = match expiry {
+            Some(expiry) => match expiry.strip_suffix('d').and_then(|days| days.parse().ok()) {
+                Some(days) if days > 0 => Some(days),
+                _ => return Err(format!("{} is not a number of days like 30d", expiry)),
+            },
+            None => None,
+        };
+        Ok(Command::Bounty {
+            amount,
+            expires_in_days,
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parse_ignores_comments_without_commands() {
+            assert_eq!(parse("looks good to me"), None);
+            assert_eq!(parse("run /oct bounty 5 to fund it"), None);
+        }
+
+        #[test]
+        fn parse_reads_bounties() {
+            assert_eq!(
+                parse("Thanks!\n  /oct bounty 12.5  \n"),
+                Some(Ok(Command::Bounty {
+                    amount: 12.5,
+                    expires_in_days: None,
+                }))
+            );
+            assert_eq!(
+                parse("/oct bounty 3 30d"),
+                Some(Ok(Command::Bounty {
+                    amount: 3.0,
+                    expires_in_days: Some(30),
+                }))
+            );
+        }
+
+        #[test]
+        fn parse_rejects_malformed_bounties() {
+            for body in &[
+                "/oct bounty",
+                "/oct bounty 0",
+                "/oct bounty -4",
+                "/oct bounty NaN",
+                "/oct bounty inf",
+                "/oct bounty 5 30",
+                "/oct bounty 5 0d",
+                "/oct bounty 5 30d extra",
+            ] {
+                assert!(matches!(parse(body), Some(Err(_))), "{}", body);
+            }
+        }
+
+        #[test]
+        fn parse_reads_approve() {
+            assert_eq!(parse("/oct approve"), Some(Ok(Command::Approve)));
+            assert!(matches!(parse("/oct approve now"), Some(Err(_))));
+        }
+
+        #[test]
+        fn parse_rejects_unknown_commands() {
+            assert_eq!(
+                parse("/oct tip 5"),
+                Some(Err(String::from("unknown command tip")))
+            );
+            assert_eq!(parse("/oct"), Some(Err(String::from("missing command"))));
+        }
+    }
+}
@@ -0,0 +1,192 @@
+pub mod estimate {
+    use rocket_contrib::databases::postgres;
+    use std::io::{Error, ErrorKind};
+
+    pub const ROLE_AUTHOR: &str = "author";
+    pub const ROLE_REVIEWER: &str = "reviewer";
+
+    // ScoreEntry is the score and estimated reward of a contributor to a
+    // pull request after an event changed it. Reward is in base units
+    pub struct ScoreEntry {
+        pub event: String,
+        pub username: String,
+        pub role: String,
+        pub score: f64,
+        pub reward: i64,
+        pub created_at: String,
+    }
+
+    impl ScoreEntry {
+        pub fn new(event: &str, username: &str, role: &str, score: f64, reward: i64) -> ScoreEntry {
+            ScoreEntry {
+                event: event.to_string(),
+                username: username.to_string(),
+                role: role.to_string(),
+                score,
+                reward,
+                created_at: String::from(""),
+            }
+        }
+
+        fn from_row(row: &postgres::rows::Row) -> ScoreEntry {
+            ScoreEntry {
+                event: row.get("Event"),
+                username: row.get("Username"),
+                role: row.get("Role"),
+                score: row.get("Score"),
+                reward: row.get("Reward"),
+                created_at: row.get("CreatedAt"),
+            }
+        }
+    }
+
+    // history lists how the estimate of a pull request changed, oldest first
+    pub fn history(
+        db: &postgres::Connection,
+        repository: &str,
+        pull_request: i32,
+    ) -> Result<Vec<ScoreEntry>, Error> {
+        let rows = match db.query(
+            "
+        SELECT Event, Username, Role, Score, Reward,
+            to_char(CreatedAt AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS CreatedAt
+        FROM pull_request_score
+        WHERE Repository=$1 AND PullRequest=$2
+        ORDER BY ID
+        ",
+            &[&repository, &pull_request],
+        ) {
+            Ok(rows) => rows,
+            Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        };
+        Ok(rows.iter().map(|row| ScoreEntry::from_row(&row)).collect())
+    }
+
+    // latest returns the last entry of every contributor to the pull request
+    fn latest(
+        db: &postgres::Connection,
+        repository: &str,
+        pull_request: i32,
+    ) -> Result<Vec<ScoreEntry>, Error> {
+        let rows = match db.query(
+            "
+        SELECT DISTINCT ON (Username, Role) Event, Username, Role, Score, Reward,
+            to_char(CreatedAt AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS CreatedAt
+        FROM pull_request_score
+        WHERE Repository=$1 AND PullRequest=$2
+        ORDER BY Username, Role, ID DESC
+        ",
+            &[&repository, &pull_request],
+        ) {
+            Ok(rows) => rows,
+            Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        };
+        Ok(rows.iter().map(|row| ScoreEntry::from_row(&row)).collect())
+    }
+
+    // record stores the entries whose score changed since the last event.
+    // Contributors missing from the entries, for example reviewers whose
+    // review was dismissed, are recorded with a score of zero
+    pub fn record(
+        db: &postgres::Connection,
+        repository: &str,
+        pull_request: i32,
+        event: &str,
+        entries: &[ScoreEntry],
+    ) -> Result<(), Error> {
+        let previous = latest(db, repository, pull_request)?;
+        let mut changed: Vec<ScoreEntry> = vec![];
+        for entry in entries {
+            let unchanged = previous.iter().any(|last| {
+                last.username == entry.username
+                    && last.role == entry.role
+                    && last.score == entry.score
+                    && last.reward == entry.reward
+            });
+            if !unchanged {
+                changed.push(ScoreEntry::new(
+                    event,
+                    &entry.username,
+                    &entry.role,
+                    entry.score,
+                    entry.reward,
+                ));
+            }
+        }
+        for last in &previous {
+            let dropped = !entries
+                .iter()
+                .any(|entry| entry.username == last.username && entry.role == last.role);
+            if dropped && (last.score != 0.0 || last.reward != 0) {
+                changed.push(ScoreEntry::new(event, &last.username, &last.role, 0.0, 0));
+            }
+        }
+
+        for entry in &changed {
+            if let Err(err) = db.execute(
+                "
+            INSERT INTO pull_request_score (Repository, PullRequest, Event, Username, Role, Score, Reward)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+                &[
+                    &repository,
+                    &pull_request,
+                    &entry.event,
+                    &entry.username,
+                    &entry.role,
+                    &entry.score,
+                    &entry.reward,
+                ],
+            ) {
+                return Err(Error::new(ErrorKind::Other, format!("{}", err)));
+            }
+        }
+        Ok(())
+    }
+
+    // comment_id returns the comment the estimate of the pull request is
+    // posted in, if it was posted
+    pub fn comment_id(
+        db: &postgres::Connection,
+        repository: &str,
+        pull_request: i32,
+    ) -> Result<Option<usize>, Error> {
+        let rows = match db.query(
+            "
+        SELECT CommentID
+        FROM pull_request_comment
+        WHERE Repository=$1 AND PullRequest=$2
+        ",
+            &[&repository, &pull_request],
+        ) {
+            Ok(rows) => rows,
+            Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        };
+        for row in &rows {
+            let comment_id: i64 = row.get("CommentID");
+            return Ok(Some(comment_id as usize));
+        }
+        Ok(None)
+    }
+
+    pub fn set_comment_id(
+        db: &postgres::Connection,
+        repository: &str,
+        pull_request: i32,
+        comment_id: usize,
+    ) -> Result<(), Error> {
+        let query_res = db.execute(
+            "
+        INSERT INTO pull_request_comment (Repository, PullRequest, CommentID)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (Repository, PullRequest)
+        DO UPDATE SET CommentID=EXCLUDED.CommentID
+        ",
+            &[&repository, &pull_request, &(comment_id as i64)],
+        );
+        match query_res {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+pub mod fraud {
+    use crate::ledger::ledger::Payout;
+    use crate::policy::policy::Policy;
+    use rithub::api::api::Account;
+    use rocket_contrib::databases::postgres;
+    use std::io::Error;
+
+    // ACCOUNT_TYPE_BOT is the type GitHub gives to apps and bots
+    const ACCOUNT_TYPE_BOT: &str = "Bot";
+
+    // is_excluded_account checks if the policy never rewards the account
+    // because of its type
+    pub fn is_excluded_account(policy: &Policy, account: &Account) -> bool {
+        (policy.exclude_bots && account.account_type == ACCOUNT_TYPE_BOT)
+            || (policy.exclude_site_admins && account.site_admin)
+    }
+
+    // hold_reason tells why the reward of a user for a pull request of the
+    // author must wait for a maintainer. None when it can be paid. The age
+    // is not checked when the account could not be looked up
+    pub fn hold_reason(
+        db: &postgres::Connection,
+        policy: &Policy,
+        username: &str,
+        account: Option<&Account>,
+        author: &str,
+    ) -> Result<Option<String>, Error> {
+        if let (true, Some(account)) = (policy.min_account_age_days > 0, account) {
+            match chrono::DateTime::parse_from_rfc3339(&account.created_at) {
+                Ok(created_at) => {
+                    let age = chrono::Utc::now().signed_duration_since(created_at);
+                    if age < chrono::Duration::days(policy.min_account_age_days as i64) {
+                        return Ok(Some(format!(
+                            "account is younger than {} days",
+                            policy.min_account_age_days
+                        )));
+                    }
+                }
+                Err(err) => log::error!(
+                    "fraud.account_age.fail. User: {} Cause: {}",
+                    account.login,
+                    err
+                ),
+            }
+        }
+        if policy.hold_inactive_accounts && Payout::count_for_user(db, username)? == 0 {
+            return Ok(Some(String::from("account has no prior activity")));
+        }
+        if policy.review_ring_threshold > 0 && !username.eq_ignore_ascii_case(author) {
+            let reciprocal = Payout::reciprocal_reviews(db, username, author)?;
+            if reciprocal >= policy.review_ring_threshold as i64 {
+                return Ok(Some(format!(
+                    "reviews traded with @{} {} times",
+                    author, reciprocal
+                )));
+            }
+        }
+        Ok(None)
+    }
+}
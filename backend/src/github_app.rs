@@ -0,0 +1,259 @@
+pub mod github_app {
+    use crate::handlers::Config;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::sign::Signer;
+    use rithub::app::app;
+    use rocket::request::{FromRequest, Outcome, Request};
+    use std::env;
+    use std::fs;
+
+    // SIGNATURE_PREFIX is the prefix of the X-Hub-Signature-256 header
+    const SIGNATURE_PREFIX: &str = "sha256=";
+
+    // GithubApp is a GitHub App the backend receives webhooks from and
+    // acts as on the repositories it is installed on
+    pub struct GithubApp {
+        pub id: String,
+        pub client: app::Config,
+        webhook_secret: String,
+    }
+
+    impl GithubApp {
+        // verify_signature checks the HMAC-SHA256 of the webhook body
+        // against the X-Hub-Signature-256 header
+        pub fn verify_signature(&self, body: &[u8], signature: &str) -> bool {
+            let expected = match hex_decode(signature.trim_start_matches(SIGNATURE_PREFIX)) {
+                Some(expected) => expected,
+                None => return false,
+            };
+            let key = match PKey::hmac(self.webhook_secret.as_bytes()) {
+                Ok(key) => key,
+                Err(_) => return false,
+            };
+            let digest = Signer::new(MessageDigest::sha256(), &key)
+                .and_then(|mut signer| signer.update(body).map(|_| signer))
+                .and_then(|signer| signer.sign_to_vec());
+            match digest {
+                Ok(digest) => {
+                    digest.len() == expected.len() && openssl::memcmp::eq(&digest, &expected)
+                }
+                Err(_) => false,
+            }
+        }
+    }
+
+    fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+        // from_str_radix also takes a sign, which is not hex
+        if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+
+    // load_private_key reads the private key inline or from a file and
+    // makes sure it can sign the app JWT
+    fn load_private_key(
+        name: &str,
+        inline: Option<String>,
+        path: Option<String>,
+    ) -> Result<Vec<u8>, String> {
+        let pem = match (inline, path) {
+            (Some(pem), _) => pem.replace("\\n", "\n").into_bytes(),
+            (None, Some(path)) => match fs::read(&path) {
+                Ok(pem) => pem,
+                Err(err) => {
+                    return Err(format!(
+                        "could not read the private key of GitHub App {} at {}: {}",
+                        name, path, err
+                    ))
+                }
+            },
+            (None, None) => {
+                return Err(format!("no private key configured for GitHub App {}", name))
+            }
+        };
+        match jsonwebtoken::EncodingKey::from_rsa_pem(&pem) {
+            Ok(_) => Ok(pem),
+            Err(err) => Err(format!(
+                "the private key of GitHub App {} is not a valid RSA PEM key: {}",
+                name, err
+            )),
+        }
+    }
+
+    // WebhookSignature is the X-Hub-Signature-256 header of a webhook
+    // delivery, if it was sent
+    pub struct WebhookSignature(pub Option<String>);
+
+    impl<'r, 'a> FromRequest<'r, 'a> for WebhookSignature {
+        type Error = ();
+        fn from_request(req: &'r Request<'a>) -> Outcome<Self, Self::Error> {
+            let signature = req
+                .headers()
+                .get_one("X-Hub-Signature-256")
+                .map(|signature| signature.to_string());
+            Outcome::Success(WebhookSignature(signature))
+        }
+    }
+
+    // GithubApps are the GitHub Apps served by one deployment, for
+    // example a staging and a production app
+    pub struct GithubApps {
+        apps: Vec<GithubApp>,
+    }
+
+    fn profile_var(profile: &str, name: &str) -> Option<String> {
+        env::var(format!("GITHUB_APP_{}_{}", profile.to_uppercase(), name)).ok()
+    }
+
+    impl GithubApps {
+        // load reads the app configured with GITHUB_APP_ID and the apps of
+        // the profiles listed in GITHUB_APP_PROFILES. A profile NAME is
+        // configured with GITHUB_APP_NAME_ID, GITHUB_APP_NAME_PRIVATE_KEY
+        // or GITHUB_APP_NAME_PRIVATE_KEY_PATH and GITHUB_APP_NAME_WEBHOOK_SECRET
+        pub fn load(cfg: &Config) -> Result<GithubApps, String> {
+            let mut apps = vec![GithubApp {
+                id: cfg.app_id.clone(),
+                client: app::Config::new(
+                    cfg.app_id.clone(),
+                    load_private_key(
+                        &cfg.app_id,
+                        cfg.app_private_key.clone(),
+                        cfg.cert_pem_path.clone(),
+                    )?,
+                ),
+                webhook_secret: cfg.webhook_secret.clone(),
+            }];
+
+            let profiles = cfg.app_profiles.clone().unwrap_or_default();
+            for profile in profiles.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                let id = match profile_var(profile, "ID") {
+                    Some(id) => id,
+                    None => {
+                        return Err(format!(
+                            "GITHUB_APP_{}_ID is missing",
+                            profile.to_uppercase()
+                        ))
+                    }
+                };
+                let webhook_secret = match profile_var(profile, "WEBHOOK_SECRET") {
+                    Some(secret) => secret,
+                    None => {
+                        return Err(format!(
+                            "GITHUB_APP_{}_WEBHOOK_SECRET is missing",
+                            profile.to_uppercase()
+                        ))
+                    }
+                };
+                let private_key = load_private_key(
+                    profile,
+                    profile_var(profile, "PRIVATE_KEY"),
+                    profile_var(profile, "PRIVATE_KEY_PATH"),
+                )?;
+                if apps.iter().any(|app| app.id == id) {
+                    return Err(format!("GitHub App {} is configured twice", id));
+                }
+                apps.push(GithubApp {
+                    client: app::Config::new(id.clone(), private_key),
+                    id,
+                    webhook_secret,
+                });
+            }
+            Ok(GithubApps { apps })
+        }
+
+        // default is the app configured with GITHUB_APP_ID
+        pub fn default(&self) -> &GithubApp {
+            &self.apps[0]
+        }
+
+        // get returns the app with the id. Repositories installed before
+        // the app id was recorded have none and use the default app. Ids
+        // of apps that are not configured return None
+        pub fn get(&self, id: &str) -> Option<&GithubApp> {
+            if id.is_empty() {
+                return Some(self.default());
+            }
+            self.apps.iter().find(|app| app.id == id)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = &GithubApp> {
+            self.apps.iter()
+        }
+
+        // verify_webhook finds the app whose webhook secret signed the body
+        pub fn verify_webhook(&self, body: &[u8], signature: &str) -> Option<&GithubApp> {
+            self.apps
+                .iter()
+                .find(|app| app.verify_signature(body, signature))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // SIGNATURE is the signature GitHub documents for the body
+        // "Hello, World!" and the secret "It's a Secret to Everybody"
+        const SIGNATURE: &str =
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+        fn github_app(webhook_secret: &str) -> GithubApp {
+            app_with_id("1", webhook_secret)
+        }
+
+        fn app_with_id(id: &str, webhook_secret: &str) -> GithubApp {
+            GithubApp {
+                id: id.to_string(),
+                client: app::Config::new(id.to_string(), vec![]),
+                webhook_secret: webhook_secret.to_string(),
+            }
+        }
+
+        #[test]
+        fn get_finds_configured_apps_only() {
+            let apps = GithubApps {
+                apps: vec![app_with_id("1", "first"), app_with_id("2", "second")],
+            };
+            assert_eq!(apps.get("2").map(|app| app.id.as_str()), Some("2"));
+            assert_eq!(apps.get("").map(|app| app.id.as_str()), Some("1"));
+            assert!(apps.get("3").is_none());
+        }
+
+        #[test]
+        fn verify_signature_accepts_the_signed_body() {
+            let app = github_app("It's a Secret to Everybody");
+            assert!(app.verify_signature(b"Hello, World!", SIGNATURE));
+        }
+
+        #[test]
+        fn verify_signature_rejects_other_bodies_and_secrets() {
+            assert!(!github_app("It's a Secret to Everybody")
+                .verify_signature(b"Hello, World?", SIGNATURE));
+            assert!(!github_app("another secret").verify_signature(b"Hello, World!", SIGNATURE));
+            assert!(!github_app("It's a Secret to Everybody")
+                .verify_signature(b"Hello, World!", &SIGNATURE[..SIGNATURE.len() - 2]));
+            assert!(
+                !github_app("It's a Secret to Everybody").verify_signature(b"Hello, World!", "")
+            );
+        }
+
+        #[test]
+        fn hex_decode_reads_pairs() {
+            assert_eq!(hex_decode("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+            assert_eq!(hex_decode(""), Some(vec![]));
+        }
+
+        #[test]
+        fn hex_decode_rejects_malformed_hex() {
+            assert_eq!(hex_decode("abc"), None);
+            assert_eq!(hex_decode("zz"), None);
+            assert_eq!(hex_decode("+1"), None);
+            assert_eq!(hex_decode("é"), None);
+        }
+    }
+}
@@ -1,8 +1,27 @@
+use crate::bounty::bounty::{self, Bounty};
+use crate::caps::caps::{self, Caps, Granted};
+use crate::command::command::{self, Command};
+use crate::estimate::estimate::{self, ScoreEntry, ROLE_AUTHOR, ROLE_REVIEWER};
+use crate::fraud::fraud;
+use crate::github_app::github_app::GithubApps;
+use crate::ledger::ledger::{
+    Payout, STATUS_FAILED, STATUS_HELD, STATUS_PENDING, STATUS_SENT, STATUS_UNCLAIMED,
+};
+use crate::lib::throttle::throttle::Throttle;
+use crate::lib::trace::trace;
+use crate::metrics::metrics::Metrics;
+use crate::permission::permission::{self, RoleCache};
+use crate::policy::policy::{Policy, PolicyCache, CAP_OVERFLOW_HOLD, POLICY_PATH};
+use crate::pool::pool::{self, DonationIntent, DONATION_OPEN};
+use crate::proposal::proposal::{Proposal, PROPOSAL_APPROVED, PROPOSAL_OPEN, PROPOSAL_REJECTED};
+use crate::repository::repository::{FundingMode, Repository};
+use crate::reward::reward::PullRequestReward;
 use crate::sdk;
+use crate::stats::stats;
 use crate::user::user::User;
 use envconfig::Envconfig;
 use log::info;
-use rithub::api::api::{self, ReviewComment};
+use rithub::api::api::{self, Account, PullRequestFile, ReviewComment};
 use rithub::app::app;
 use rithub::error::errors::Error;
 use rithub::webhook::webhook::WebhookRequest;
@@ -23,8 +42,12 @@ use solana_sdk::{
     transaction::Transaction,
 };
 use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 #[database("my_db")]
 pub struct MyPgDatabase(postgres::Connection);
 
@@ -34,93 +57,608 @@ pub struct Config {
     pub oauth_client_id: String,
     #[envconfig(from = "GITHUB_OAUTH_SECRET")]
     pub oauth_client_secret: String,
-    #[envconfig(from = "GITHUB_APP_CLIENT_ID")]
-    pub app_client_id: String,
-    #[envconfig(from = "GITHUB_APP_SECRET")]
-    pub app_client_secret: String,
+    #[envconfig(from = "GITHUB_OAUTH_REDIRECT_URL")]
+    pub oauth_redirect_url: Option<String>,
+    #[envconfig(from = "GITHUB_APP_ID")]
+    pub app_id: String,
+    // The private key of the GitHub App is given inline or as a file path
+    #[envconfig(from = "GITHUB_APP_PRIVATE_KEY")]
+    pub app_private_key: Option<String>,
+    #[envconfig(from = "CERT_PEM_PATH")]
+    pub cert_pem_path: Option<String>,
+    #[envconfig(from = "GITHUB_WEBHOOK_SECRET")]
+    pub webhook_secret: String,
+    // app_profiles lists further GitHub Apps served by this deployment
+    #[envconfig(from = "GITHUB_APP_PROFILES")]
+    pub app_profiles: Option<String>,
     #[envconfig(from = "DATABASE_URL")]
     pub database_url: String,
     #[envconfig(from = "SHARED_KEY")]
     pub shared_key: String,
-    #[envconfig(from = "SECRET_TOKEN")]
-    pub secret_token: String,
-    #[envconfig(from = "CERT_PEM_PATH")]
-    pub cert_pem_path: String,
+    #[envconfig(from = "TOKEN_ENCRYPTION_KEY")]
+    pub token_encryption_key: String,
+    #[envconfig(from = "SESSION_TTL_SECONDS", default = "86400")]
+    pub session_ttl_seconds: u64,
+    #[envconfig(from = "ROLE_CACHE_TTL_SECONDS", default = "300")]
+    pub role_cache_ttl_seconds: u64,
+    #[envconfig(from = "SOLANA_RPC_URL", default = "http://localhost:8899")]
+    pub solana_rpc_url: String,
+    #[envconfig(from = "SOLANA_WS_URL", default = "ws://localhost:8900")]
+    pub solana_ws_url: String,
+    #[envconfig(from = "SOLANA_COMMITMENT", default = "confirmed")]
+    pub solana_commitment: String,
+    #[envconfig(from = "OCT_MINT_ADDRESS")]
+    pub oct_mint_address: String,
+    #[envconfig(
+        from = "OCT_TOKEN_PROGRAM_ID",
+        default = "DHZypXyN9vRh24S8UgP37DeQ9dpCndDVCTQMMDvuNX8g"
+    )]
+    pub oct_token_program_id: String,
+    #[envconfig(from = "TREASURY_KEYPAIR_PATH")]
+    pub treasury_keypair_path: String,
+    #[envconfig(from = "OCT_DECIMALS", default = "0")]
+    pub oct_decimals: u8,
+    // treasury_min_balance is the OCT balance below which the treasury
+    // is reported as low by the readiness check
+    #[envconfig(from = "TREASURY_MIN_BALANCE", default = "0")]
+    pub treasury_min_balance: f64,
+    // max_pull_request_reward is the largest max reward in OCT a
+    // maintainer may set for a single pull request
+    #[envconfig(from = "MAX_PULL_REQUEST_REWARD", default = "1000")]
+    pub max_pull_request_reward: f64,
 }
 
 pub struct Api {
     pub config: Config,
-    pub github_app_client: app::Config,
+    pub github_apps: GithubApps,
+    pub sdk: sdk::Sdk,
+    pub policy_cache: PolicyCache,
+    pub role_cache: RoleCache,
+    pub metrics: Metrics,
+    // gauge_refresh limits how often scrapes read the payout queue depth
+    // and the treasury balance
+    pub gauge_refresh: Throttle,
+    // stats_changed tells the background refresh that payouts changed the
+    // statistics
+    pub stats_changed: Arc<AtomicBool>,
 }
 
-fn scaled_sigmoid(scale_val: f64, val: f64) -> f64 {
-    1.0 / (1.0 + (val / scale_val).exp())
+// github_client is a GitHub client that passes on the id of the request
+// being handled and records its calls in the metrics
+pub fn github_client(api: &Api, access_token: &str) -> api::Config {
+    api::Config::new(access_token)
+        .with_request_id(trace::request_id())
+        .with_observer(Some(api.metrics.github_observer()))
 }
 
-fn calculate_pull_request_score(webhook_data: &WebhookRequest) -> String {
-    let pr_score = 1.2 * webhook_data.pull_request.additions as f64
-        + 0.8 * webhook_data.pull_request.deletions as f64
-        + 1.1 * webhook_data.pull_request.changed_files as f64;
-    let pr_score_normalized = scaled_sigmoid(100.0, pr_score);
-    let pr_score_comment = format!(
-                ":unicorn: **Total Reward** : {:} OCT (open contribution tokens). [Access your OCTs](http://localhost:5000/)",
-                pr_score_normalized
-            );
-    pr_score_comment
+// saturating_score maps a raw score onto [0, 1). It is 0 for nothing,
+// grows with the raw score and approaches 1 as it passes the scale
+fn saturating_score(scale_val: f64, val: f64) -> f64 {
+    1.0 - (-val / scale_val).exp()
+}
+
+// repository_policy loads the reward policy from the default branch of
+// the repository. Parsed policies are cached per commit. An invalid policy
+// is reported on the pull request once and the defaults apply until fixed
+pub fn repository_policy(
+    api: &Api,
+    github_client: &api::Config,
+    webhook_data: &WebhookRequest,
+) -> Policy {
+    let repository = &webhook_data.repository;
+    let owner = repository.owner.login.as_str();
+    let sha = match github_client.get_branch(owner, &repository.name, &repository.default_branch) {
+        Ok(branch) => branch.commit.sha,
+        Err(err) => {
+            log::error!("github_webhook.policy.branch_fail. Cause: {:?}", err);
+            return Policy::default();
+        }
+    };
+    if let Some(policy) = api.policy_cache.get(&repository.full_name, &sha) {
+        return policy.unwrap_or_default();
+    }
+
+    let policy = match github_client.get_contents(owner, &repository.name, POLICY_PATH, &sha) {
+        Ok(Some(content)) => Policy::parse(&content, api.config.max_pull_request_reward),
+        Ok(None) => Ok(Policy::default()),
+        Err(err) => {
+            log::error!("github_webhook.policy.contents_fail. Cause: {:?}", err);
+            return Policy::default();
+        }
+    };
+    if let Err(err) = &policy {
+        log::info!("github_webhook.policy.invalid. Cause: {}", err);
+        let invalid_policy_comment = format!(
+            ":warning: **Invalid reward policy** : `{}` at {} could not be used: {}. The default policy applies until it is fixed.",
+            POLICY_PATH, sha, err
+        );
+        if let Err(err) = github_client.comment_issue(&webhook_data, &invalid_policy_comment) {
+            log::error!("error: {:?}", err);
+        }
+    }
+    api.policy_cache
+        .insert(&repository.full_name, &sha, policy.clone());
+    policy.unwrap_or_default()
+}
+
+// pull_request_policy is the repository policy with the max reward
+// overridden for the pull request when one was set over the API
+pub fn pull_request_policy(
+    api: &Api,
+    db: &postgres::Connection,
+    github_client: &api::Config,
+    webhook_data: &WebhookRequest,
+) -> Policy {
+    let mut policy = repository_policy(api, github_client, webhook_data);
+    match PullRequestReward::get(
+        db,
+        &webhook_data.repository.full_name,
+        webhook_data.pull_request.number as i32,
+    ) {
+        Ok(Some(reward)) => policy.max_reward = reward.max_reward,
+        Ok(None) => (),
+        Err(err) => log::error!("github_webhook.policy.reward_fail. Cause: {}", err),
+    }
+    policy
+}
+
+fn pull_request_labels(webhook_data: &WebhookRequest) -> Vec<String> {
+    webhook_data
+        .pull_request
+        .labels
+        .iter()
+        .map(|label| label.name.clone())
+        .collect()
+}
+
+fn calculate_pull_request_score(files: &[PullRequestFile], policy: &Policy) -> f64 {
+    let weights = &policy.weights;
+    let files: Vec<&PullRequestFile> = files
+        .iter()
+        .filter(|file| !policy.is_excluded_path(&file.filename))
+        .collect();
+    let additions: usize = files.iter().map(|file| file.additions).sum();
+    let deletions: usize = files.iter().map(|file| file.deletions).sum();
+    let pr_score = weights.additions * additions as f64
+        + weights.deletions * deletions as f64
+        + weights.changed_files * files.len() as f64;
+    saturating_score(weights.pull_request_scale, pr_score)
 }
 
-// pull_request calculates the value of the pull request and posts the
-// estimated value to the pull request as a comment
-pub fn pull_request(webhook_data: &WebhookRequest, api: &Api) -> Result<(), Error> {
+fn pull_request_score_comment(pr_score: f64, policy: &Policy) -> String {
+    format!(
+        ":unicorn: **Total Reward** : {:} OCT (open contribution tokens). [Access your OCTs](http://localhost:5000/)",
+        pr_score * policy.max_reward
+    )
+}
+
+// pull_request calculates the value of the pull request when it is
+// opened or changed and updates the estimate posted on it
+pub fn pull_request(
+    webhook_data: &WebhookRequest,
+    api: &Api,
+    github_app: &app::Config,
+    db: &postgres::Connection,
+) -> Result<(), Error> {
     info!("github_webhook.type.pull_request");
-    let access_token = match api
-        .github_app_client
-        .authenticate_app(webhook_data.installation.id.to_string())
-    {
+    let access_token = match github_app.authenticate_app(webhook_data.installation.id.to_string()) {
         Ok(token) => token,
         Err(err) => {
             log::error!("github_webhook.type.pull_fail. Cause: {:}", err);
             return Err(Error::new(500, err.to_string()));
         }
     };
+    let github_client = github_client(api, &access_token.token);
+    update_estimate(api, db, &github_client, webhook_data, &webhook_data.action)
+}
 
-    // Calculate Pull request score
-    let pr_score_comment = calculate_pull_request_score(&webhook_data);
-    let github_client = api::Config::new(&access_token.token);
+// active_review_comments lists the comments of the reviews of the pull
+// request that were not dismissed. The body of a review is scored like
+// its comments so an approval with a substantive body counts like any
+// review
+fn active_review_comments(
+    github_client: &api::Config,
+    webhook_data: &WebhookRequest,
+) -> Result<Vec<ReviewComment>, Error> {
+    let mut comments: Vec<ReviewComment> = vec![];
+    for review in github_client
+        .list_reviews(webhook_data)?
+        .into_iter()
+        .filter(|review| !review.state.eq_ignore_ascii_case("dismissed"))
+    {
+        comments.extend(github_client.list_comments_of_review(webhook_data, review.id)?);
+        if let Some(body) = review.body.filter(|body| !body.trim().is_empty()) {
+            comments.push(ReviewComment {
+                body,
+                position: 0,
+                original_position: 0,
+                user: api::User {
+                    login: review.user.login,
+                    name: String::from(""),
+                },
+            });
+        }
+    }
+    Ok(comments)
+}
+
+// reviewers lists the users rewarded for reviewing the pull request of
+// the author. Only users with a comment of at least min_review_length
+// count, so a bare approval makes no one a reviewer
+fn reviewers<'a>(comments: &'a [ReviewComment], author: &str, policy: &Policy) -> Vec<&'a str> {
+    let mut users: Vec<&str> = comments
+        .iter()
+        .filter(|comment| comment.body.chars().count() >= policy.min_review_length)
+        .map(|comment| comment.user.login.as_str())
+        .filter(|login| *login != author)
+        .filter(|login| !policy.is_excluded_user(login))
+        .collect();
+    users.sort();
+    users.dedup();
+    users
+}
+
+// payee_accounts looks up the accounts of the reviewers and the author
+// of the pull request and drops the reviewers the policy excludes by
+// account type. Accounts that could not be looked up are kept
+fn payee_accounts<'a>(
+    github_client: &api::Config,
+    webhook_data: &WebhookRequest,
+    policy: &Policy,
+    users: Vec<&'a str>,
+) -> (Vec<&'a str>, HashMap<String, Account>) {
+    let mut accounts: HashMap<String, Account> = HashMap::new();
+    let author = webhook_data.pull_request.user.login.as_str();
+    for login in users.iter().copied().chain(std::iter::once(author)) {
+        match github_client.get_account(login) {
+            Ok(account) => {
+                accounts.insert(login.to_string(), account);
+            }
+            Err(err) => log::error!(
+                "fraud.get_account.fail. User: {} Cause: {}",
+                login,
+                err.message
+            ),
+        }
+    }
+    let users = users
+        .into_iter()
+        .filter(|login| !is_excluded_account(&accounts, login, policy))
+        .collect();
+    (users, accounts)
+}
 
-    match github_client.comment_issue(&webhook_data, &pr_score_comment) {
+fn is_excluded_account(accounts: &HashMap<String, Account>, login: &str, policy: &Policy) -> bool {
+    match accounts.get(login) {
+        Some(account) => fraud::is_excluded_account(policy, account),
+        None => false,
+    }
+}
+
+// update_estimate scores the pull request and its reviews, records the
+// scores that changed with the event in the score history and updates
+// the estimate comment of the pull request
+fn update_estimate(
+    api: &Api,
+    db: &postgres::Connection,
+    github_client: &api::Config,
+    webhook_data: &WebhookRequest,
+    event: &str,
+) -> Result<(), Error> {
+    let policy = pull_request_policy(api, db, github_client, webhook_data);
+    if !policy.is_eligible(&pull_request_labels(webhook_data)) {
+        info!("github_webhook.type.pull_request.not_eligible");
+        return Ok(());
+    }
+
+    // Calculate Pull request score
+    let files = match github_client.list_pull_request_files(&webhook_data) {
         Ok(res) => res,
         Err(err) => {
             log::error!("error: {:?}", err);
             return Err(Error::new(500, err.message));
         }
     };
+    let pr_score = calculate_pull_request_score(&files, &policy);
+    // Calculate review scores
+    let review_comments = active_review_comments(github_client, webhook_data)?;
+    let (users, accounts) = payee_accounts(
+        github_client,
+        webhook_data,
+        &policy,
+        reviewers(
+            &review_comments,
+            &webhook_data.pull_request.user.login,
+            &policy,
+        ),
+    );
+
+    let author = &webhook_data.pull_request.user.login;
+    let mut entries: Vec<ScoreEntry> = vec![];
+    if policy.author_share > 0.0
+        && !policy.is_excluded_user(author)
+        && !is_excluded_account(&accounts, author, &policy)
+    {
+        entries.push(ScoreEntry::new(
+            event,
+            author,
+            ROLE_AUTHOR,
+            pr_score,
+            api.sdk
+                .to_base_units(capped_author_reward(pr_score, &policy)) as i64,
+        ));
+    }
+
+    for username in &users {
+        let review_score = review_score_for_user(&review_comments, username, &policy);
+        let reward = review_reward(review_score, users.len(), &policy);
+        entries.push(ScoreEntry::new(
+            event,
+            username,
+            ROLE_REVIEWER,
+            review_score,
+            api.sdk.to_base_units(reward) as i64,
+        ));
+    }
+
+    let full_name = &webhook_data.repository.full_name;
+    let number = webhook_data.pull_request.number as i32;
+    if let Err(err) = estimate::record(db, full_name, number, event, &entries) {
+        log::error!("estimate.record.fail. Cause: {}", err);
+    }
+
+    let comment = estimate_comment(api, pr_score, &policy, &entries);
+    let owner = &webhook_data.repository.owner.login;
+    let name = &webhook_data.repository.name;
+    if let Ok(Some(comment_id)) = estimate::comment_id(db, full_name, number) {
+        match github_client.update_comment(owner, name, comment_id, &comment) {
+            Ok(_) => return Ok(()),
+            // The estimate comment was deleted, post a new one
+            Err(err) if err.status() == 404 => (),
+            Err(err) => return Err(err),
+        }
+    }
+    let comment_id =
+        github_client.create_comment(owner, name, webhook_data.pull_request.number, &comment)?;
+    if let Err(err) = estimate::set_comment_id(db, full_name, number, comment_id) {
+        log::error!("estimate.comment.fail. Cause: {}", err);
+    }
     Ok(())
 }
 
-fn review_score_for_user(comments: &Vec<ReviewComment>, user: &str) -> String {
-    let num_comments = comments.len();
-    let comment_lengths: usize = comments
+// estimate_comment is the estimate of the pull request with the reward
+// of the author and every reviewer
+fn estimate_comment(api: &Api, pr_score: f64, policy: &Policy, entries: &[ScoreEntry]) -> String {
+    let lines: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "- @{} ({}): {} OCT",
+                entry.username,
+                entry.role,
+                api.sdk.to_ui_amount(entry.reward as u64)
+            )
+        })
+        .collect();
+    format!(
+        "{}\n\n{}",
+        pull_request_score_comment(pr_score, policy),
+        lines.join("\n")
+    )
+}
+
+// review_score_for_user scores the review comments of a user. Comments
+// shorter than the policy's minimum review length are not counted
+fn review_score_for_user(comments: &Vec<ReviewComment>, user: &str, policy: &Policy) -> f64 {
+    let weights = &policy.weights;
+    let user_comments: Vec<&ReviewComment> = comments
         .into_iter()
         .filter(|x| x.user.login == user)
-        .map(|c| c.body.chars().count())
-        .sum();
-    let abs_score = (num_comments + comment_lengths / 30) as f64;
-    let scaled_score = scaled_sigmoid(100.0, abs_score);
-    let review_score_comment =  format!(
-        ":unicorn: **Pull Request Value** : The minimal value of your PR is {:} OCT (open contribution tokens). If approved your OCTs will be accessible in your wallet. [Access your OCTs](http://localhost:5000/)",
-        scaled_score
-    );
-    review_score_comment
+        .filter(|x| x.body.chars().count() >= policy.min_review_length)
+        .collect();
+    let num_comments = user_comments.len();
+    let comment_lengths: usize = user_comments.iter().map(|c| c.body.chars().count()).sum();
+    let abs_score = weights.review_comment * num_comments as f64
+        + (comment_lengths / weights.review_chars_per_point) as f64;
+    saturating_score(weights.review_scale, abs_score)
+}
+
+// review_reward is the OCT a reviewer receives for a review score when
+// the reviewer share is divided between the reviewers
+fn review_reward(review_score: f64, num_reviewers: usize, policy: &Policy) -> f64 {
+    if num_reviewers == 0 {
+        return 0.0;
+    }
+    policy.max_reward * policy.reviewer_share * review_score / num_reviewers as f64
+}
+
+// author_reward is the score of the merged pull request and the OCT its
+// author receives for it, capped by author_reward_cap. It returns None
+// when the author is not rewarded, for example for maintainers merging
+// their own pull requests when the policy excludes self merges
+fn author_reward(
+    api: &Api,
+    github_client: &api::Config,
+    webhook_data: &WebhookRequest,
+    repository: &Repository,
+    policy: &Policy,
+    accounts: &HashMap<String, Account>,
+) -> Result<Option<(f64, f64)>, Error> {
+    let author = &webhook_data.pull_request.user.login;
+    if policy.author_share == 0.0
+        || policy.is_excluded_user(author)
+        || is_excluded_account(accounts, author, policy)
+    {
+        return Ok(None);
+    }
+    let self_merged = match &webhook_data.pull_request.merged_by {
+        Some(merged_by) => merged_by.login == *author,
+        None => false,
+    };
+    if policy.exclude_self_merges
+        && self_merged
+        && permission::repository_role(api, repository, author)?.is_maintainer()
+    {
+        return Ok(None);
+    }
+    let files = github_client.list_pull_request_files(webhook_data)?;
+    let score = calculate_pull_request_score(&files, policy);
+    Ok(Some((score, capped_author_reward(score, policy))))
+}
+
+// capped_author_reward is the OCT the author of a pull request with the
+// score receives, capped by author_reward_cap
+fn capped_author_reward(score: f64, policy: &Policy) -> f64 {
+    let reward = policy.max_reward * policy.author_share * score;
+    match policy.author_reward_cap {
+        Some(cap) => reward.min(cap),
+        None => reward,
+    }
+}
+
+// payout_hold_reason runs the fraud checks on the reward of a user. The
+// reward is held when the checks cannot run
+fn payout_hold_reason(
+    db: &postgres::Connection,
+    policy: &Policy,
+    accounts: &HashMap<String, Account>,
+    username: &str,
+    author: &str,
+) -> Option<String> {
+    match fraud::hold_reason(db, policy, username, accounts.get(username), author) {
+        Ok(reason) => reason,
+        Err(err) => {
+            log::error!("fraud.check.fail. User: {} Cause: {}", username, err);
+            Some(String::from("fraud checks failed"))
+        }
+    }
+}
+
+// PayoutDecision is the part of a reward that is recorded, why it is
+// held and what the merge summary tells about it
+struct PayoutDecision {
+    amount: u64,
+    hold_reason: Option<String>,
+    note: Option<String>,
+}
+
+// PayoutChecks are the fraud checks and payout caps rewards of a merged
+// pull request go through before they are recorded
+struct PayoutChecks<'a> {
+    api: &'a Api,
+    db: &'a postgres::Connection,
+    webhook_data: &'a WebhookRequest,
+    policy: &'a Policy,
+    accounts: &'a HashMap<String, Account>,
+    caps: Caps,
+    // granted are the rewards of the merge that passed the caps so far
+    granted: Granted,
+}
+
+impl<'a> PayoutChecks<'a> {
+    fn new(
+        api: &'a Api,
+        db: &'a postgres::Connection,
+        webhook_data: &'a WebhookRequest,
+        policy: &'a Policy,
+        accounts: &'a HashMap<String, Account>,
+    ) -> PayoutChecks<'a> {
+        let to_base_units = |max: f64| api.sdk.to_base_units(max) as i64;
+        PayoutChecks {
+            api,
+            db,
+            webhook_data,
+            policy,
+            accounts,
+            caps: Caps {
+                per_reviewer: policy.max_reward_per_reviewer.map(to_base_units),
+                per_user_per_day: policy.max_reward_per_user_per_day.map(to_base_units),
+                per_repository_per_week: policy
+                    .max_reward_per_repository_per_week
+                    .map(to_base_units),
+            },
+            granted: Granted::default(),
+        }
+    }
+
+    // decide runs the fraud checks and the payout caps on a reward in base
+    // units. Rewards over a cap are truncated or held as the policy says
+    fn decide(&mut self, username: &str, amount: u64, reviewer: bool) -> PayoutDecision {
+        let author = &self.webhook_data.pull_request.user.login;
+        let mut decision = PayoutDecision {
+            amount,
+            hold_reason: payout_hold_reason(self.db, self.policy, self.accounts, username, author),
+            note: None,
+        };
+        let full_name = &self.webhook_data.repository.full_name;
+        match caps::limit(
+            self.db,
+            &self.caps,
+            &self.granted,
+            full_name,
+            username,
+            amount as i64,
+            reviewer,
+        ) {
+            Ok(Some(limit)) if self.policy.cap_overflow == CAP_OVERFLOW_HOLD => {
+                if decision.hold_reason.is_none() {
+                    decision.hold_reason = Some(format!("over the {} payout cap", limit.cap));
+                }
+            }
+            Ok(Some(limit)) => {
+                decision.amount = limit.allowed as u64;
+                decision.note = Some(format!(
+                    "truncated to {} OCT by the {} payout cap",
+                    self.api.sdk.to_ui_amount(decision.amount),
+                    limit.cap
+                ));
+            }
+            Ok(None) => (),
+            Err(err) => {
+                log::error!("payout.caps.fail. User: {} Cause: {}", username, err);
+                if decision.hold_reason.is_none() {
+                    decision.hold_reason = Some(String::from("payout caps could not be checked"));
+                }
+            }
+        }
+        match &decision.hold_reason {
+            Some(reason) => {
+                decision.note = Some(format!("held for maintainer approval: {}", reason))
+            }
+            None => self.granted.add(username, decision.amount as i64),
+        }
+        decision
+    }
+}
+
+// summary_line is the reward of a user in the merge summary
+fn summary_line(username: &str, role: &str, reward: f64, note: &Option<String>) -> String {
+    match note {
+        Some(note) => format!("@{} ({}): {} OCT, {}", username, role, reward, note),
+        None => format!("@{} ({}): {} OCT", username, role, reward),
+    }
 }
+
+// merge_summary_comment lists the rewards of a merged pull request
+fn merge_summary_comment(rewards: &[String]) -> String {
+    let lines: Vec<String> = rewards.iter().map(|line| format!("- {}", line)).collect();
+    format!(
+        ":unicorn: **Rewards** for this pull request in OCT (open contribution tokens):\n{}\n\nRewards of users without a linked wallet are kept until they link one and claim them. [Access your OCTs](http://localhost:5000/)",
+        lines.join("\n")
+    )
+}
+
 // pull_request_review is called when a user submits a pull request review
-// is either submitted, edited or dismissed
-pub fn pull_request_review(webhook_data: &WebhookRequest, api: &Api) -> Result<(), Error> {
+// is either submitted, edited or dismissed. A dismissed review no longer
+// counts for its reviewer
+pub fn pull_request_review(
+    webhook_data: &WebhookRequest,
+    api: &Api,
+    github_app: &app::Config,
+    db: &postgres::Connection,
+) -> Result<(), Error> {
     info!("github_webhook.type.pull_request_review");
-    let access_token = match api
-        .github_app_client
-        .authenticate_app(webhook_data.installation.id.to_string())
-    {
+    let access_token = match github_app.authenticate_app(webhook_data.installation.id.to_string()) {
         Ok(token) => token,
         Err(err) => {
             log::error!(
@@ -130,57 +668,402 @@ pub fn pull_request_review(webhook_data: &WebhookRequest, api: &Api) -> Result<(
             return Err(Error::new(500, err.to_string()));
         }
     };
+    let github_client = github_client(api, &access_token.token);
+    update_estimate(
+        api,
+        db,
+        &github_client,
+        webhook_data,
+        &format!("review_{}", webhook_data.action),
+    )
+}
 
-    // Get review comments
-    let github_client = api::Config::new(&access_token.token);
-    let review_comments = match github_client.list_review_comments(&webhook_data) {
-        Ok(res) => res,
+// approved_pull_request recalculates the pull request value of
+// all requests and comments made by the user
+// This is posted on the pull request and on merge commit the
+// OCT is transferred to the users wallet
+pub fn approved_pull_request(
+    webhook_data: &WebhookRequest,
+    api: &Api,
+    github_app: &app::Config,
+    db: &postgres::Connection,
+) -> Result<(), Error> {
+    info!("github_webhook.type.approved_pull_request");
+    let access_token = match github_app.authenticate_app(webhook_data.installation.id.to_string()) {
+        Ok(token) => token,
         Err(err) => {
             log::error!(
-                "github_webhook.type.pull_request_review.fail. Cause: {:?}",
+                "github_webhook.type.approved_pull_request.fail. Cause: {:}",
                 err
             );
-            return Err(Error::new(500, err.message));
+            return Err(Error::new(500, err.to_string()));
         }
     };
-    info!("review_comments {:?}", review_comments);
+    let github_client = github_client(api, &access_token.token);
+    update_estimate(api, db, &github_client, webhook_data, "review_approved")
+}
 
-    // Calculate review score
-    let username = webhook_data.review.user.login.as_str();
-    let review_score_comment = review_score_for_user(&review_comments, username);
-    let review_score_comment_atuser = format!("@{}: {}", username, review_score_comment);
+// effective_funding_mode is the funding mode reviewer rewards of the
+// repository are paid with. The mode of the policy file on merge, or the
+// one recorded on a payout since, takes precedence over the stored one
+fn effective_funding_mode(repository: &Repository, mode: Option<&str>) -> FundingMode {
+    match mode.map(FundingMode::from_str) {
+        Some(Ok(mode)) => mode,
+        _ => repository.funding_mode.clone(),
+    }
+}
 
-    // comment review score
-    match github_client.comment_issue(&webhook_data, &review_score_comment_atuser) {
-        Ok(res) => res,
+// funding_source resolves where the rewards of the repository are paid
+// from according to the funding mode
+pub fn funding_source(
+    db: &postgres::Connection,
+    repository: &Repository,
+    funding_mode: &FundingMode,
+    author: &str,
+) -> Result<sdk::FundingSource, Error> {
+    match funding_mode {
+        FundingMode::Treasury => Ok(sdk::FundingSource::Treasury),
+        FundingMode::Author => {
+            let author_user = User::new(author, author);
+            let author_addr = match author_user.get_address_from_username(db) {
+                Ok(Some(addr)) => addr,
+                Ok(None) => {
+                    return Err(Error::new(
+                        500,
+                        format!("{} has not linked a wallet", author),
+                    ))
+                }
+                Err(err) => {
+                    log::error!("error: {:?}", err);
+                    return Err(Error::new(500, err.to_string()));
+                }
+            };
+            Ok(sdk::FundingSource::Delegate(sdk::parse_pubkey(
+                &author_addr,
+            )?))
+        }
+        FundingMode::Sponsor => match &repository.sponsor_account {
+            Some(account) => Ok(sdk::FundingSource::Account(sdk::parse_pubkey(account)?)),
+            None => Err(Error::new(
+                500,
+                format!("{} has no sponsor account", repository.full_name),
+            )),
+        },
+    }
+}
+
+// linked_wallet is the wallet the user linked, empty when there is none.
+// Rewards of users whose wallet cannot be read are kept as unclaimed
+fn linked_wallet(db: &postgres::Connection, username: &str) -> String {
+    match User::new(username, username).get_address_from_username(db) {
+        Ok(addr) => addr.unwrap_or_default(),
         Err(err) => {
-            log::error!("error: {:?}", err);
-            return Err(Error::new(500, err.message));
+            log::error!("user.wallet.fail. User: {} Cause: {}", username, err);
+            String::from("")
+        }
+    }
+}
+
+// new_payout builds the ledger entry of a reward for the merged pull
+// request. Users without a linked wallet keep the reward as unclaimed and
+// rewards with a hold reason wait for a maintainer
+fn new_payout(
+    db: &postgres::Connection,
+    webhook_data: &WebhookRequest,
+    username: &str,
+    amount: u64,
+    score: f64,
+    bounty_id: Option<i32>,
+    hold_reason: Option<String>,
+) -> Payout {
+    let addr = linked_wallet(db, username);
+
+    let mut payout = Payout::new(
+        &webhook_data.repository.full_name,
+        webhook_data.pull_request.number,
+        &webhook_data.pull_request.user.login,
+        username,
+        &addr,
+        amount,
+    );
+    payout.score = score;
+    payout.bounty_id = bounty_id;
+    if hold_reason.is_some() {
+        payout.status = String::from(STATUS_HELD);
+        payout.hold_reason = hold_reason;
+    } else if addr.is_empty() {
+        payout.status = String::from(STATUS_UNCLAIMED);
+    }
+    payout
+}
+
+// insert_payout stores the payout in the ledger, it is returned only when
+// it can be sent
+fn insert_payout(
+    db: &dyn postgres::GenericConnection,
+    mut payout: Payout,
+) -> Result<Option<Payout>, Error> {
+    if let Err(err) = payout.insert(db) {
+        log::error!("error: {:?}", err);
+        return Err(Error::new(500, err.to_string()));
+    }
+    if payout.status == STATUS_HELD {
+        log::info!(
+            "payout.held. Repository: {} User: {} Reason: {}",
+            payout.repository,
+            payout.username,
+            payout.hold_reason.as_deref().unwrap_or("")
+        );
+        return Ok(None);
+    }
+    if payout.status == STATUS_UNCLAIMED {
+        log::info!(
+            "payout.unclaimed. Repository: {} User: {}",
+            payout.repository,
+            payout.username
+        );
+        return Ok(None);
+    }
+    Ok(Some(payout))
+}
+
+// pay_bounties pays the open bounties of the issues the merged pull
+// request closes to its author. The reviewers split the part of each
+// bounty set by bounty_reviewer_share in the policy
+fn pay_bounties(
+    api: &Api,
+    db: &postgres::Connection,
+    github_client: &api::Config,
+    webhook_data: &WebhookRequest,
+    reviewers: &[&str],
+    policy: &Policy,
+    accounts: &HashMap<String, Account>,
+) -> Result<(), Error> {
+    let full_name = &webhook_data.repository.full_name;
+    let number = webhook_data.pull_request.number;
+    let body = webhook_data.pull_request.body.as_deref().unwrap_or("");
+    let mut issues = bounty::closing_issues(body, full_name);
+    match github_client.closing_issue_numbers(
+        &webhook_data.repository.owner.login,
+        &webhook_data.repository.name,
+        number,
+    ) {
+        Ok(linked) => {
+            for issue in linked {
+                if !issues.contains(&(issue as i32)) {
+                    issues.push(issue as i32);
+                }
+            }
         }
+        Err(err) => log::error!("bounty.closing_issues.fail. Cause: {}", err.message),
+    }
+    if issues.is_empty() {
+        return Ok(());
+    }
+    if let Err(err) = Bounty::expire(db) {
+        log::error!("bounty.expire.fail. Cause: {}", err);
+    }
+    // The bounties are claimed along with their payouts, a bounty is never
+    // paid without them
+    let tx = match db.transaction() {
+        Ok(tx) => tx,
+        Err(err) => return Err(Error::new(500, err.to_string())),
+    };
+    let bounties = match Bounty::claim(&tx, full_name, &issues, number as i32) {
+        Ok(bounties) => bounties,
+        Err(err) => return Err(Error::new(500, err.to_string())),
     };
 
+    let author = webhook_data.pull_request.user.login.as_str();
+    let mut payouts: Vec<Payout> = vec![];
+    for bounty in &bounties {
+        let reviewer_amount = if reviewers.is_empty() {
+            0
+        } else {
+            (bounty.amount as f64 * policy.bounty_reviewer_share / reviewers.len() as f64) as i64
+        };
+        let author_amount = bounty.amount - reviewer_amount * reviewers.len() as i64;
+        let mut shares = vec![(author, author_amount)];
+        shares.extend(
+            reviewers
+                .iter()
+                .map(|reviewer| (*reviewer, reviewer_amount)),
+        );
+        for (username, amount) in shares {
+            if amount <= 0 {
+                continue;
+            }
+            let payout = new_payout(
+                db,
+                webhook_data,
+                username,
+                amount as u64,
+                0.0,
+                Some(bounty.id),
+                payout_hold_reason(db, policy, accounts, username, author),
+            );
+            if let Some(payout) = insert_payout(&tx, payout)? {
+                payouts.push(payout);
+            }
+        }
+    }
+    if let Err(err) = tx.commit() {
+        return Err(Error::new(500, err.to_string()));
+    }
+
+    for bounty in &bounties {
+        let message = format!(
+            "@{}: the bounty of {} OCT on #{} goes to this pull request",
+            author,
+            api.sdk.to_ui_amount(bounty.amount as u64),
+            bounty.issue
+        );
+        if let Err(err) = github_client.comment_issue(webhook_data, &message) {
+            log::error!("bounty.comment.fail. Cause: {}", err.message);
+        }
+    }
+    send_payouts(api, db, &sdk::FundingSource::Treasury, &mut payouts);
     Ok(())
 }
 
-// approved_pull_request recalculates the pull request value of
-// all requests and comments made by the user
-// This is posted on the pull request and on merge commit the
-// OCT is transferred to the users wallet
-pub fn approved_pull_request(webhook_data: &WebhookRequest, api: &Api) -> Result<(), Error> {
-    Ok(())
+// issue_comment runs the commands given in issue comments
+pub fn issue_comment(
+    webhook_data: &WebhookRequest,
+    api: &Api,
+    github_app: &app::Config,
+    db: &postgres::Connection,
+) -> Result<(), Error> {
+    let command = match command::parse(&webhook_data.comment.body) {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+    info!("github_webhook.type.issue_comment.command");
+    let access_token = match github_app.authenticate_app(webhook_data.installation.id.to_string()) {
+        Ok(token) => token,
+        Err(err) => {
+            log::error!("github_webhook.type.issue_comment.fail. Cause: {:}", err);
+            return Err(Error::new(500, err.to_string()));
+        }
+    };
+    let github_client = github_client(api, &access_token.token);
+    let commenter = &webhook_data.comment.user.login;
+    let reply = match command {
+        Ok(Command::Bounty {
+            amount,
+            expires_in_days,
+        }) => bounty_command(api, db, webhook_data, amount, expires_in_days),
+        Ok(Command::Approve) => approve_command(api, db, webhook_data),
+        Err(message) => message,
+    };
+    github_client.comment_on_issue(
+        &webhook_data.repository.owner.login,
+        &webhook_data.repository.name,
+        webhook_data.issue.number,
+        &format!("@{}: {}", commenter, reply),
+    )
+}
+
+// approve_command approves the open payout proposal of the pull request
+// the comment is on. Only maintainers may approve it
+fn approve_command(api: &Api, db: &postgres::Connection, webhook_data: &WebhookRequest) -> String {
+    if webhook_data.issue.pull_request.is_none() {
+        return String::from("payout proposals can only be approved on pull requests");
+    }
+    let full_name = &webhook_data.repository.full_name;
+    let commenter = &webhook_data.comment.user.login;
+    let repository = match Repository::get(db, full_name) {
+        Ok(repository) => repository,
+        Err(err) => return err.to_string(),
+    };
+    match permission::repository_role(api, &repository, commenter) {
+        Ok(role) if role.is_maintainer() => {}
+        Ok(_) => return String::from("only maintainers can approve payout proposals"),
+        Err(err) => return err.message,
+    }
+    let number = webhook_data.issue.number as i32;
+    let proposal =
+        match Proposal::list(db, full_name, Some(number), Some(PROPOSAL_OPEN.to_string())) {
+            Ok(proposals) => match proposals.into_iter().next() {
+                Some(proposal) => proposal,
+                None => return String::from("this pull request has no open payout proposal"),
+            },
+            Err(err) => return err.to_string(),
+        };
+    match approve_proposal(api, db, full_name, proposal.id, commenter) {
+        Ok((_, payouts)) => {
+            let sent = payouts
+                .iter()
+                .filter(|payout| payout.status == STATUS_SENT)
+                .count();
+            format!(
+                "approved payout proposal {}, {} of {} payouts were sent",
+                proposal.id,
+                sent,
+                payouts.len()
+            )
+        }
+        Err(err) => err.message,
+    }
+}
+
+// bounty_command escrows a bounty for the issue from the pool of the
+// repository. Only maintainers may spend the pool, it returns the reply
+fn bounty_command(
+    api: &Api,
+    db: &postgres::Connection,
+    webhook_data: &WebhookRequest,
+    amount: f64,
+    expires_in_days: Option<i32>,
+) -> String {
+    if webhook_data.issue.pull_request.is_some() {
+        return String::from("bounties can only be set on issues");
+    }
+    let full_name = &webhook_data.repository.full_name;
+    let commenter = &webhook_data.comment.user.login;
+    let repository = match Repository::get(db, full_name) {
+        Ok(repository) => repository,
+        Err(err) => return err.to_string(),
+    };
+    match permission::repository_role(api, &repository, commenter) {
+        Ok(role) if role.is_maintainer() => {}
+        Ok(_) => {
+            return format!(
+                "only maintainers can fund bounties from the pool. Anyone can fund this issue with a donation to {}",
+                full_name
+            )
+        }
+        Err(err) => return err.message,
+    }
+    let expires_at = expires_in_days
+        .map(|days| (chrono::Utc::now() + chrono::Duration::days(days as i64)).to_rfc3339());
+    match Bounty::fund(
+        db,
+        full_name,
+        webhook_data.issue.number as i32,
+        api.sdk.to_base_units(amount) as i64,
+        commenter,
+        expires_at.as_deref(),
+    ) {
+        Ok(bounty) => match bounty.expires_at {
+            Some(expires_at) => format!(
+                "a bounty of {} OCT is escrowed for this issue until {}",
+                amount, expires_at
+            ),
+            None => format!("a bounty of {} OCT is escrowed for this issue", amount),
+        },
+        Err(err) => err.to_string(),
+    }
 }
 
 // merge_pull_request makes sure the reviewers receieves their tokens
 pub fn merge_pull_request(
     webhook_data: &WebhookRequest,
     api: &Api,
+    github_app: &app::Config,
     db: &MyPgDatabase,
 ) -> Result<(), Error> {
     info!("github_webhook.type.pull_request_review");
-    let access_token = match api
-        .github_app_client
-        .authenticate_app(webhook_data.installation.id.to_string())
-    {
+    let access_token = match github_app.authenticate_app(webhook_data.installation.id.to_string()) {
         Ok(token) => token,
         Err(err) => {
             log::error!(
@@ -192,8 +1075,8 @@ pub fn merge_pull_request(
     };
 
     // get all reviews comments
-    let github_client = api::Config::new(&access_token.token);
-    let review_comments = match github_client.list_review_comments(&webhook_data) {
+    let github_client = github_client(api, &access_token.token);
+    let review_comments = match active_review_comments(&github_client, webhook_data) {
         Ok(res) => res,
         Err(err) => {
             log::error!(
@@ -204,54 +1087,784 @@ pub fn merge_pull_request(
         }
     };
     info!("review_comments {:?}", review_comments);
-    let review_comments_ = &review_comments;
-    // get all reviewers
-    // Vec
-    let mut users: Vec<&str> = review_comments_
-        .into_iter()
-        .filter(|comment| comment.user.login != webhook_data.pull_request.user.login)
-        .map(|comment| comment.user.login.as_str())
-        .collect();
-    users.sort();
-    users.dedup();
+    let policy = pull_request_policy(api, &db, &github_client, webhook_data);
+    let (users, accounts) = payee_accounts(
+        &github_client,
+        webhook_data,
+        &policy,
+        reviewers(
+            &review_comments,
+            &webhook_data.pull_request.user.login,
+            &policy,
+        ),
+    );
+    let num_reviewers = users.len();
 
-    let owner = &webhook_data.pull_request.user.login;
-    let owner_user = User::new(&owner, &owner);
-    let owner_addr = match owner_user.get_address_from_username(&db) {
-        Ok(addr) => addr,
+    // Bounties are paid whether or not the pull request is eligible for
+    // review rewards
+    if let Err(err) = pay_bounties(
+        api,
+        &db,
+        &github_client,
+        webhook_data,
+        &users,
+        &policy,
+        &accounts,
+    ) {
+        log::error!("bounty.pay.fail. Cause: {}", err.message);
+    }
+    // Rewards are checked against the caps and recorded under the caps
+    // lock, so concurrent merges do not pass a cap together and a
+    // redelivered webhook finds the rewards of the first delivery
+    let caps_lock = match caps::lock(&db, &webhook_data.repository.full_name) {
+        Ok(caps_lock) => caps_lock,
+        Err(err) => {
+            log::error!("payout.caps.lock_fail. Cause: {}", err);
+            return Err(Error::new(500, err.to_string()));
+        }
+    };
+    // A redelivered webhook must not reward the pull request again
+    match Payout::exists_for_pull_request(
+        &db,
+        &webhook_data.repository.full_name,
+        webhook_data.pull_request.number as i32,
+    ) {
+        Ok(true) => {
+            info!("github_webhook.type.pull_request.merged.already_rewarded");
+            return Ok(());
+        }
+        Ok(false) => (),
+        Err(err) => {
+            log::error!("payout.exists.fail. Cause: {}", err);
+            return Err(Error::new(500, err.to_string()));
+        }
+    }
+    if !policy.is_eligible(&pull_request_labels(webhook_data)) {
+        info!("github_webhook.type.pull_request.merged.not_eligible");
+        return Ok(());
+    }
+
+    let repository = match Repository::get(&db, &webhook_data.repository.full_name) {
+        Ok(repository) => repository,
         Err(err) => {
             log::error!("error: {:?}", err);
-            return Err(Error::new(500, String::from("oh no")));
+            return Err(Error::new(500, err.to_string()));
         }
     };
+    let funding_mode = effective_funding_mode(&repository, policy.funding_mode.as_deref());
+    let source = funding_source(
+        &db,
+        &repository,
+        &funding_mode,
+        &webhook_data.pull_request.user.login,
+    )?;
 
+    let author = &webhook_data.pull_request.user.login;
+    let mut checks = PayoutChecks::new(api, &db, webhook_data, &policy, &accounts);
+    let mut payouts: Vec<Payout> = vec![];
+    let mut reward_total: i64 = 0;
+    let mut summary: Vec<String> = vec![];
     for username in users {
-        let review_score_comment = review_score_for_user(review_comments_, &username);
-        let review_score_comment_atuser = format!("@{}: {}", username, review_score_comment);
+        let review_score = review_score_for_user(&review_comments, &username, &policy);
+        let reward = review_reward(review_score, num_reviewers, &policy);
+        let amount = api.sdk.to_base_units(reward);
+        if amount == 0 {
+            summary.push(format!("@{} (reviewer): {} OCT", username, reward));
+            continue;
+        }
+
+        // Transfer money to wallets from PR owner
+        let decision = checks.decide(username, amount, true);
+        summary.push(summary_line(username, "reviewer", reward, &decision.note));
+        if decision.amount == 0 {
+            continue;
+        }
+        reward_total += decision.amount as i64;
+        let mut payout = new_payout(
+            &db,
+            webhook_data,
+            &username,
+            decision.amount,
+            review_score,
+            None,
+            decision.hold_reason,
+        );
+        payout.funding_mode = Some(funding_mode.as_str().to_string());
+        payouts.push(payout);
+    }
+
+    // The author is paid from the pool of the repository whatever pays
+    // the reviewers
+    let mut author_payouts: Vec<Payout> = vec![];
+    match author_reward(
+        api,
+        &github_client,
+        webhook_data,
+        &repository,
+        &policy,
+        &accounts,
+    ) {
+        Ok(Some((score, reward))) => {
+            let amount = api.sdk.to_base_units(reward);
+            if amount == 0 {
+                summary.insert(0, summary_line(author, "author", reward, &None));
+            } else {
+                let decision = checks.decide(author, amount, false);
+                summary.insert(0, summary_line(author, "author", reward, &decision.note));
+                if decision.amount > 0 {
+                    reward_total += decision.amount as i64;
+                    author_payouts.push(new_payout(
+                        &db,
+                        webhook_data,
+                        author,
+                        decision.amount,
+                        score,
+                        None,
+                        decision.hold_reason,
+                    ));
+                }
+            }
+        }
+        Ok(None) => info!("payout.author.excluded. Author: {}", author),
+        Err(err) => log::error!("payout.author.fail. Cause: {}", err.message),
+    }
+
+    if let Err(err) = stats::record_merge(
+        &db,
+        &webhook_data.repository.full_name,
+        webhook_data.pull_request.number as i32,
+        author,
+        &webhook_data.pull_request.created_at,
+        &webhook_data.pull_request.merged_at,
+        reward_total,
+    ) {
+        log::error!("stats.record_merge.fail. Cause: {}", err);
+    }
+
+    let mut comment = merge_summary_comment(&summary);
+    if policy.require_payout_approval {
+        // Nothing is sent until a maintainer approves the proposal
+        if reward_total > 0 {
+            payouts.append(&mut author_payouts);
+            match propose_payouts(&db, webhook_data, payouts) {
+                Ok(proposal) => comment.push_str(&format!(
+                    "\n\nThese rewards are payout proposal {} and are sent once a maintainer approves it with `/oct approve`.",
+                    proposal.id
+                )),
+                Err(err) => log::error!("payout.propose.fail. Cause: {}", err.message),
+            }
+        }
+        drop(caps_lock);
+    } else {
+        // The rewards are inserted together, a concurrent delivery of the
+        // same merge fails on the unique payout index and inserts none
+        let tx = match db.transaction() {
+            Ok(tx) => tx,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        let mut sendable: Vec<Payout> = vec![];
+        for payout in payouts {
+            if let Some(payout) = insert_payout(&tx, payout)? {
+                sendable.push(payout);
+            }
+        }
+        let mut author_sendable: Vec<Payout> = vec![];
+        for payout in author_payouts {
+            if let Some(payout) = insert_payout(&tx, payout)? {
+                author_sendable.push(payout);
+            }
+        }
+        if let Err(err) = tx.commit() {
+            return Err(Error::new(500, err.to_string()));
+        }
+        drop(caps_lock);
+        // Pay all reviewers in as few transactions as possible
+        send_payouts(api, &db, &source, &mut sendable);
+        send_payouts(
+            api,
+            &db,
+            &sdk::FundingSource::Treasury,
+            &mut author_sendable,
+        );
+    }
+
+    if !summary.is_empty() {
+        if let Err(err) = github_client.comment_issue(webhook_data, &comment) {
+            log::error!("payout.summary.fail. Cause: {}", err.message);
+        }
+    }
+    Ok(())
+}
+
+// propose_payouts stores the rewards of the merged pull request as a new
+// payout proposal. The proposal and its payouts are inserted together so
+// no reward is recorded outside of it
+fn propose_payouts(
+    db: &postgres::Connection,
+    webhook_data: &WebhookRequest,
+    mut payouts: Vec<Payout>,
+) -> Result<Proposal, Error> {
+    let full_name = &webhook_data.repository.full_name;
+    let number = webhook_data.pull_request.number as i32;
+    let tx = match db.transaction() {
+        Ok(tx) => tx,
+        Err(err) => return Err(Error::new(500, err.to_string())),
+    };
+    let proposal = match Proposal::create(&tx, full_name, number) {
+        Ok(proposal) => proposal,
+        Err(err) => return Err(Error::new(500, err.to_string())),
+    };
+    for payout in payouts.iter_mut() {
+        payout.propose(proposal.id);
+        if let Err(err) = payout.insert(&tx) {
+            return Err(Error::new(500, err.to_string()));
+        }
+    }
+    if let Err(err) = tx.commit() {
+        return Err(Error::new(500, err.to_string()));
+    }
+    info!(
+        "payout.proposed. Repository: {} Proposal: {} Payouts: {}",
+        full_name,
+        proposal.id,
+        payouts.len()
+    );
+    Ok(proposal)
+}
+
+// open_proposal returns the proposal of the repository if it is open
+fn open_proposal(db: &postgres::Connection, full_name: &str, id: i32) -> Result<Proposal, Error> {
+    match Proposal::get(db, id) {
+        Ok(proposal) if proposal.repository != full_name => {
+            Err(Error::new(404, format!("payout proposal {} not found", id)))
+        }
+        Ok(proposal) if proposal.status != PROPOSAL_OPEN => Err(Error::new(
+            400,
+            format!("payout proposal {} is not open", id),
+        )),
+        Ok(proposal) => Ok(proposal),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            Err(Error::new(404, err.to_string()))
+        }
+        Err(err) => Err(Error::new(500, err.to_string())),
+    }
+}
+
+// approve_proposal approves a payout proposal on behalf of the maintainer
+// and sends its payouts with the amounts as edited. Authors are paid from
+// the pool of the repository like on merge
+pub fn approve_proposal(
+    api: &Api,
+    db: &postgres::Connection,
+    full_name: &str,
+    id: i32,
+    maintainer: &str,
+) -> Result<(Proposal, Vec<Payout>), Error> {
+    let mut proposal = open_proposal(db, full_name, id)?;
+    let repository = match Repository::get(db, full_name) {
+        Ok(repository) => repository,
+        Err(err) => return Err(Error::new(404, err.to_string())),
+    };
+    let proposed = match Payout::list_for_proposal(db, id) {
+        Ok(payouts) => payouts,
+        Err(err) => return Err(Error::new(500, err.to_string())),
+    };
+    // The funding source is resolved before the proposal is decided, so a
+    // repository that cannot pay its reviewers keeps the proposal open
+    // instead of approving payouts that are never sent
+    let source = match proposed
+        .iter()
+        .find(|payout| payout.username != payout.author)
+    {
+        Some(payout) => {
+            let funding_mode = effective_funding_mode(&repository, payout.funding_mode.as_deref());
+            Some(funding_source(
+                db,
+                &repository,
+                &funding_mode,
+                &payout.author,
+            )?)
+        }
+        None => None,
+    };
+    if let Err(err) = proposal.decide(db, PROPOSAL_APPROVED, maintainer) {
+        return Err(Error::new(400, err.to_string()));
+    }
+
+    let mut payouts: Vec<Payout> = vec![];
+    let mut author_payouts: Vec<Payout> = vec![];
+    for mut payout in proposed {
+        let addr = linked_wallet(db, &payout.username);
+        if let Err(err) = payout.approve(db, &addr) {
+            log::error!("payout.approve.fail. Payout: {} Cause: {}", payout.id, err);
+            continue;
+        }
+        if payout.status != STATUS_PENDING {
+            continue;
+        }
+        if payout.username == payout.author {
+            author_payouts.push(payout);
+        } else {
+            payouts.push(payout);
+        }
+    }
+    if let Some(source) = source {
+        send_payouts(api, db, &source, &mut payouts);
+    }
+    send_payouts(api, db, &sdk::FundingSource::Treasury, &mut author_payouts);
+
+    match Payout::list_for_proposal(db, id) {
+        Ok(payouts) => Ok((proposal, payouts)),
+        Err(err) => Err(Error::new(500, err.to_string())),
+    }
+}
 
-        match github_client.comment_issue(&webhook_data, &review_score_comment_atuser) {
-            Ok(res) => res,
+// reject_proposal rejects a payout proposal on behalf of the maintainer,
+// none of its payouts are sent
+pub fn reject_proposal(
+    db: &postgres::Connection,
+    full_name: &str,
+    id: i32,
+    maintainer: &str,
+) -> Result<(Proposal, Vec<Payout>), Error> {
+    let mut proposal = open_proposal(db, full_name, id)?;
+    if let Err(err) = proposal.decide(db, PROPOSAL_REJECTED, maintainer) {
+        return Err(Error::new(400, err.to_string()));
+    }
+    let mut payouts = match Payout::list_for_proposal(db, id) {
+        Ok(payouts) => payouts,
+        Err(err) => return Err(Error::new(500, err.to_string())),
+    };
+    for payout in payouts.iter_mut() {
+        if let Err(err) = payout.reject(db) {
+            log::error!("payout.reject.fail. Payout: {} Cause: {}", payout.id, err);
+        }
+    }
+    Ok((proposal, payouts))
+}
+
+// credit_donation credits the donation to the pool of its repository
+// once its transfer is found on chain. It returns whether it was credited
+pub fn credit_donation(
+    api: &Api,
+    db: &postgres::Connection,
+    intent: &mut DonationIntent,
+) -> Result<bool, Error> {
+    if intent.status != DONATION_OPEN {
+        return Ok(false);
+    }
+    let reference = sdk::parse_pubkey(&intent.reference)?;
+    let deposit = match api.sdk.find_deposit(&reference)? {
+        Some(deposit) => deposit,
+        None => return Ok(false),
+    };
+    if let Err(err) = intent.credit(db, &deposit.signature, deposit.amount) {
+        return Err(Error::new(500, err.to_string()));
+    }
+    info!(
+        "pool.donation.credited. Repository: {} Donation: {} Amount: {}",
+        intent.repository, intent.id, deposit.amount
+    );
+    Ok(true)
+}
+
+// sync_donations credits the open donations of a repository that have
+// been paid on chain
+pub fn sync_donations(api: &Api, db: &postgres::Connection, repository: &str) {
+    let intents = match DonationIntent::list_open(db, repository) {
+        Ok(intents) => intents,
+        Err(err) => {
+            log::error!("pool.sync.fail. Repository: {} Cause: {}", repository, err);
+            return;
+        }
+    };
+    for mut intent in intents {
+        if let Err(err) = credit_donation(api, db, &mut intent) {
+            log::error!(
+                "pool.sync.fail. Donation: {} Cause: {}",
+                intent.id,
+                err.message
+            );
+        }
+    }
+}
+
+// reserve_pool takes payouts funded by the treasury out of the pool of
+// the repository before they are sent, after crediting new donations.
+// Bounty payouts were escrowed already. It returns whether anything was
+// reserved, or why the payouts cannot be sent
+fn reserve_pool(
+    api: &Api,
+    db: &postgres::Connection,
+    source: &sdk::FundingSource,
+    payouts: &[Payout],
+) -> Result<bool, String> {
+    let pooled: Vec<(i32, i64)> = payouts
+        .iter()
+        .filter(|payout| payout.bounty_id.is_none())
+        .map(|payout| (payout.id, payout.amount))
+        .collect();
+    let repository = match (source, payouts.first()) {
+        (sdk::FundingSource::Treasury, Some(payout)) if !pooled.is_empty() => {
+            payout.repository.clone()
+        }
+        _ => return Ok(false),
+    };
+    sync_donations(api, db, &repository);
+    match pool::reserve(db, &repository, &pooled) {
+        Ok(true) => Ok(true),
+        Ok(false) => {
+            let total: i64 = pooled.iter().map(|(_, amount)| amount).sum();
+            Err(format!(
+                "the pool of {} does not hold the {} needed",
+                repository, total
+            ))
+        }
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// send_payouts transfers the recorded payouts and stores the outcome of
+// every transfer in the ledger
+fn send_payouts(
+    api: &Api,
+    db: &postgres::Connection,
+    source: &sdk::FundingSource,
+    payouts: &mut Vec<Payout>,
+) {
+    let transfers: Vec<sdk::Transfer> = payouts
+        .iter()
+        .map(|payout| sdk::Transfer {
+            recipient: payout.recipient.clone(),
+            amount: payout.amount as u64,
+        })
+        .collect();
+    let (reserved, results): (bool, Vec<Result<sdk::Receipt, Error>>) =
+        match reserve_pool(api, db, source, payouts) {
+            Ok(reserved) => (reserved, api.sdk.transfer_batch(source, &transfers)),
+            Err(message) => (
+                false,
+                payouts
+                    .iter()
+                    .map(|_| Err(Error::new(500, message.clone())))
+                    .collect(),
+            ),
+        };
+    for (payout, result) in payouts.iter_mut().zip(results) {
+        let res = match result {
+            // The reservation of the pool is kept until a retry finds out
+            // whether the transaction landed
+            Ok(receipt) if !receipt.confirmed => payout.mark_unconfirmed(
+                db,
+                &receipt.signature,
+                &receipt.token_account,
+                receipt.rent_lamports,
+            ),
+            Ok(receipt) => {
+                api.metrics
+                    .oct_paid_out
+                    .with_label_values(&[&payout.repository])
+                    .inc_by(payout.amount as u64);
+                payout.mark_sent(
+                    db,
+                    &receipt.signature,
+                    &receipt.token_account,
+                    receipt.rent_lamports,
+                )
+            }
             Err(err) => {
-                log::error!("error: {:?}", err);
-                return Err(Error::new(500, err.message));
+                log::error!(
+                    "payout.transfer_fail. Repository: {} User: {} Cause: {}",
+                    payout.repository,
+                    payout.username,
+                    err.message
+                );
+                if reserved && payout.bounty_id.is_none() {
+                    if let Err(err) = pool::release(db, payout.id) {
+                        log::error!("pool.release.fail. Payout: {} Cause: {}", payout.id, err);
+                    }
+                }
+                payout.mark_failed(db)
             }
         };
+        api.metrics
+            .payouts
+            .with_label_values(&[&payout.status])
+            .inc();
+        if let Err(err) = res {
+            log::error!("error: {:?}", err);
+        }
+    }
+    api.stats_changed.store(true, Ordering::SeqCst);
+}
 
-        // Transfer money to wallets from PR owner
-        // if not present then post fail message
-        let user = User::new(&username, &username);
-        let addr = match user.get_address_from_username(&db) {
-            Ok(addr) => addr,
+// release_held_payout sends a payout held by the fraud checks once a
+// maintainer approved it. Authors are paid from the pool of the
+// repository like on merge
+pub fn release_held_payout(
+    api: &Api,
+    db: &postgres::Connection,
+    full_name: &str,
+    id: i32,
+) -> Result<Payout, Error> {
+    let mut payout = match Payout::get(db, id) {
+        Ok(payout) if payout.repository == full_name => payout,
+        Ok(_) => return Err(Error::new(404, format!("payout {} not found", id))),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::new(404, err.to_string()))
+        }
+        Err(err) => return Err(Error::new(500, err.to_string())),
+    };
+    if let Err(err) = Repository::get(db, full_name) {
+        return Err(Error::new(404, err.to_string()));
+    }
+    let addr = linked_wallet(db, &payout.username);
+    if let Err(err) = payout.release(db, &addr) {
+        return Err(Error::new(400, err.to_string()));
+    }
+    if payout.status == STATUS_UNCLAIMED {
+        return Ok(payout);
+    }
+    // Held bounty shares are paid from the escrow in the treasury like
+    // the ones that were not held
+    Ok(send_by_source(api, db, vec![payout]).remove(0))
+}
+
+// retry_failed_payouts sends the failed payouts of a repository again,
+// unless the transaction they were last submitted in landed after all.
+// Returns the number of payouts that were sent
+pub fn retry_failed_payouts(
+    api: &Api,
+    db: &postgres::Connection,
+    full_name: &str,
+) -> Result<usize, Error> {
+    if let Err(err) = Repository::get(db, full_name) {
+        return Err(Error::new(404, err.to_string()));
+    }
+    let failed = match Payout::list_by_status(db, full_name, STATUS_FAILED) {
+        Ok(failed) => failed,
+        Err(err) => return Err(Error::new(500, err.to_string())),
+    };
+    let mut landed = 0;
+    let mut resend: Vec<Payout> = vec![];
+    for mut payout in failed {
+        // Concurrent retries only send the payouts they claimed
+        if let Err(err) = payout.retry(db) {
+            log::info!("payout.retry.skip. Payout: {} Cause: {}", payout.id, err);
+            continue;
+        }
+        match unconfirmed_transfer_landed(api, db, &mut payout) {
+            Ok(true) => landed += 1,
+            Ok(false) => resend.push(payout),
             Err(err) => {
-                log::error!("error: {:?}", err);
-                return Err(Error::new(500, String::from("oh no")));
+                log::error!(
+                    "payout.retry.status_fail. Payout: {} Cause: {}",
+                    payout.id,
+                    err.message
+                );
+                if let Err(err) = payout.mark_failed(db) {
+                    log::error!("error: {:?}", err);
+                }
             }
+        }
+    }
+    Ok(landed
+        + send_by_source(api, db, resend)
+            .iter()
+            .filter(|payout| payout.status == STATUS_SENT)
+            .count())
+}
+
+// unconfirmed_transfer_landed looks up the transaction a failed payout
+// was last submitted in. A transaction that landed marks the payout as
+// sent, otherwise the reservation of the pool is put back before the
+// payout is sent again
+fn unconfirmed_transfer_landed(
+    api: &Api,
+    db: &postgres::Connection,
+    payout: &mut Payout,
+) -> Result<bool, Error> {
+    let signature = match payout.signature.clone() {
+        Some(signature) => signature,
+        None => return Ok(false),
+    };
+    if api.sdk.transaction_status(&signature)? == Some(true) {
+        let token_account = payout.token_account.clone();
+        if let Err(err) =
+            payout.mark_sent(db, &signature, &token_account, payout.rent_lamports as u64)
+        {
+            return Err(Error::new(500, err.to_string()));
+        }
+        api.stats_changed.store(true, Ordering::SeqCst);
+        return Ok(true);
+    }
+    if payout.bounty_id.is_none() {
+        if let Err(err) = pool::release(db, payout.id) {
+            return Err(Error::new(500, err.to_string()));
+        }
+    }
+    Ok(false)
+}
+
+// claim_rewards sends the rewards a user earned before linking a wallet
+// to the wallet linked now. Only rewards of the repositories the caller
+// may claim from are sent
+pub fn claim_rewards(
+    api: &Api,
+    db: &postgres::Connection,
+    username: &str,
+    recipient: &str,
+    allows: &dyn Fn(&str) -> bool,
+) -> Result<Vec<Payout>, Error> {
+    let unclaimed = match Payout::list_unclaimed(db, username) {
+        Ok(unclaimed) => unclaimed,
+        Err(err) => return Err(Error::new(500, err.to_string())),
+    };
+    let mut claimed: Vec<Payout> = vec![];
+    for mut payout in unclaimed {
+        if !allows(&payout.repository) {
+            continue;
+        }
+        match payout.claim(db, recipient) {
+            Ok(_) => claimed.push(payout),
+            Err(err) => log::error!("payout.claim.fail. Payout: {} Cause: {}", payout.id, err),
+        }
+    }
+    Ok(send_by_source(api, db, claimed))
+}
+
+// send_by_source sends payouts from the source each was earned against.
+// Bounties were escrowed in the treasury when they were funded and
+// authors are paid from the pool of the repository, reviewers from the
+// funding source of the repository. Payouts whose source cannot be
+// resolved are marked failed so they can be retried
+fn send_by_source(api: &Api, db: &postgres::Connection, payouts: Vec<Payout>) -> Vec<Payout> {
+    // Payouts are grouped by repository, whether the treasury pays them,
+    // the pull request author and the funding mode the source depends on
+    let mut by_source: Vec<((String, bool, String, Option<String>), Vec<Payout>)> = vec![];
+    for payout in payouts {
+        let treasury = payout.bounty_id.is_some() || payout.username == payout.author;
+        let (author, funding_mode) = if treasury {
+            (String::from(""), None)
+        } else {
+            (payout.author.clone(), payout.funding_mode.clone())
         };
+        let key = (payout.repository.clone(), treasury, author, funding_mode);
+        match by_source.iter_mut().find(|(group, _)| *group == key) {
+            Some((_, payouts)) => payouts.push(payout),
+            None => by_source.push((key, vec![payout])),
+        }
+    }
 
-        match sdk::transfer_token(&owner_addr, &addr, 10) {
-            Ok(res) => res,
-            Err(err) => return Err(err),
+    let mut sent: Vec<Payout> = vec![];
+    for ((full_name, treasury, author, funding_mode), mut payouts) in by_source {
+        let source = if treasury {
+            Ok(sdk::FundingSource::Treasury)
+        } else {
+            match Repository::get(db, &full_name) {
+                Ok(repository) => {
+                    let funding_mode = effective_funding_mode(&repository, funding_mode.as_deref());
+                    funding_source(db, &repository, &funding_mode, &author)
+                }
+                Err(err) => Err(Error::new(404, err.to_string())),
+            }
         };
+        match source {
+            Ok(source) => send_payouts(api, db, &source, &mut payouts),
+            Err(err) => {
+                log::error!(
+                    "payout.funding_fail. Repository: {} Author: {} Cause: {}",
+                    full_name,
+                    author,
+                    err.message
+                );
+                for payout in payouts.iter_mut() {
+                    if let Err(err) = payout.mark_failed(db) {
+                        log::error!("error: {:?}", err);
+                    }
+                }
+            }
+        }
+        sent.append(&mut payouts);
+    }
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(login: &str, body: &str) -> ReviewComment {
+        ReviewComment {
+            body: body.to_string(),
+            position: 0,
+            original_position: 0,
+            user: api::User {
+                login: login.to_string(),
+                name: String::from(""),
+            },
+        }
+    }
+
+    #[test]
+    fn saturating_score_is_zero_for_nothing() {
+        assert_eq!(saturating_score(100.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn saturating_score_grows_with_the_raw_score() {
+        let mut last = saturating_score(100.0, 0.0);
+        for val in &[1.0, 10.0, 50.0, 100.0, 500.0] {
+            let score = saturating_score(100.0, *val);
+            assert!(score > last);
+            last = score;
+        }
+    }
+
+    #[test]
+    fn saturating_score_tends_to_one() {
+        let score = saturating_score(100.0, 10_000.0);
+        assert!(score <= 1.0);
+        assert!(score > 0.999);
+    }
+
+    #[test]
+    fn bare_approval_earns_no_more_than_a_commented_review() {
+        let policy = Policy {
+            min_review_length: 10,
+            ..Policy::default()
+        };
+        // The approver left an empty or a too short body, the reviewer
+        // commented on the change
+        let comments = vec![
+            comment("approver", "LGTM"),
+            comment(
+                "reviewer",
+                "This loop reads the file once per line, hoist it out",
+            ),
+        ];
+        let users = reviewers(&comments, "author", &policy);
+        assert_eq!(users, vec!["reviewer"]);
+
+        let approver = review_score_for_user(&comments, "approver", &policy);
+        let reviewer = review_score_for_user(&comments, "reviewer", &policy);
+        assert_eq!(approver, 0.0);
+        assert!(reviewer > approver);
+    }
+
+    #[test]
+    fn reviewers_skip_the_author_and_excluded_users() {
+        let policy = Policy {
+            excluded_users: vec![String::from("maintainer")],
+            ..Policy::default()
+        };
+        let comments = vec![
+            comment("author", "answering the review"),
+            comment("maintainer", "looks fine"),
+            comment("dependabot[bot]", "rebased"),
+            comment("reviewer", "nit: naming"),
+            comment("reviewer", "another nit"),
+        ];
+        assert_eq!(reviewers(&comments, "author", &policy), vec!["reviewer"]);
     }
-    Ok(())
 }
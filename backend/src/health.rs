@@ -0,0 +1,84 @@
+pub mod health {
+    use crate::handlers::Api;
+    use rocket_contrib::databases::postgres;
+    use rocket_contrib::json::JsonValue;
+
+    // Check is the outcome of a single readiness check
+    struct Check {
+        name: &'static str,
+        error: Option<String>,
+    }
+
+    impl Check {
+        fn new<E: ToString>(name: &'static str, result: Result<(), E>) -> Check {
+            Check {
+                name,
+                error: result.err().map(|err| err.to_string()),
+            }
+        }
+
+        fn to_json(&self) -> JsonValue {
+            json!({
+                "ok": self.error.is_none(),
+                "error": self.error,
+            })
+        }
+    }
+
+    fn check_database(db: Option<&postgres::Connection>) -> Check {
+        let result = match db {
+            Some(db) => db
+                .query("SELECT 1", &[])
+                .map(|_| ())
+                .map_err(|err| err.to_string()),
+            None => Err(String::from("no connection available in the pool")),
+        };
+        Check::new("database", result)
+    }
+
+    // check_github_apps makes sure the JWT of every GitHub App can be signed
+    fn check_github_apps(api: &Api) -> Result<(), String> {
+        for github_app in api.github_apps.iter() {
+            if let Err(err) = github_app.client.app_jwt() {
+                return Err(format!("GitHub App {}: {}", github_app.id, err));
+            }
+        }
+        Ok(())
+    }
+
+    // readiness checks the dependencies the backend needs to serve
+    // requests. A low treasury balance is reported but does not make the
+    // backend unready since only payouts are affected
+    pub fn readiness(api: &Api, db: Option<&postgres::Connection>) -> (bool, JsonValue) {
+        let checks = vec![
+            check_database(db),
+            Check::new("github_app", check_github_apps(api)),
+            Check::new("solana", api.sdk.validate_mint().map_err(|err| err.message)),
+        ];
+        let ready = checks.iter().all(|check| check.error.is_none());
+
+        let treasury = match api.sdk.wallet_balance(&api.sdk.treasury_pubkey()) {
+            Ok(balance) => {
+                let balance = api.sdk.to_ui_amount(balance);
+                json!({
+                    "ok": balance >= api.config.treasury_min_balance,
+                    "balance": balance,
+                    "threshold": api.config.treasury_min_balance,
+                })
+            }
+            Err(err) => json!({
+                "ok": false,
+                "error": err.message,
+                "threshold": api.config.treasury_min_balance,
+            }),
+        };
+
+        let mut report = serde_json::Map::new();
+        for check in &checks {
+            report.insert(check.name.to_string(), check.to_json().0);
+        }
+        report.insert(String::from("treasury"), treasury.0);
+        let status = if ready { "ok" } else { "unavailable" };
+        (ready, json!({ "status": status, "checks": report }))
+    }
+}
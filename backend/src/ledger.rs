@@ -0,0 +1,696 @@
+pub mod ledger {
+    use crate::pool::pool::ENTRY_BOUNTY_SHARE_REFUND;
+    use rocket_contrib::databases::postgres;
+    use std::io::{Error, ErrorKind};
+
+    pub const STATUS_PENDING: &str = "pending";
+    pub const STATUS_SENT: &str = "sent";
+    pub const STATUS_FAILED: &str = "failed";
+    // STATUS_UNCLAIMED is a reward for a user that had no linked wallet
+    // when it was earned
+    pub const STATUS_UNCLAIMED: &str = "unclaimed";
+    // STATUS_HELD is a reward flagged by the fraud checks that waits for
+    // a maintainer to release or reject it
+    pub const STATUS_HELD: &str = "held";
+    pub const STATUS_REJECTED: &str = "rejected";
+    // STATUS_PROPOSED is a reward of a payout proposal that waits for a
+    // maintainer to approve the proposal
+    pub const STATUS_PROPOSED: &str = "proposed";
+
+    // PAYOUT_COLUMNS are the columns read by from_row
+    const PAYOUT_COLUMNS: &str = "ID, Repository, PullRequest, Author, Username, Recipient, TokenAccount,
+                Amount, RentLamports, Signature, Status, Score, BountyID, HoldReason,
+                ProposalID, ProposedAmount, FundingMode,
+                to_char(CreatedAt AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS CreatedAt";
+
+    // Payout is a single OCT transfer to a contributor
+    // as recorded in the payout ledger
+    pub struct Payout {
+        pub id: i32,
+        pub repository: String,
+        pub pull_request: i32,
+        // author is the pull request author, needed to resolve author
+        // funded payouts when they are retried
+        pub author: String,
+        pub username: String,
+        pub recipient: String,
+        pub token_account: String,
+        pub amount: i64,
+        pub rent_lamports: i64,
+        pub signature: Option<String>,
+        pub status: String,
+        // score is the review score the reward was computed from
+        pub score: f64,
+        // bounty_id is set for payouts of an issue bounty. They were
+        // escrowed from the pool when the bounty was funded
+        pub bounty_id: Option<i32>,
+        // hold_reason is why the fraud checks held the payout
+        pub hold_reason: Option<String>,
+        // proposal_id is set for payouts of a payout proposal. The
+        // proposed amount is kept when a maintainer edits the amount
+        pub proposal_id: Option<i32>,
+        pub proposed_amount: Option<i64>,
+        // funding_mode is the funding mode reviewer rewards were earned
+        // with, they are sent from the same source when they are retried,
+        // approved or claimed later
+        pub funding_mode: Option<String>,
+        pub created_at: String,
+    }
+
+    // PayoutFilter narrows down the payouts of a user. Dates are RFC 3339
+    #[derive(Default)]
+    pub struct PayoutFilter {
+        pub repository: Option<String>,
+        pub pull_request: Option<i32>,
+        pub statuses: Vec<String>,
+        pub from: Option<String>,
+        pub to: Option<String>,
+    }
+
+    // RepositoryTotal is what a user earned in a repository, in base units
+    pub struct RepositoryTotal {
+        pub repository: String,
+        pub earned: i64,
+        pub pending: i64,
+    }
+
+    impl Payout {
+        pub fn new(
+            repository: &str,
+            pull_request: usize,
+            author: &str,
+            username: &str,
+            recipient: &str,
+            amount: u64,
+        ) -> Payout {
+            Payout {
+                id: 0,
+                repository: repository.to_string(),
+                pull_request: pull_request as i32,
+                author: author.to_string(),
+                username: username.to_string(),
+                recipient: recipient.to_string(),
+                token_account: String::from(""),
+                amount: amount as i64,
+                rent_lamports: 0,
+                signature: None,
+                status: STATUS_PENDING.to_string(),
+                score: 0.0,
+                bounty_id: None,
+                hold_reason: None,
+                proposal_id: None,
+                proposed_amount: None,
+                funding_mode: None,
+                created_at: String::from(""),
+            }
+        }
+
+        fn from_row(row: &postgres::rows::Row) -> Payout {
+            Payout {
+                id: row.get("ID"),
+                repository: row.get("Repository"),
+                pull_request: row.get("PullRequest"),
+                author: row.get("Author"),
+                username: row.get("Username"),
+                recipient: row.get("Recipient"),
+                token_account: row.get("TokenAccount"),
+                amount: row.get("Amount"),
+                rent_lamports: row.get("RentLamports"),
+                signature: row.get("Signature"),
+                status: row.get("Status"),
+                score: row.get("Score"),
+                bounty_id: row.get("BountyID"),
+                hold_reason: row.get("HoldReason"),
+                proposal_id: row.get("ProposalID"),
+                proposed_amount: row.get("ProposedAmount"),
+                funding_mode: row.get("FundingMode"),
+                created_at: row.get("CreatedAt"),
+            }
+        }
+
+        pub fn get(db: &postgres::Connection, id: i32) -> Result<Payout, Error> {
+            let rows = match db.query(
+                &format!("SELECT {} FROM payout WHERE ID=$1", PAYOUT_COLUMNS),
+                &[&id],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                return Ok(Payout::from_row(&row));
+            }
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!("payout {} not found", id),
+            ))
+        }
+
+        // list_by_status lists the payouts of a repository with the given status
+        pub fn list_by_status(
+            db: &postgres::Connection,
+            repository: &str,
+            status: &str,
+        ) -> Result<Vec<Payout>, Error> {
+            let rows = match db.query(
+                &format!(
+                    "
+            SELECT {}
+            FROM payout
+            WHERE Repository=$1 AND Status=$2
+            ORDER BY ID
+            ",
+                    PAYOUT_COLUMNS
+                ),
+                &[&repository, &status],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            Ok(rows.iter().map(|row| Payout::from_row(&row)).collect())
+        }
+
+        // list_unclaimed lists the rewards a user earned before linking a
+        // wallet, oldest first
+        pub fn list_unclaimed(
+            db: &postgres::Connection,
+            username: &str,
+        ) -> Result<Vec<Payout>, Error> {
+            let rows = match db.query(
+                &format!(
+                    "SELECT {} FROM payout WHERE Username=$1 AND Status=$2 ORDER BY ID",
+                    PAYOUT_COLUMNS
+                ),
+                &[&username, &STATUS_UNCLAIMED],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            Ok(rows.iter().map(|row| Payout::from_row(&row)).collect())
+        }
+
+        // list_for_proposal lists the payouts of a payout proposal
+        pub fn list_for_proposal(
+            db: &postgres::Connection,
+            proposal_id: i32,
+        ) -> Result<Vec<Payout>, Error> {
+            let rows = match db.query(
+                &format!(
+                    "SELECT {} FROM payout WHERE ProposalID=$1 ORDER BY ID",
+                    PAYOUT_COLUMNS
+                ),
+                &[&proposal_id],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            Ok(rows.iter().map(|row| Payout::from_row(&row)).collect())
+        }
+
+        // propose puts a reward that would be sent or kept as unclaimed
+        // into the payout proposal before it is inserted. Held rewards stay
+        // held
+        pub fn propose(&mut self, proposal_id: i32) {
+            if self.status == STATUS_PENDING || self.status == STATUS_UNCLAIMED {
+                self.status = STATUS_PROPOSED.to_string();
+                self.proposal_id = Some(proposal_id);
+                self.proposed_amount = Some(self.amount);
+            }
+        }
+
+        // list_for_user lists the payouts of a user matching the filter,
+        // newest first. Pages continue before the id given as cursor
+        pub fn list_for_user(
+            db: &postgres::Connection,
+            username: &str,
+            filter: &PayoutFilter,
+            cursor: Option<i32>,
+            limit: i64,
+        ) -> Result<Vec<Payout>, Error> {
+            let statuses: Option<&Vec<String>> = if filter.statuses.is_empty() {
+                None
+            } else {
+                Some(&filter.statuses)
+            };
+            let rows = match db.query(
+                &format!(
+                    "
+            SELECT {}
+            FROM payout
+            WHERE Username=$1
+                AND ($2::VARCHAR IS NULL OR Repository=$2)
+                AND ($3::INTEGER IS NULL OR PullRequest=$3)
+                AND ($4::VARCHAR[] IS NULL OR Status = ANY($4))
+                AND ($5::TEXT IS NULL OR CreatedAt >= CAST($5::TEXT AS TIMESTAMPTZ))
+                AND ($6::TEXT IS NULL OR CreatedAt < CAST($6::TEXT AS TIMESTAMPTZ))
+                AND ($7::INTEGER IS NULL OR ID < $7)
+            ORDER BY ID DESC
+            LIMIT $8
+            ",
+                    PAYOUT_COLUMNS
+                ),
+                &[
+                    &username,
+                    &filter.repository,
+                    &filter.pull_request,
+                    &statuses,
+                    &filter.from,
+                    &filter.to,
+                    &cursor,
+                    &limit,
+                ],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            Ok(rows.iter().map(|row| Payout::from_row(&row)).collect())
+        }
+
+        // totals_for_user sums what a user was paid and is still owed per
+        // repository
+        pub fn totals_for_user(
+            db: &postgres::Connection,
+            username: &str,
+        ) -> Result<Vec<RepositoryTotal>, Error> {
+            let rows = match db.query(
+                "
+            SELECT Repository,
+                CAST(COALESCE(SUM(Amount) FILTER (WHERE Status=$2), 0) AS BIGINT) AS Earned,
+                CAST(COALESCE(SUM(Amount) FILTER (WHERE Status<>$2), 0) AS BIGINT) AS Pending
+            FROM payout
+            WHERE Username=$1
+            GROUP BY Repository
+            ORDER BY Repository
+            ",
+                &[&username, &STATUS_SENT],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            Ok(rows
+                .iter()
+                .map(|row| RepositoryTotal {
+                    repository: row.get("Repository"),
+                    earned: row.get("Earned"),
+                    pending: row.get("Pending"),
+                })
+                .collect())
+        }
+
+        // count_by_status counts the payouts of all repositories that
+        // have one of the statuses
+        pub fn count_by_status(db: &postgres::Connection, statuses: &[&str]) -> Result<i64, Error> {
+            let rows = match db.query(
+                "
+            SELECT COUNT(*) AS Count
+            FROM payout
+            WHERE Status = ANY($1)
+            ",
+                &[&statuses],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            let mut count = 0;
+            for row in &rows {
+                count = row.get("Count");
+            }
+            Ok(count)
+        }
+
+        // count_for_user counts the payouts a user earned before, held
+        // and rejected ones aside
+        pub fn count_for_user(db: &postgres::Connection, username: &str) -> Result<i64, Error> {
+            let rows = match db.query(
+                "
+            SELECT COUNT(*) AS Count
+            FROM payout
+            WHERE Username=$1 AND Status <> ALL($2)
+            ",
+                &[&username, &vec![STATUS_HELD, STATUS_REJECTED]],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            let mut count = 0;
+            for row in &rows {
+                count = row.get("Count");
+            }
+            Ok(count)
+        }
+
+        // reciprocal_reviews counts how often two users were both paid for
+        // reviewing pull requests of the other. It is the smaller of the
+        // two counts so one-sided reviewing is never counted
+        pub fn reciprocal_reviews(
+            db: &postgres::Connection,
+            user: &str,
+            other: &str,
+        ) -> Result<i64, Error> {
+            let rows = match db.query(
+                "
+            SELECT LEAST(
+                COUNT(*) FILTER (WHERE Username=$1 AND Author=$2),
+                COUNT(*) FILTER (WHERE Username=$2 AND Author=$1)
+            ) AS Count
+            FROM payout
+            WHERE BountyID IS NULL AND Status <> $3
+                AND ((Username=$1 AND Author=$2) OR (Username=$2 AND Author=$1))
+            ",
+                &[&user, &other, &STATUS_REJECTED],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            let mut count = 0;
+            for row in &rows {
+                count = row.get("Count");
+            }
+            Ok(count)
+        }
+
+        // exists_for_pull_request tells whether the reviewers or the author
+        // of a merged pull request were already rewarded. Bounty shares are
+        // recorded on their own
+        pub fn exists_for_pull_request(
+            db: &postgres::Connection,
+            repository: &str,
+            pull_request: i32,
+        ) -> Result<bool, Error> {
+            let rows = match db.query(
+                "
+            SELECT EXISTS (
+                SELECT 1 FROM payout
+                WHERE Repository=$1 AND PullRequest=$2 AND BountyID IS NULL
+            ) AS Found
+            ",
+                &[&repository, &pull_request],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            let mut found = false;
+            for row in &rows {
+                found = row.get("Found");
+            }
+            Ok(found)
+        }
+
+        // sum_since sums the payouts of a repository, or of a single user
+        // in it, of the last days. Bounties were escrowed when they were
+        // funded and held, rejected or failed payouts were not paid, so
+        // none of them counts
+        pub fn sum_since(
+            db: &postgres::Connection,
+            repository: &str,
+            username: Option<&str>,
+            days: i32,
+        ) -> Result<i64, Error> {
+            let rows = match db.query(
+                "
+            SELECT CAST(COALESCE(SUM(Amount), 0) AS BIGINT) AS Total
+            FROM payout
+            WHERE Repository=$1
+                AND ($2::VARCHAR IS NULL OR Username=$2)
+                AND CreatedAt > now() - make_interval(days => $3)
+                AND BountyID IS NULL
+                AND Status <> ALL($4)
+            ",
+                &[
+                    &repository,
+                    &username,
+                    &days,
+                    &vec![STATUS_HELD, STATUS_REJECTED, STATUS_FAILED],
+                ],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            let mut total = 0;
+            for row in &rows {
+                total = row.get("Total");
+            }
+            Ok(total)
+        }
+
+        // insert stores the payout and returns its id. It takes a
+        // transaction to insert it along with other records
+        pub fn insert(&mut self, db: &dyn postgres::GenericConnection) -> Result<i32, Error> {
+            let query_result = db.query(
+                "
+            INSERT INTO payout (Repository, PullRequest, Author, Username, Recipient, TokenAccount, Amount, Status, Score, BountyID, HoldReason, ProposalID, ProposedAmount, FundingMode)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            RETURNING ID
+            ",
+                &[
+                    &self.repository,
+                    &self.pull_request,
+                    &self.author,
+                    &self.username,
+                    &self.recipient,
+                    &self.token_account,
+                    &self.amount,
+                    &self.status,
+                    &self.score,
+                    &self.bounty_id,
+                    &self.hold_reason,
+                    &self.proposal_id,
+                    &self.proposed_amount,
+                    &self.funding_mode,
+                ],
+            );
+
+            let rows = match query_result {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                self.id = row.get("ID");
+            }
+            Ok(self.id)
+        }
+
+        // mark_sent records the transaction signature, the associated
+        // token account and the rent paid to create it
+        pub fn mark_sent(
+            &mut self,
+            db: &postgres::Connection,
+            signature: &str,
+            token_account: &str,
+            rent_lamports: u64,
+        ) -> Result<(), Error> {
+            self.signature = Some(signature.to_string());
+            self.token_account = token_account.to_string();
+            self.rent_lamports = rent_lamports as i64;
+            self.status = STATUS_SENT.to_string();
+            let query_res = db.execute(
+                "
+            UPDATE payout
+            SET Signature=$1, TokenAccount=$2, RentLamports=$3, Status=$4
+            WHERE ID=$5
+            ",
+                &[
+                    &self.signature,
+                    &self.token_account,
+                    &self.rent_lamports,
+                    &self.status,
+                    &self.id,
+                ],
+            );
+
+            match query_res {
+                Ok(_) => return Ok(()),
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        // release makes a held payout payable again. Users without a
+        // linked wallet keep it as unclaimed
+        pub fn release(&mut self, db: &postgres::Connection, recipient: &str) -> Result<(), Error> {
+            self.make_payable(db, recipient, STATUS_HELD)
+        }
+
+        // claim makes an unclaimed payout payable to the wallet the user
+        // linked since
+        pub fn claim(&mut self, db: &postgres::Connection, recipient: &str) -> Result<(), Error> {
+            self.make_payable(db, recipient, STATUS_UNCLAIMED)
+        }
+
+        // approve makes a proposed payout payable. Payouts edited down to
+        // nothing are rejected
+        pub fn approve(&mut self, db: &postgres::Connection, recipient: &str) -> Result<(), Error> {
+            if self.amount == 0 {
+                self.status = STATUS_REJECTED.to_string();
+                return self.update_from(db, STATUS_PROPOSED);
+            }
+            self.make_payable(db, recipient, STATUS_PROPOSED)
+        }
+
+        fn make_payable(
+            &mut self,
+            db: &postgres::Connection,
+            recipient: &str,
+            from: &str,
+        ) -> Result<(), Error> {
+            self.recipient = recipient.to_string();
+            self.status = if recipient.is_empty() {
+                STATUS_UNCLAIMED.to_string()
+            } else {
+                STATUS_PENDING.to_string()
+            };
+            self.update_from(db, from)
+        }
+
+        // reject rejects a held or proposed payout, it is never sent. The
+        // escrow of a rejected bounty share goes back to the pool of the
+        // repository along with the rejection
+        pub fn reject(&mut self, db: &postgres::Connection) -> Result<(), Error> {
+            let from = self.status.clone();
+            if from != STATUS_HELD && from != STATUS_PROPOSED {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("payout {} is not held or proposed", self.id),
+                ));
+            }
+            self.status = STATUS_REJECTED.to_string();
+            if self.bounty_id.is_none() {
+                return self.update_from(db, &from);
+            }
+            let query_res = db.query(
+                "
+            WITH rejected AS (
+                UPDATE payout SET Status=$1
+                WHERE ID=$2 AND Status=$3
+                RETURNING ID, Repository, Amount
+            )
+            INSERT INTO pool_entry (Repository, Amount, Kind, Reference)
+            SELECT Repository, Amount, $4::VARCHAR, ID FROM rejected
+            RETURNING Reference
+            ",
+                &[&self.status, &self.id, &from, &ENTRY_BOUNTY_SHARE_REFUND],
+            );
+            match query_res {
+                Ok(rows) if rows.is_empty() => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("payout {} is not {}", self.id, from),
+                )),
+                Ok(_) => Ok(()),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        // set_amount changes the amount of a proposed payout, at most up to
+        // the amount that was proposed
+        pub fn set_amount(&mut self, db: &postgres::Connection, amount: i64) -> Result<(), Error> {
+            if amount < 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    String::from("amount must not be negative"),
+                ));
+            }
+            // The proposed amount already went through the caps, so a
+            // payout is only ever edited down
+            let query_res = db.query(
+                "
+            UPDATE payout
+            SET Amount=LEAST($1, COALESCE(ProposedAmount, Amount))
+            WHERE ID=$2 AND Status=$3
+            RETURNING Amount
+            ",
+                &[&amount, &self.id, &STATUS_PROPOSED],
+            );
+            match query_res {
+                Ok(rows) => match rows.iter().next() {
+                    Some(row) => {
+                        self.amount = row.get(0);
+                        Ok(())
+                    }
+                    None => Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("payout {} is not proposed", self.id),
+                    )),
+                },
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        // update_from stores the new status of the payout. It fails when
+        // the payout left the status it is moved from in the meantime
+        fn update_from(&self, db: &postgres::Connection, from: &str) -> Result<(), Error> {
+            let query_res = db.execute(
+                "
+            UPDATE payout
+            SET Recipient=$1, Status=$2
+            WHERE ID=$3 AND Status=$4
+            ",
+                &[&self.recipient, &self.status, &self.id, &from],
+            );
+            match query_res {
+                Ok(1) => Ok(()),
+                Ok(_) => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("payout {} is not {}", self.id, from),
+                )),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        // mark_unconfirmed records a transfer that was submitted but not
+        // confirmed as failed. The signature is kept so a retry can look up
+        // whether it landed before sending it again
+        pub fn mark_unconfirmed(
+            &mut self,
+            db: &postgres::Connection,
+            signature: &str,
+            token_account: &str,
+            rent_lamports: u64,
+        ) -> Result<(), Error> {
+            self.signature = Some(signature.to_string());
+            self.token_account = token_account.to_string();
+            self.rent_lamports = rent_lamports as i64;
+            self.status = STATUS_FAILED.to_string();
+            let query_res = db.execute(
+                "
+            UPDATE payout
+            SET Signature=$1, TokenAccount=$2, RentLamports=$3, Status=$4
+            WHERE ID=$5
+            ",
+                &[
+                    &self.signature,
+                    &self.token_account,
+                    &self.rent_lamports,
+                    &self.status,
+                    &self.id,
+                ],
+            );
+
+            match query_res {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        // retry claims a failed payout to send it again. It fails when
+        // another retry claimed it first
+        pub fn retry(&mut self, db: &postgres::Connection) -> Result<(), Error> {
+            self.status = STATUS_PENDING.to_string();
+            self.update_from(db, STATUS_FAILED)
+        }
+
+        pub fn mark_failed(&mut self, db: &postgres::Connection) -> Result<(), Error> {
+            self.status = STATUS_FAILED.to_string();
+            let query_res = db.execute(
+                "
+            UPDATE payout
+            SET Status=$1
+            WHERE ID=$2
+            ",
+                &[&self.status, &self.id],
+            );
+
+            match query_res {
+                Ok(_) => return Ok(()),
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+pub mod api_error {
+    use crate::lib::trace::trace::request_id_for;
+    use crate::lib::web_error::WebError::WebError;
+    use rithub::error::errors::Error;
+    use rocket::http::{ContentType, Status};
+    use rocket::request::Request;
+    use rocket::response::{self, Responder, Response};
+    use rocket_contrib::databases::postgres;
+    use serde_json::Value;
+    use std::io;
+    use std::io::Cursor;
+
+    // ApiError is the error returned by all routes. It is sent as
+    // {code, message, details, request_id} with the matching status
+    #[derive(Debug)]
+    pub struct ApiError {
+        pub status: Status,
+        pub message: String,
+        pub details: Option<Value>,
+    }
+
+    // code is the machine readable name of the status
+    fn code(status: Status) -> &'static str {
+        match status.code {
+            400 => "bad_request",
+            401 => "unauthorized",
+            403 => "forbidden",
+            404 => "not_found",
+            409 => "conflict",
+            422 => "unprocessable_entity",
+            502 => "bad_gateway",
+            503 => "service_unavailable",
+            code if code >= 500 => "internal_error",
+            _ => "error",
+        }
+    }
+
+    fn status_from_code(code: usize) -> Status {
+        match Status::from_code(code as u16) {
+            Some(status) if status.code >= 400 => status,
+            _ => Status::InternalServerError,
+        }
+    }
+
+    impl ApiError {
+        pub fn new(status: Status, message: &str) -> ApiError {
+            ApiError {
+                status,
+                message: message.to_string(),
+                details: None,
+            }
+        }
+
+        pub fn with_details(mut self, details: Value) -> ApiError {
+            self.details = Some(details);
+            self
+        }
+
+        pub fn bad_request(message: &str) -> ApiError {
+            ApiError::new(Status::BadRequest, message)
+        }
+
+        pub fn unauthorized(message: &str) -> ApiError {
+            ApiError::new(Status::Unauthorized, message)
+        }
+
+        pub fn forbidden(message: &str) -> ApiError {
+            ApiError::new(Status::Forbidden, message)
+        }
+
+        pub fn not_found(message: &str) -> ApiError {
+            ApiError::new(Status::NotFound, message)
+        }
+
+        pub fn internal(message: &str) -> ApiError {
+            ApiError::new(Status::InternalServerError, message)
+        }
+    }
+
+    impl<'a> Responder<'a> for ApiError {
+        fn respond_to(self, req: &Request) -> response::Result<'a> {
+            let body = json!({
+                "code": code(self.status),
+                "message": self.message,
+                "details": self.details,
+                "request_id": request_id_for(req),
+            });
+            Response::build()
+                .header(ContentType::JSON)
+                .status(self.status)
+                .sized_body(Cursor::new(body.to_string()))
+                .ok()
+        }
+    }
+
+    // The rithub error is used by the GitHub client, the handlers and the sdk
+    impl From<Error> for ApiError {
+        fn from(error: Error) -> Self {
+            ApiError::new(status_from_code(error.status()), &error.message)
+        }
+    }
+
+    // Database errors are logged and not shown to the client
+    impl From<postgres::Error> for ApiError {
+        fn from(error: postgres::Error) -> Self {
+            log::error!("api_error.postgres. Cause: {}", error);
+            ApiError::internal("database error")
+        }
+    }
+
+    impl From<io::Error> for ApiError {
+        fn from(error: io::Error) -> Self {
+            let status = match error.kind() {
+                io::ErrorKind::NotFound => Status::NotFound,
+                io::ErrorKind::InvalidInput => Status::BadRequest,
+                io::ErrorKind::PermissionDenied => Status::Forbidden,
+                _ => {
+                    log::error!("api_error.io. Cause: {}", error);
+                    return ApiError::internal("internal error");
+                }
+            };
+            ApiError::new(status, &error.to_string())
+        }
+    }
+
+    impl From<WebError> for ApiError {
+        fn from(error: WebError) -> Self {
+            ApiError::new(status_from_code(error.status()), error.message())
+        }
+    }
+
+    // Catchers answer errors raised outside of routes, such as failing
+    // request guards and unknown paths, with the same JSON body
+    #[catch(400)]
+    pub fn bad_request(_req: &Request) -> ApiError {
+        ApiError::bad_request("bad request")
+    }
+
+    #[catch(401)]
+    pub fn unauthorized(_req: &Request) -> ApiError {
+        ApiError::unauthorized("unauthorized")
+    }
+
+    #[catch(403)]
+    pub fn forbidden(_req: &Request) -> ApiError {
+        ApiError::forbidden("forbidden")
+    }
+
+    #[catch(404)]
+    pub fn not_found(req: &Request) -> ApiError {
+        ApiError::not_found(&format!("{} was not found", req.uri().path()))
+    }
+
+    #[catch(422)]
+    pub fn unprocessable_entity(_req: &Request) -> ApiError {
+        ApiError::new(
+            Status::UnprocessableEntity,
+            "the request body could not be parsed",
+        )
+    }
+
+    #[catch(500)]
+    pub fn internal_error(_req: &Request) -> ApiError {
+        ApiError::internal("internal error")
+    }
+}
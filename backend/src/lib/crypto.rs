@@ -0,0 +1,115 @@
+pub mod crypto {
+    use openssl::rand::rand_bytes;
+    use openssl::sha::sha256;
+    use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+    use std::io::{Error, ErrorKind};
+
+    const NONCE_LEN: usize = 12;
+    const TAG_LEN: usize = 16;
+
+    // random_token returns len random bytes encoded as hex
+    pub fn random_token(len: usize) -> Result<String, Error> {
+        let mut buf = vec![0; len];
+        if let Err(err) = rand_bytes(&mut buf) {
+            return Err(Error::new(ErrorKind::Other, format!("{}", err)));
+        }
+        Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    // SecretBox encrypts secrets such as OAuth tokens before they are
+    // stored. The layout is nonce || tag || ciphertext
+    pub struct SecretBox {
+        key: [u8; 32],
+    }
+
+    impl SecretBox {
+        pub fn new(secret: &str) -> SecretBox {
+            SecretBox {
+                key: sha256(secret.as_bytes()),
+            }
+        }
+
+        pub fn encrypt(&self, plaintext: &str) -> Result<Vec<u8>, Error> {
+            let mut nonce = [0; NONCE_LEN];
+            if let Err(err) = rand_bytes(&mut nonce) {
+                return Err(Error::new(ErrorKind::Other, format!("{}", err)));
+            }
+            let mut tag = [0; TAG_LEN];
+            let ciphertext = match encrypt_aead(
+                Cipher::aes_256_gcm(),
+                &self.key,
+                Some(&nonce),
+                &[],
+                plaintext.as_bytes(),
+                &mut tag,
+            ) {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            let mut sealed = Vec::with_capacity(NONCE_LEN + TAG_LEN + ciphertext.len());
+            sealed.extend_from_slice(&nonce);
+            sealed.extend_from_slice(&tag);
+            sealed.extend_from_slice(&ciphertext);
+            Ok(sealed)
+        }
+
+        pub fn decrypt(&self, sealed: &[u8]) -> Result<String, Error> {
+            if sealed.len() < NONCE_LEN + TAG_LEN {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    String::from("sealed secret is too short"),
+                ));
+            }
+            let (nonce, rest) = sealed.split_at(NONCE_LEN);
+            let (tag, ciphertext) = rest.split_at(TAG_LEN);
+            let plaintext = match decrypt_aead(
+                Cipher::aes_256_gcm(),
+                &self.key,
+                Some(nonce),
+                &[],
+                ciphertext,
+                tag,
+            ) {
+                Ok(res) => res,
+                Err(err) => return Err(Error::new(ErrorKind::InvalidData, format!("{}", err))),
+            };
+            match String::from_utf8(plaintext) {
+                Ok(res) => Ok(res),
+                Err(err) => Err(Error::new(ErrorKind::InvalidData, format!("{}", err))),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn secret_box_round_trips() {
+            let secret_box = SecretBox::new("session secret");
+            let sealed = secret_box.encrypt("gho_token").unwrap();
+            assert_eq!(secret_box.decrypt(&sealed).unwrap(), "gho_token");
+        }
+
+        #[test]
+        fn secret_box_uses_a_fresh_nonce() {
+            let secret_box = SecretBox::new("session secret");
+            let first = secret_box.encrypt("gho_token").unwrap();
+            let second = secret_box.encrypt("gho_token").unwrap();
+            assert_ne!(first, second);
+        }
+
+        #[test]
+        fn secret_box_rejects_tampered_or_foreign_secrets() {
+            let secret_box = SecretBox::new("session secret");
+            let mut sealed = secret_box.encrypt("gho_token").unwrap();
+            assert!(SecretBox::new("other secret").decrypt(&sealed).is_err());
+            let last = sealed.len() - 1;
+            sealed[last] ^= 1;
+            assert!(secret_box.decrypt(&sealed).is_err());
+            assert!(secret_box
+                .decrypt(&sealed[..NONCE_LEN + TAG_LEN - 1])
+                .is_err());
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use postgres::{Client, NoTls};
 use refinery::{Error, Report};
 use std::io;
+pub mod api_error;
+pub mod crypto;
+pub mod throttle;
+pub mod trace;
 pub mod web_error;
 
 mod embedded {
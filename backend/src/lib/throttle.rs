@@ -0,0 +1,55 @@
+pub mod throttle {
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    // Throttle lets work that is expensive to repeat run at most once per
+    // interval
+    pub struct Throttle {
+        interval: Duration,
+        ran_at: Mutex<Option<Instant>>,
+    }
+
+    impl Throttle {
+        pub fn new(interval: Duration) -> Throttle {
+            Throttle {
+                interval,
+                ran_at: Mutex::new(None),
+            }
+        }
+
+        // ready checks if the interval passed since the work last ran and,
+        // when it did, records that it runs now
+        pub fn ready(&self) -> bool {
+            let mut ran_at = match self.ran_at.lock() {
+                Ok(ran_at) => ran_at,
+                Err(_) => return false,
+            };
+            match *ran_at {
+                Some(at) if at.elapsed() < self.interval => false,
+                _ => {
+                    *ran_at = Some(Instant::now());
+                    true
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn ready_once_per_interval() {
+            let throttle = Throttle::new(Duration::from_secs(60));
+            assert!(throttle.ready());
+            assert!(!throttle.ready());
+        }
+
+        #[test]
+        fn ready_every_time_without_interval() {
+            let throttle = Throttle::new(Duration::from_secs(0));
+            assert!(throttle.ready());
+            assert!(throttle.ready());
+        }
+    }
+}
@@ -0,0 +1,122 @@
+pub mod trace {
+    use crate::lib::crypto::crypto::random_token;
+    use log::Record;
+    use rocket::request::Request;
+    use serde_json::{Map, Value};
+    use std::cell::RefCell;
+    use std::io::Write;
+
+    pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+    const GITHUB_DELIVERY_HEADER: &str = "X-GitHub-Delivery";
+
+    // Headers whose values never end up in the logs
+    const SENSITIVE_HEADERS: [&str; 5] = [
+        "authorization",
+        "cookie",
+        "set-cookie",
+        "x-hub-signature",
+        "x-hub-signature-256",
+    ];
+
+    thread_local! {
+        // REQUEST_ID is the id of the request handled by the current
+        // thread. Rocket handles a request on a single thread, from the
+        // request fairing through the route to the response fairing
+        static REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+    }
+
+    // RequestId identifies a request in responses and logs
+    struct RequestId(String);
+
+    fn is_valid_request_id(id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= 64
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }
+
+    // request_id_for returns the id of the request. An id sent by the
+    // client is kept and webhook deliveries use the GitHub delivery id,
+    // otherwise a new id is generated
+    pub fn request_id_for(req: &Request) -> String {
+        req.local_cache(|| {
+            let incoming = req
+                .headers()
+                .get_one(REQUEST_ID_HEADER)
+                .or_else(|| req.headers().get_one(GITHUB_DELIVERY_HEADER));
+            let id = match incoming {
+                Some(id) if is_valid_request_id(id) => id.to_string(),
+                _ => random_token(8).unwrap_or_default(),
+            };
+            RequestId(id)
+        })
+        .0
+        .clone()
+    }
+
+    pub fn set_request_id(request_id: Option<String>) {
+        REQUEST_ID.with(|current| *current.borrow_mut() = request_id);
+    }
+
+    // request_id returns the id of the request being handled, if any, so
+    // it can be passed on to GitHub and included in logs
+    pub fn request_id() -> Option<String> {
+        REQUEST_ID.with(|current| current.borrow().clone())
+    }
+
+    // redact_header hides the value of credentials. Cookies keep their
+    // names so it is still visible which cookies were sent
+    pub fn redact_header(name: &str, value: &str) -> String {
+        let name = name.to_lowercase();
+        if name == "cookie" {
+            return value
+                .split(';')
+                .map(|cookie| match cookie.split_once('=') {
+                    Some((cookie_name, _)) => format!("{}=[redacted]", cookie_name.trim()),
+                    None => String::from("[redacted]"),
+                })
+                .collect::<Vec<String>>()
+                .join("; ");
+        }
+        if SENSITIVE_HEADERS.contains(&name.as_str()) {
+            return String::from("[redacted]");
+        }
+        value.to_string()
+    }
+
+    fn format_record(buf: &mut env_logger::fmt::Formatter, record: &Record) -> std::io::Result<()> {
+        let mut line = Map::new();
+        line.insert(
+            String::from("ts"),
+            Value::from(chrono::Utc::now().to_rfc3339()),
+        );
+        line.insert(String::from("level"), Value::from(record.level().as_str()));
+        line.insert(String::from("target"), Value::from(record.target()));
+        // Events logged as JSON objects are merged into the line
+        let message = record.args().to_string();
+        let event = if message.starts_with('{') {
+            serde_json::from_str::<Value>(&message).ok()
+        } else {
+            None
+        };
+        match event {
+            Some(Value::Object(fields)) => line.extend(fields),
+            _ => {
+                line.insert(String::from("message"), Value::from(message));
+            }
+        }
+        if let Some(request_id) = request_id() {
+            line.insert(String::from("request_id"), Value::from(request_id));
+        }
+        writeln!(buf, "{}", Value::Object(line))
+    }
+
+    // init_logger logs one JSON object per line carrying the id of the
+    // request the line was logged for
+    pub fn init_logger() {
+        env_logger::Builder::from_default_env()
+            .format(format_record)
+            .init();
+    }
+}
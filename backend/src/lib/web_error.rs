@@ -14,15 +14,19 @@ pub mod WebError {
                 Message: message,
             }
         }
+
+        pub fn status(&self) -> usize {
+            self.Status
+        }
+
+        pub fn message(&self) -> &str {
+            &self.Message
+        }
     }
 
     impl fmt::Display for WebError {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            let err_msg = match self.Status {
-                404 => "404. Not found",
-                _ => "undefined status code ",
-            };
-            write!(f, "{}", err_msg)
+            write!(f, "{}: {}", self.Status, self.Message)
         }
     }
     impl From<io::Error> for WebError {
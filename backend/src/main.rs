@@ -8,316 +8,1485 @@ extern crate rocket;
 
 use envconfig::Envconfig;
 use log::info;
-use reqwest::header::ACCEPT;
 use reqwest::{self, redirect};
 use rithub::api::api;
-use rithub::app::app;
 use rithub::headers::rocket_request_headers;
 use rithub::webhook::webhook;
 use rocket::http::{ContentType, Cookie, Cookies, SameSite, Status};
 use rocket::request::{FromRequest, Outcome, Request};
-use rocket::response::{self, Responder, Response};
-use rocket::State;
+use rocket::response::{status, Content, Response};
+use rocket::{Data, State};
 use serde::{Deserialize, Serialize};
 use std::{borrow::Borrow, io::prelude::*};
+mod api_key;
+mod bounty;
+mod caps;
+mod command;
+mod estimate;
+mod fraud;
+mod github_app;
 mod handlers;
+mod health;
+mod ledger;
+mod policy;
+mod pool;
+mod proposal;
+mod repository;
+mod reward;
 pub mod sdk;
 use openssl::pkey::PKey;
 use rocket_contrib::json::{Json, JsonValue};
 mod lib;
+use github_app::github_app::{GithubApps, WebhookSignature};
+use lib::api_error::api_error::{self, ApiError};
 use lib::web_error::WebError::WebError;
+mod metrics;
 mod middleware;
+mod oauth;
+mod permission;
+mod session;
+mod stats;
 mod user;
+use api_key::api_key::{ApiKey, Principal, SCOPE_REWARDS_READ, SCOPE_REWARDS_WRITE};
+use authorization::jwt_authentication::Jwt;
+use bounty::bounty::Bounty;
+use ledger::ledger::{
+    Payout, PayoutFilter, STATUS_FAILED, STATUS_HELD, STATUS_PENDING, STATUS_UNCLAIMED,
+};
+use lib::crypto::crypto::SecretBox;
+use lib::throttle::throttle::Throttle;
+use oauth::oauth::{
+    user_access_token, AccessTokenResponse, LoginAttempt, LoginError, OAUTH_STATE_COOKIE,
+};
+use permission::permission::Maintainer;
+use pool::pool::DonationIntent;
+use proposal::proposal::Proposal;
+use repository::repository::{FundingMode, Repository};
+use reward::reward::PullRequestReward;
+use rocket::response::Redirect;
 use rocket_cors::{AllowedHeaders, AllowedOrigins};
-use std::fs::File;
+use session::session::{Session, SessionUser, SESSION_COOKIE};
+use stats::stats::{Ranking, Window};
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 extern crate authorization;
-#[derive(Debug)]
-struct ResponseBodyError {
-    status: Status,
-    message: JsonValue,
+#[get("/user")]
+fn get_user(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    session_user: SessionUser,
+) -> Result<Json<api::User>, ApiError> {
+    let mut user = session_user.user;
+    let access_token = match user_access_token(&api.config, &conn, &mut user) {
+        Ok(access_token) => access_token,
+        Err(err) => return Err(ApiError::new(Status::Unauthorized, &err.message())),
+    };
+    let github_client = handlers::github_client(&api, &access_token);
+    let req_client = reqwest::blocking::Client::new();
+    let user = match github_client.user(req_client) {
+        Ok(res) => res,
+        Err(err) => return Err(ApiError::new(Status::Unauthorized, &err.message)),
+    };
+
+    Ok(Json(user))
 }
 
-impl<'a> Responder<'a> for ResponseBodyError {
-    fn respond_to(self, req: &Request) -> response::Result<'a> {
-        Response::build()
-            .header(ContentType::JSON)
-            .status(self.status)
-            .ok()
+#[post("/logout")]
+fn logout<'a>(
+    conn: handlers::MyPgDatabase,
+    session_user: SessionUser,
+    mut cookies: Cookies,
+) -> Result<rocket::Response<'a>, ApiError> {
+    if let Err(err) = Session::revoke(&conn, &session_user.session_id) {
+        log::error!("logout.revoke.fail. Cause: {}", err);
+        return Err(ApiError::new(
+            Status::InternalServerError,
+            "could not revoke session",
+        ));
     }
+    cookies.remove_private(Cookie::build(SESSION_COOKIE, "").path("/v0").finish());
+    Ok(Response::build().status(Status::Ok).finalize())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Oauth {
-    client_id: String,
-    client_secret: String,
-    code: String,
-}
-fn empty_string() -> String {
-    "".to_string()
+#[derive(Deserialize, Serialize, Debug)]
+struct FundingData {
+    mode: String,
+    sponsor_account: Option<String>,
 }
 
-fn empty_usize() -> usize {
-    0
+#[get("/repos/<owner>/<name>/funding")]
+fn get_repository_funding(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    _session_user: SessionUser,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    let repository = match Repository::get(&conn, &full_name) {
+        Ok(repository) => repository,
+        Err(err) => return Err(ApiError::from(err)),
+    };
+    Ok(json!({
+        "mode": repository.funding_mode.as_str(),
+        "sponsor_account": repository.sponsor_account,
+        "delegate": api.sdk.treasury_pubkey().to_string(),
+    }))
 }
 
-#[derive(Debug, Deserialize)]
-struct AccessTokenResponse {
-    #[serde(default = "empty_string")]
-    access_token: String,
-    #[serde(default = "empty_usize")]
-    expires_in: usize,
+// set_repository_funding changes where rewards are paid from. Only
+// maintainers of the repository may change it
+#[put("/repos/<owner>/<name>/funding", data = "<funding_data>")]
+fn set_repository_funding(
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    funding_data: Json<FundingData>,
+    maintainer: Maintainer,
+) -> Result<Json<FundingData>, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let mode = match FundingMode::from_str(&funding_data.mode) {
+        Ok(mode) => mode,
+        Err(err) => return Err(ApiError::new(Status::BadRequest, &err.to_string())),
+    };
+    if let Some(account) = &funding_data.sponsor_account {
+        if let Err(err) = sdk::parse_pubkey(account) {
+            return Err(ApiError::new(Status::BadRequest, &err.message));
+        }
+    }
+    let mut repository = maintainer.repository;
+    let funding_data = funding_data.into_inner();
+    match repository.set_funding(&conn, mode, funding_data.sponsor_account.clone()) {
+        Ok(_) => Ok(Json(funding_data)),
+        Err(err) => Err(ApiError::from(err)),
+    }
 }
 
-fn authenticate_user() -> Result<(), WebError> {
-    Ok(())
+#[derive(Deserialize, Debug)]
+struct ApiKeyData {
+    name: String,
+    scopes: Vec<String>,
 }
 
-#[derive(Debug)]
-struct AuthorzationHeader(String);
+// create_api_key creates a key for the logged in user. The key is only
+// returned here, afterwards just its prefix is known
+#[post("/api_keys", data = "<api_key_data>")]
+fn create_api_key(
+    conn: handlers::MyPgDatabase,
+    api_key_data: Json<ApiKeyData>,
+    session_user: SessionUser,
+) -> Result<JsonValue, ApiError> {
+    let api_key_data = api_key_data.into_inner();
+    let (api_key, key) = match ApiKey::create(
+        &conn,
+        session_user.user.id,
+        &api_key_data.name,
+        api_key_data.scopes,
+    ) {
+        Ok(res) => res,
+        Err(err) => return Err(ApiError::from(err)),
+    };
+    Ok(json!({
+        "id": api_key.id,
+        "name": api_key.name,
+        "prefix": api_key.prefix,
+        "scopes": api_key.scopes,
+        "key": key,
+    }))
+}
 
-impl<'r, 'a> FromRequest<'r, 'a> for AuthorzationHeader {
-    type Error = WebError;
-    fn from_request(req: &'r Request<'a>) -> Outcome<Self, Self::Error> {
-        let authorization_cookie = req.cookies().get_private("authorization");
-        match authorization_cookie {
-            Some(authorization_header) => {
-                Outcome::Success(AuthorzationHeader(authorization_header.value().to_string()))
-            }
-            None => Outcome::Failure((
-                Status::Unauthorized,
-                WebError::new(401, "unauthorized".to_string()),
-            )),
+#[get("/api_keys")]
+fn list_api_keys(
+    conn: handlers::MyPgDatabase,
+    session_user: SessionUser,
+) -> Result<Json<Vec<ApiKey>>, ApiError> {
+    match ApiKey::list_for_user(&conn, session_user.user.id) {
+        Ok(api_keys) => Ok(Json(api_keys)),
+        Err(err) => {
+            log::error!("list_api_keys.fail. Cause: {}", err);
+            Err(ApiError::new(
+                Status::InternalServerError,
+                "could not list api keys",
+            ))
         }
     }
 }
 
-#[get("/user")]
-fn get_user(
+#[delete("/api_keys/<id>")]
+fn revoke_api_key<'a>(
+    conn: handlers::MyPgDatabase,
+    id: i32,
+    session_user: SessionUser,
+) -> Result<rocket::Response<'a>, ApiError> {
+    match ApiKey::revoke(&conn, session_user.user.id, id) {
+        Ok(_) => Ok(Response::build().status(Status::Ok).finalize()),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+// require_scope turns a missing scope into an error response
+fn require_scope(
+    principal: &Principal,
+    scope: &str,
+    repository: Option<&str>,
+) -> Result<(), ApiError> {
+    match principal.require(scope, repository) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct WalletData {
+    address: String,
+}
+
+// link_wallet sets the wallet the rewards of the logged in user are sent
+// to. Unclaimed rewards are sent once the user claims them
+#[put("/me/wallet", data = "<wallet_data>")]
+fn link_wallet(
+    conn: handlers::MyPgDatabase,
+    wallet_data: Json<WalletData>,
+    session_user: SessionUser,
+) -> Result<JsonValue, ApiError> {
+    if let Err(err) = sdk::parse_pubkey(&wallet_data.address) {
+        return Err(ApiError::new(Status::BadRequest, &err.message));
+    }
+    if let Err(err) = session_user
+        .user
+        .add_address_to_user(&conn, &wallet_data.address)
+    {
+        return Err(ApiError::from(err));
+    }
+    Ok(json!({ "address": wallet_data.address }))
+}
+
+#[get("/wallet/balance")]
+fn get_wallet_balance(
     api: State<handlers::Api>,
     conn: handlers::MyPgDatabase,
-    authorization_header: AuthorzationHeader,
-) -> Result<Json<api::User>, ResponseBodyError> {
-    let access_token = authorization_header.0;
-    info!("Authorization header: {:?}", access_token);
-    let github_client = api::Config::new(&access_token);
-    let req_client = reqwest::blocking::Client::new();
-    let user = match github_client.user(req_client) {
-        Ok(res) => res,
+    principal: Principal,
+) -> Result<JsonValue, ApiError> {
+    require_scope(&principal, SCOPE_REWARDS_READ, None)?;
+    let address = match principal.user().get_address_from_username(&conn) {
+        Ok(Some(address)) => address,
+        Ok(None) => return Err(ApiError::new(Status::NotFound, "no wallet linked")),
+        Err(err) => return Err(ApiError::from(err)),
+    };
+    let balance = sdk::parse_pubkey(&address).and_then(|wallet| api.sdk.wallet_balance(&wallet));
+    match balance {
+        Ok(balance) => Ok(json!({
+            "address": address,
+            "amount": balance,
+            "ui_amount": api.sdk.to_ui_amount(balance),
+        })),
         Err(err) => {
-            return Err(ResponseBodyError {
-                status: Status::Unauthorized,
-                message: json!({ "message": format!("{:?}", err) }),
-            })
+            log::error!("get_wallet_balance.fail. Cause: {}", err.message);
+            Err(ApiError::new(
+                Status::InternalServerError,
+                "could not read balance",
+            ))
         }
+    }
+}
+
+// REWARDS_PAGE_SIZE is the number of rewards listed per page unless the
+// client asks for fewer
+const REWARDS_PAGE_SIZE: i64 = 50;
+
+fn reward_json(api: &handlers::Api, payout: &Payout) -> serde_json::Value {
+    json!({
+        "id": payout.id,
+        "repository": payout.repository,
+        "pull_request": payout.pull_request,
+        "status": payout.status,
+        "amount": payout.amount,
+        "ui_amount": api.sdk.to_ui_amount(payout.amount as u64),
+        "recipient": payout.recipient,
+        "signature": payout.signature,
+        "score": payout.score,
+        "hold_reason": payout.hold_reason,
+        "proposal_id": payout.proposal_id,
+        "proposed_amount": payout.proposed_amount,
+        "created_at": payout.created_at,
+    })
+    .0
+}
+
+// rewards_page lists a page of the rewards of the user. The cursor of the
+// next page is the id of the last reward, null on the last page
+fn rewards_page(
+    api: &handlers::Api,
+    conn: &handlers::MyPgDatabase,
+    principal: &Principal,
+    filter: &PayoutFilter,
+    cursor: Option<i32>,
+    limit: Option<i64>,
+) -> Result<JsonValue, ApiError> {
+    let limit = limit.unwrap_or(REWARDS_PAGE_SIZE);
+    if limit < 1 || limit > REWARDS_PAGE_SIZE {
+        return Err(ApiError::bad_request(&format!(
+            "limit must be between 1 and {}",
+            REWARDS_PAGE_SIZE
+        )));
+    }
+    let username = principal.user().user_name().to_string();
+    let mut payouts = match Payout::list_for_user(conn, &username, filter, cursor, limit + 1) {
+        Ok(payouts) => payouts,
+        Err(err) => return Err(ApiError::from(err)),
     };
+    let next_cursor = if payouts.len() as i64 > limit {
+        payouts.truncate(limit as usize);
+        payouts.last().map(|payout| payout.id)
+    } else {
+        None
+    };
+    let items: Vec<serde_json::Value> = payouts
+        .iter()
+        .map(|payout| reward_json(api, payout))
+        .collect();
+    Ok(json!({ "items": items, "next_cursor": next_cursor }))
+}
 
-    Ok(Json(user))
+fn parse_date(name: &str, date: Option<String>) -> Result<Option<String>, ApiError> {
+    match date {
+        Some(date) => match chrono::DateTime::parse_from_rfc3339(&date) {
+            Ok(_) => Ok(Some(date)),
+            Err(_) => Err(ApiError::bad_request(&format!(
+                "{} must be an RFC 3339 date",
+                name
+            ))),
+        },
+        None => Ok(None),
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct TransferData {
-    from: String,
-    to: String,
-    amount: u64,
+// get_my_rewards lists the reward ledger entries of the user, newest
+// first. status takes a comma separated list of statuses
+#[get("/me/rewards?<repository>&<pull_request>&<status>&<from>&<to>&<cursor>&<limit>")]
+fn get_my_rewards(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    principal: Principal,
+    repository: Option<String>,
+    pull_request: Option<i32>,
+    status: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    cursor: Option<i32>,
+    limit: Option<i64>,
+) -> Result<JsonValue, ApiError> {
+    require_scope(&principal, SCOPE_REWARDS_READ, repository.as_deref())?;
+    let filter = PayoutFilter {
+        repository,
+        pull_request,
+        statuses: status
+            .map(|status| {
+                status
+                    .split(',')
+                    .map(|status| status.trim().to_string())
+                    .filter(|status| !status.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        from: parse_date("from", from)?,
+        to: parse_date("to", to)?,
+    };
+    rewards_page(&api, &conn, &principal, &filter, cursor, limit)
 }
-#[post("/github/app/send_token", data = "<transfer_data>")]
-fn github_app_send_tokens(
+
+// get_my_pending_rewards lists the rewards of the user that were not paid
+// out yet, including the ones waiting for a linked wallet
+#[get("/me/rewards/pending?<cursor>&<limit>")]
+fn get_my_pending_rewards(
     api: State<handlers::Api>,
-    transfer_data: Json<TransferData>,
-) -> Result<rocket::Response, ResponseBodyError> {
-    match sdk::transfer_token(&transfer_data.from, &transfer_data.to, transfer_data.amount) {
-        Ok(res) => Ok(Response::build().status(Status::Ok).finalize()),
-        Err(err) => Err(ResponseBodyError {
-            status: Status::InternalServerError,
-            message: json!({ "message": "not able to send tokens" }),
-        }),
+    conn: handlers::MyPgDatabase,
+    principal: Principal,
+    cursor: Option<i32>,
+    limit: Option<i64>,
+) -> Result<JsonValue, ApiError> {
+    require_scope(&principal, SCOPE_REWARDS_READ, None)?;
+    let filter = PayoutFilter {
+        statuses: vec![
+            String::from(STATUS_UNCLAIMED),
+            String::from(STATUS_PENDING),
+            String::from(STATUS_FAILED),
+            String::from(STATUS_HELD),
+        ],
+        ..PayoutFilter::default()
+    };
+    rewards_page(&api, &conn, &principal, &filter, cursor, limit)
+}
+
+// get_my_reward_totals sums the rewards of the user per repository
+#[get("/me/rewards/totals")]
+fn get_my_reward_totals(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    principal: Principal,
+) -> Result<JsonValue, ApiError> {
+    require_scope(&principal, SCOPE_REWARDS_READ, None)?;
+    let totals = match Payout::totals_for_user(&conn, principal.user().user_name()) {
+        Ok(totals) => totals,
+        Err(err) => return Err(ApiError::from(err)),
+    };
+    let repositories: Vec<serde_json::Value> = totals
+        .iter()
+        .map(|total| {
+            json!({
+                "repository": total.repository,
+                "earned": total.earned,
+                "ui_earned": api.sdk.to_ui_amount(total.earned as u64),
+                "pending": total.pending,
+                "ui_pending": api.sdk.to_ui_amount(total.pending as u64),
+            })
+            .0
+        })
+        .collect();
+    Ok(json!({ "repositories": repositories }))
+}
+
+// claim_my_rewards sends the rewards the user earned before linking a
+// wallet to the wallet linked now
+#[post("/me/rewards/claim")]
+fn claim_my_rewards(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    principal: Principal,
+) -> Result<JsonValue, ApiError> {
+    require_scope(&principal, SCOPE_REWARDS_WRITE, None)?;
+    let address = match principal.user().get_address_from_username(&conn) {
+        Ok(Some(address)) => address,
+        Ok(None) => return Err(ApiError::new(Status::NotFound, "no wallet linked")),
+        Err(err) => return Err(ApiError::from(err)),
+    };
+    // API keys limited to some repositories only claim the rewards of those
+    let allows = |repository: &str| {
+        principal
+            .require(SCOPE_REWARDS_WRITE, Some(repository))
+            .is_ok()
+    };
+    let payouts =
+        handlers::claim_rewards(&api, &conn, principal.user().user_name(), &address, &allows)?;
+    let rewards: Vec<serde_json::Value> = payouts
+        .iter()
+        .map(|payout| reward_json(&api, payout))
+        .collect();
+    Ok(json!({ "items": rewards }))
+}
+
+// LEADERBOARD_SIZE is the number of contributors listed per leaderboard
+// unless the client asks for fewer
+const LEADERBOARD_SIZE: i64 = 10;
+
+// contribution_stats builds the leaderboards and pull request statistics
+// of a repository, or of all repositories when repository is None
+fn contribution_stats(
+    api: &handlers::Api,
+    conn: &handlers::MyPgDatabase,
+    repository: Option<&str>,
+    window: Option<String>,
+    rank_by: Option<String>,
+    limit: Option<i64>,
+) -> Result<JsonValue, ApiError> {
+    let window_name = window.unwrap_or_else(|| String::from("30d"));
+    let window = Window::parse(&window_name)?;
+    let ranking = Ranking::parse(rank_by.as_deref().unwrap_or("earned"))?;
+    let limit = limit.unwrap_or(LEADERBOARD_SIZE);
+    if limit < 1 || limit > 100 {
+        return Err(ApiError::bad_request("limit must be between 1 and 100"));
     }
+
+    let mut leaderboards = serde_json::Map::new();
+    for (name, authors) in &[("reviewers", false), ("authors", true)] {
+        let entries =
+            stats::stats::leaderboard(conn, repository, *authors, &ranking, &window, limit)?;
+        let entries: Vec<serde_json::Value> = entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "username": entry.username,
+                    "earned": entry.earned,
+                    "ui_earned": api.sdk.to_ui_amount(entry.earned as u64),
+                    "score": entry.score,
+                    "payouts": entry.payouts,
+                })
+                .0
+            })
+            .collect();
+        leaderboards.insert(name.to_string(), serde_json::Value::from(entries));
+    }
+
+    let pull_requests = stats::stats::pull_request_stats(conn, repository, &window)?;
+    let average_reward = if pull_requests.pull_requests > 0 {
+        pull_requests.reward / pull_requests.pull_requests
+    } else {
+        0
+    };
+    Ok(json!({
+        "repository": repository,
+        "window": window_name,
+        "rank_by": rank_by.unwrap_or_else(|| String::from("earned")),
+        "leaderboards": leaderboards,
+        "pull_requests": {
+            "merged": pull_requests.pull_requests,
+            "average_reward": average_reward,
+            "ui_average_reward": api.sdk.to_ui_amount(average_reward as u64),
+            "median_review_latency_seconds": pull_requests.median_review_latency_seconds,
+        },
+    }))
 }
 
-#[get("/github/app/post/status")]
-fn github_app_post_status(
+// get_stats returns the leaderboards of all repositories. window is all
+// or a number of days like 7d, rank_by is earned or score
+#[get("/stats?<window>&<rank_by>&<limit>")]
+fn get_stats(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    window: Option<String>,
+    rank_by: Option<String>,
+    limit: Option<i64>,
+) -> Result<JsonValue, ApiError> {
+    contribution_stats(&api, &conn, None, window, rank_by, limit)
+}
+
+// get_repository_stats returns the leaderboards of an installed repository
+#[get("/repos/<owner>/<name>/stats?<window>&<rank_by>&<limit>")]
+fn get_repository_stats(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    window: Option<String>,
+    rank_by: Option<String>,
+    limit: Option<i64>,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    if let Err(err) = Repository::get(&conn, &full_name) {
+        return Err(ApiError::from(err));
+    }
+    contribution_stats(&api, &conn, Some(&full_name), window, rank_by, limit)
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct DonationData {
+    amount: f64,
+    memo: Option<String>,
+    // issue turns the donation into a bounty on the issue
+    issue: Option<i32>,
+    bounty_expires_at: Option<String>,
+}
+
+// donation_json describes the donation and how to pay it. payment_url is
+// a Solana Pay transfer request wallets can open directly
+fn donation_json(api: &handlers::Api, intent: &DonationIntent) -> JsonValue {
+    let recipient = api.sdk.treasury_pubkey().to_string();
+    let mint = api.sdk.mint().to_string();
+    let ui_amount = api.sdk.to_ui_amount(intent.amount as u64);
+    let mut payment_url = format!(
+        "solana:{}?amount={}&spl-token={}&reference={}&label={}",
+        recipient,
+        ui_amount,
+        mint,
+        intent.reference,
+        rocket::http::uri::Uri::percent_encode(&intent.repository),
+    );
+    if let Some(memo) = &intent.memo {
+        payment_url.push_str(&format!(
+            "&memo={}",
+            rocket::http::uri::Uri::percent_encode(memo)
+        ));
+    }
+    json!({
+        "id": intent.id,
+        "repository": intent.repository,
+        "amount": intent.amount,
+        "ui_amount": ui_amount,
+        "memo": intent.memo,
+        "donor": intent.donor,
+        "status": intent.status,
+        "reference": intent.reference,
+        "recipient": recipient,
+        "token_account": api.sdk.treasury_token_account().to_string(),
+        "mint": mint,
+        "payment_url": payment_url,
+        "signature": intent.signature,
+        "credited_amount": intent.credited_amount,
+        "issue": intent.issue,
+        "bounty_expires_at": intent.bounty_expires_at,
+    })
+}
+
+// create_donation starts a donation to the pool of an installed
+// repository. Anyone may donate, the donor is recorded when logged in
+#[post("/repos/<owner>/<name>/donations", data = "<donation_data>")]
+fn create_donation(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    donation_data: Json<DonationData>,
+    session_user: Option<SessionUser>,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    if let Err(err) = Repository::get(&conn, &full_name) {
+        return Err(ApiError::from(err));
+    }
+    if !donation_data.amount.is_finite() || donation_data.amount <= 0.0 {
+        return Err(ApiError::bad_request("amount must be positive"));
+    }
+    if donation_data.issue.is_none() && donation_data.bounty_expires_at.is_some() {
+        return Err(ApiError::bad_request(
+            "bounty_expires_at needs an issue to put the bounty on",
+        ));
+    }
+    let mut intent = DonationIntent::new(
+        &full_name,
+        &sdk::new_reference(),
+        api.sdk.to_base_units(donation_data.amount),
+        donation_data.memo.clone(),
+        session_user.map(|session_user| session_user.user.user_name().to_string()),
+        donation_data.issue,
+        parse_date("bounty_expires_at", donation_data.bounty_expires_at.clone())?,
+    );
+    intent.insert(&conn)?;
+    Ok(donation_json(&api, &intent))
+}
+
+// get_donation returns the donation, crediting it when its transfer has
+// arrived
+#[get("/donations/<id>")]
+fn get_donation(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    id: i32,
+) -> Result<JsonValue, ApiError> {
+    let mut intent = DonationIntent::get(&conn, id)?;
+    handlers::credit_donation(&api, &conn, &mut intent)?;
+    Ok(donation_json(&api, &intent))
+}
+
+// get_repository_pool returns the balance of the pool treasury funded
+// payouts of the repository draw from
+#[get("/repos/<owner>/<name>/pool")]
+fn get_repository_pool(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    if let Err(err) = Repository::get(&conn, &full_name) {
+        return Err(ApiError::from(err));
+    }
+    handlers::sync_donations(&api, &conn, &full_name);
+    let balance = pool::pool::balance(&conn, &full_name)?;
+    Ok(json!({
+        "repository": full_name,
+        "balance": balance,
+        "ui_balance": api.sdk.to_ui_amount(balance.max(0) as u64),
+        "recipient": api.sdk.treasury_pubkey().to_string(),
+        "token_account": api.sdk.treasury_token_account().to_string(),
+        "mint": api.sdk.mint().to_string(),
+    }))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct BountyData {
+    amount: f64,
+    expires_at: Option<String>,
+}
+
+fn bounty_json(api: &handlers::Api, bounty: &Bounty) -> serde_json::Value {
+    json!({
+        "id": bounty.id,
+        "repository": bounty.repository,
+        "issue": bounty.issue,
+        "amount": bounty.amount,
+        "ui_amount": api.sdk.to_ui_amount(bounty.amount as u64),
+        "funder": bounty.funder,
+        "status": bounty.status,
+        "expires_at": bounty.expires_at,
+        "pull_request": bounty.pull_request,
+    })
+    .0
+}
+
+// create_bounty escrows a bounty for an issue from the pool of the
+// repository. Only maintainers may spend the pool, donors fund bounties
+// with a donation for the issue
+#[post(
+    "/repos/<owner>/<name>/issues/<issue>/bounties",
+    data = "<bounty_data>"
+)]
+fn create_bounty(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    issue: i32,
+    bounty_data: Json<BountyData>,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    if !bounty_data.amount.is_finite() || bounty_data.amount <= 0.0 {
+        return Err(ApiError::bad_request("amount must be positive"));
+    }
+    let expires_at = parse_date("expires_at", bounty_data.expires_at.clone())?;
+    handlers::sync_donations(&api, &conn, &full_name);
+    let bounty = Bounty::fund(
+        &conn,
+        &full_name,
+        issue,
+        api.sdk.to_base_units(bounty_data.amount) as i64,
+        maintainer.principal.user().user_name(),
+        expires_at.as_deref(),
+    )?;
+    Ok(JsonValue(bounty_json(&api, &bounty)))
+}
+
+// get_bounties lists the bounties of a repository, newest first
+#[get("/repos/<owner>/<name>/bounties?<issue>&<status>")]
+fn get_bounties(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    issue: Option<i32>,
+    status: Option<String>,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    if let Err(err) = Bounty::expire(&conn) {
+        log::error!("bounty.expire.fail. Cause: {}", err);
+    }
+    let bounties = Bounty::list(&conn, &full_name, issue, status)?;
+    let items: Vec<serde_json::Value> = bounties
+        .iter()
+        .map(|bounty| bounty_json(&api, bounty))
+        .collect();
+    Ok(json!({ "items": items }))
+}
+
+// refund_bounty returns an open bounty to the pool of the repository
+#[delete("/repos/<owner>/<name>/bounties/<id>")]
+fn refund_bounty(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    id: i32,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let bounty = Bounty::refund(&conn, &full_name, id)?;
+    Ok(JsonValue(bounty_json(&api, &bounty)))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct RewardData {
+    max_reward: f64,
+}
+
+// set_pull_request_reward overrides the max reward of the repository
+// policy for a single pull request. Only maintainers may change it
+#[put("/repos/<owner>/<name>/pulls/<number>/reward", data = "<reward_data>")]
+fn set_pull_request_reward(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    number: i32,
+    reward_data: Json<RewardData>,
+    maintainer: Maintainer,
+) -> Result<Json<RewardData>, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let reward = PullRequestReward::new(&full_name, number, reward_data.max_reward);
+    match reward.set(
+        &conn,
+        maintainer.principal.user().id,
+        api.config.max_pull_request_reward,
+    ) {
+        Ok(_) => Ok(reward_data),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+// get_pull_request_scores returns how the estimate of a pull request
+// changed with every event, oldest first
+#[get("/repos/<owner>/<name>/pulls/<number>/scores")]
+fn get_pull_request_scores(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    number: i32,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    let history = estimate::estimate::history(&conn, &full_name, number)?;
+    let items: Vec<serde_json::Value> = history
+        .iter()
+        .map(|entry| {
+            json!({
+                "event": entry.event,
+                "username": entry.username,
+                "role": entry.role,
+                "score": entry.score,
+                "reward": entry.reward,
+                "ui_reward": api.sdk.to_ui_amount(entry.reward as u64),
+                "created_at": entry.created_at,
+            })
+            .0
+        })
+        .collect();
+    Ok(json!({ "items": items }))
+}
+
+// retry_payouts sends the failed payouts of the repository again. Manual
+// payouts are approved by maintainers only
+#[post("/repos/<owner>/<name>/payouts/retry")]
+fn retry_payouts(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    match handlers::retry_failed_payouts(&api, &conn, &full_name) {
+        Ok(sent) => Ok(json!({ "sent": sent })),
+        Err(err) => Err(ApiError::from(err)),
+    }
+}
+
+// get_held_payouts lists the payouts of the repository the fraud checks
+// hold for maintainer approval
+#[get("/repos/<owner>/<name>/payouts/held")]
+fn get_held_payouts(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let payouts = Payout::list_by_status(&conn, &full_name, STATUS_HELD)?;
+    let items: Vec<serde_json::Value> = payouts
+        .iter()
+        .map(|payout| reward_json(&api, payout))
+        .collect();
+    Ok(json!({ "items": items }))
+}
+
+// release_payout approves a held payout and sends it
+#[post("/repos/<owner>/<name>/payouts/<id>/release")]
+fn release_payout(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    id: i32,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let payout = handlers::release_held_payout(&api, &conn, &full_name, id)?;
+    Ok(JsonValue(reward_json(&api, &payout)))
+}
+
+// reject_payout rejects a held payout, it is never sent
+#[post("/repos/<owner>/<name>/payouts/<id>/reject")]
+fn reject_payout(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    id: i32,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let mut payout = Payout::get(&conn, id)?;
+    if payout.repository != full_name {
+        return Err(ApiError::new(Status::NotFound, "payout not found"));
+    }
+    payout.reject(&conn)?;
+    Ok(JsonValue(reward_json(&api, &payout)))
+}
+
+fn proposal_json(api: &handlers::Api, proposal: &Proposal, payouts: &[Payout]) -> JsonValue {
+    let items: Vec<serde_json::Value> = payouts
+        .iter()
+        .map(|payout| reward_json(api, payout))
+        .collect();
+    json!({
+        "id": proposal.id,
+        "repository": proposal.repository,
+        "pull_request": proposal.pull_request,
+        "status": proposal.status,
+        "decided_by": proposal.decided_by,
+        "decided_at": proposal.decided_at,
+        "created_at": proposal.created_at,
+        "payouts": items,
+    })
+}
+
+// get_proposals lists the payout proposals of a repository, newest first
+#[get("/repos/<owner>/<name>/proposals?<pull_request>&<status>")]
+fn get_proposals(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    pull_request: Option<i32>,
+    status: Option<String>,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let proposals = Proposal::list(&conn, &full_name, pull_request, status)?;
+    let mut items: Vec<serde_json::Value> = vec![];
+    for proposal in &proposals {
+        let payouts = Payout::list_for_proposal(&conn, proposal.id)?;
+        items.push(proposal_json(&api, proposal, &payouts).0);
+    }
+    Ok(json!({ "items": items }))
+}
+
+#[get("/repos/<owner>/<name>/proposals/<id>")]
+fn get_proposal(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    id: i32,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let proposal = Proposal::get(&conn, id)?;
+    if proposal.repository != full_name {
+        return Err(ApiError::new(Status::NotFound, "payout proposal not found"));
+    }
+    let payouts = Payout::list_for_proposal(&conn, id)?;
+    Ok(proposal_json(&api, &proposal, &payouts))
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct ProposalPayoutData {
+    amount: f64,
+}
+
+// set_proposal_payout edits the amount of a payout of an open proposal.
+// Amounts above the proposed amount are lowered to it
+#[put("/repos/<owner>/<name>/payouts/<id>/amount", data = "<payout_data>")]
+fn set_proposal_payout(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    id: i32,
+    payout_data: Json<ProposalPayoutData>,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    if !payout_data.amount.is_finite() || payout_data.amount < 0.0 {
+        return Err(ApiError::bad_request("amount must not be negative"));
+    }
+    let mut payout = Payout::get(&conn, id)?;
+    if payout.repository != full_name {
+        return Err(ApiError::new(Status::NotFound, "payout not found"));
+    }
+    payout.set_amount(&conn, api.sdk.to_base_units(payout_data.amount) as i64)?;
+    Ok(JsonValue(reward_json(&api, &payout)))
+}
+
+// approve_proposal approves a payout proposal and sends its payouts
+#[post("/repos/<owner>/<name>/proposals/<id>/approve")]
+fn approve_proposal(
     api: State<handlers::Api>,
-) -> Result<rocket::Response, ResponseBodyError> {
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    id: i32,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let (proposal, payouts) = handlers::approve_proposal(
+        &api,
+        &conn,
+        &full_name,
+        id,
+        maintainer.principal.user().user_name(),
+    )?;
+    Ok(proposal_json(&api, &proposal, &payouts))
+}
+
+// reject_proposal rejects a payout proposal, none of its payouts are sent
+#[post("/repos/<owner>/<name>/proposals/<id>/reject")]
+fn reject_proposal(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+    owner: String,
+    name: String,
+    id: i32,
+    maintainer: Maintainer,
+) -> Result<JsonValue, ApiError> {
+    let full_name = format!("{}/{}", owner, name);
+    require_scope(&maintainer.principal, SCOPE_REWARDS_WRITE, Some(&full_name))?;
+    let (proposal, payouts) = handlers::reject_proposal(
+        &conn,
+        &full_name,
+        id,
+        maintainer.principal.user().user_name(),
+    )?;
+    Ok(proposal_json(&api, &proposal, &payouts))
+}
+
+#[get("/github/app/post/status")]
+fn github_app_post_status(api: State<handlers::Api>) -> Result<rocket::Response, ApiError> {
     info!("github_app_post_status");
     let installation_id = "31";
-    let access_token = match api
-        .github_app_client
+    if let Err(err) = api
+        .github_apps
+        .default()
+        .client
         .authenticate_app(installation_id.to_string())
     {
-        Ok(token) => token,
-        Err(err) => {
-            return Err(ResponseBodyError {
-                status: Status::InternalServerError,
-                message: json!({ "message": format!("{}", err) }),
-            })
-        }
-    };
+        return Err(ApiError::new(Status::InternalServerError, &err.to_string()));
+    }
 
-    info!("Access token: {}", access_token);
+    info!("github_app_post_status.authenticated");
     Ok(Response::build().status(Status::Ok).finalize())
 }
+// healthz tells that the process is alive
+#[get("/healthz")]
+fn healthz() -> JsonValue {
+    json!({ "status": "ok" })
+}
+
+// readyz tells if the backend can serve requests. It answers 503 with
+// the failing checks when a dependency is unavailable
+#[get("/readyz")]
+fn readyz(
+    api: State<handlers::Api>,
+    conn: Option<handlers::MyPgDatabase>,
+) -> status::Custom<JsonValue> {
+    let (ready, report) = health::health::readiness(&api, conn.as_deref());
+    let status = if ready {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    status::Custom(status, report)
+}
+
+// GAUGE_REFRESH_SECONDS is how long scrapes serve the last payout queue
+// depth and treasury balance before they are read again
+const GAUGE_REFRESH_SECONDS: u64 = 30;
+
+// metrics exposes the metrics in the Prometheus text format. The queue
+// depth and treasury balance are read when scraped, at most once every
+// GAUGE_REFRESH_SECONDS so scrapes cannot load the database and the RPC
+// node
+#[get("/metrics")]
+fn get_metrics(
+    api: State<handlers::Api>,
+    conn: handlers::MyPgDatabase,
+) -> Result<Content<String>, ApiError> {
+    if api.gauge_refresh.ready() {
+        match Payout::count_by_status(&conn, &[STATUS_PENDING, STATUS_FAILED]) {
+            Ok(depth) => api.metrics.payout_queue_depth.set(depth),
+            Err(err) => log::error!("metrics.queue_depth.fail. Cause: {}", err),
+        }
+        match api.sdk.wallet_balance(&api.sdk.treasury_pubkey()) {
+            Ok(balance) => api
+                .metrics
+                .treasury_balance
+                .set(api.sdk.to_ui_amount(balance)),
+            Err(err) => log::error!("metrics.treasury_balance.fail. Cause: {}", err.message),
+        }
+    }
+    match api.metrics.encode() {
+        Ok(text) => Ok(Content(
+            ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+            text,
+        )),
+        Err(err) => Err(ApiError::internal(&err.to_string())),
+    }
+}
+
+// WEBHOOK_BODY_LIMIT is the largest webhook delivery that is read
+const WEBHOOK_BODY_LIMIT: u64 = 25 * 1024 * 1024;
+
 // Webhook responsible for
 // - If PR, calculate potential cost
-#[post("/github/webhook", data = "<webhook_data>")]
+#[post("/github/webhook", data = "<webhook_body>")]
 fn github_webhook(
     api: State<handlers::Api>,
     db: handlers::MyPgDatabase,
-    webhook_data: Json<webhook::WebhookRequest>,
+    webhook_body: Data,
+    signature: WebhookSignature,
     github_headers: rocket_request_headers::GithubWebhookHeaders,
-) {
+) -> Result<(), ApiError> {
+    let mut body = Vec::new();
+    if let Err(err) = webhook_body
+        .open()
+        .take(WEBHOOK_BODY_LIMIT)
+        .read_to_end(&mut body)
+    {
+        return Err(ApiError::bad_request(&err.to_string()));
+    }
+    // The app whose webhook secret signed the delivery is the app the
+    // delivery was sent for
+    let github_app = match signature
+        .0
+        .and_then(|signature| api.github_apps.verify_webhook(&body, &signature))
+    {
+        Some(github_app) => github_app,
+        None => {
+            info!("github_webhook.signature.invalid");
+            return Err(ApiError::unauthorized("invalid webhook signature"));
+        }
+    };
+    let data: webhook::WebhookRequest = match serde_json::from_slice(&body) {
+        Ok(data) => data,
+        Err(err) => return Err(ApiError::new(Status::UnprocessableEntity, &err.to_string())),
+    };
     info!(
-        "github_webhook.request: {}. Body: {:?}",
-        "github webhook request", webhook_data
+        "{}",
+        json!({
+            "event": "github_webhook.request",
+            "app_id": github_app.id,
+            "action": data.action,
+            "repository": data.repository.full_name,
+            "pull_request": data.pull_request.number,
+        })
     );
-    let data = webhook_data.into_inner();
+    let repository = Repository::new(
+        &data.repository.full_name,
+        &github_app.id,
+        data.installation.id,
+    );
+    // A repository several configured apps are installed on is only
+    // handled by the app it was bound to, so nothing is paid twice
+    let app_ids: Vec<&str> = api.github_apps.iter().map(|app| app.id.as_str()).collect();
+    match repository.create_or_update_installation(&db, &app_ids) {
+        Ok(true) => (),
+        Ok(false) => {
+            info!("github_webhook.repository.other_app");
+            return Ok(());
+        }
+        Err(err) => {
+            log::error!("github_webhook.repository.fail. Cause: {}", err);
+            return Err(ApiError::from(err));
+        }
+    }
     // Created pull request
+    let event = github_headers.event.clone();
     let request_type = webhook::WebhookRequest::get_webhook_type(github_headers, &data);
     info!("github.webhook.request_type {:?} ", request_type);
-    match request_type {
+    if let webhook::WebhookType::Review | webhook::WebhookType::Approved = request_type {
+        if data.review.user.login != data.pull_request.user.login {
+            if let Err(err) = stats::stats::record_review(
+                &db,
+                &data.repository.full_name,
+                data.pull_request.number as i32,
+                &data.pull_request.user.login,
+                &data.pull_request.created_at,
+                &data.review.submitted_at,
+            ) {
+                log::error!("stats.record_review.fail. Cause: {}", err);
+            }
+        }
+    }
+    let started_at = Instant::now();
+    let (handler, result) = match request_type {
         webhook::WebhookType::Open => {
             info!("github.webhook.pull_request.open");
-            match handlers::pull_request(&data, api.inner()) {
-                Ok(_) => info!("github.webhook.pull_request.success"),
-                Err(_) => info!("github.webhook.pull_request.fail"),
-            }
+            (
+                "pull_request",
+                handlers::pull_request(&data, api.inner(), &github_app.client, &db),
+            )
         }
         webhook::WebhookType::Review => {
             info!("github.webhook.pull_request_review.review");
-            match handlers::pull_request_review(&data, api.inner()) {
-                Ok(_) => info!("github.webhook.pull_request_review.success"),
-                Err(_) => info!("github.webhook.pull_request_review.fail"),
-            }
+            (
+                "pull_request_review",
+                handlers::pull_request_review(&data, api.inner(), &github_app.client, &db),
+            )
         }
-        webhook::WebhookType::Approved => {
-            info!("github.webhook.pull_request_review");
+        webhook::WebhookType::ReviewDismissed => {
+            info!("github.webhook.pull_request_review.dismissed");
+            (
+                "pull_request_review_dismissed",
+                handlers::pull_request_review(&data, api.inner(), &github_app.client, &db),
+            )
         }
         webhook::WebhookType::Merged => {
             info!("github.webhook.pull_request_review.merged");
-            match handlers::merge_pull_request(&data, api.inner(), &db) {
-                Ok(_) => info!("github.webhook.pull_request.success"),
-                Err(_) => info!("github.webhook.pull_request.fail"),
-            }
+            (
+                "merge_pull_request",
+                handlers::merge_pull_request(&data, api.inner(), &github_app.client, &db),
+            )
         }
-        webhook::WebhookType::Closed => return,
-        webhook::WebhookType::Unknown => return,
-    }
+        webhook::WebhookType::IssueComment => {
+            info!("github.webhook.issue_comment");
+            (
+                "issue_comment",
+                handlers::issue_comment(&data, api.inner(), &github_app.client, &db),
+            )
+        }
+        webhook::WebhookType::Approved => {
+            info!("github.webhook.pull_request_review.approved");
+            (
+                "approved_pull_request",
+                handlers::approved_pull_request(&data, api.inner(), &github_app.client, &db),
+            )
+        }
+        webhook::WebhookType::Closed | webhook::WebhookType::Unknown => {
+            api.metrics
+                .webhook_deliveries
+                .with_label_values(&[&event, "ignored"])
+                .inc();
+            return Ok(());
+        }
+    };
+    api.metrics
+        .handler_duration
+        .with_label_values(&[handler])
+        .observe(started_at.elapsed().as_secs_f64());
+    let outcome = match result {
+        Ok(_) => {
+            info!("github.webhook.{}.success", handler);
+            "success"
+        }
+        Err(err) => {
+            info!("github.webhook.{}.fail. Cause: {}", handler, err.message);
+            "fail"
+        }
+    };
+    api.metrics
+        .webhook_deliveries
+        .with_label_values(&[&event, outcome])
+        .inc();
 
     info!("github_webhook.finished");
+    Ok(())
 }
 
-#[get("/github/login?<code>")]
+// verify_login checks the state and PKCE verifier of the login attempt
+// before the code is exchanged for an access token
+fn verify_login(
+    api: &handlers::Api,
+    login_attempt: Option<LoginAttempt>,
+    code: Option<String>,
+    state: Option<String>,
+) -> Result<AccessTokenResponse, LoginError> {
+    let code = code.ok_or(LoginError::MissingCode)?;
+    let state = state.ok_or(LoginError::MissingState)?;
+    let login_attempt = login_attempt.ok_or(LoginError::NotStarted)?;
+    login_attempt.verify(&state)?;
+    login_attempt.exchange_code(
+        &api.config.oauth_client_id,
+        &api.config.oauth_client_secret,
+        &code,
+    )
+}
+
+// github_login_start starts the OAuth flow. The state and PKCE verifier
+// are kept in a short lived private cookie and checked on the callback
+#[get("/github/login/start")]
+fn github_login_start(
+    api: State<handlers::Api>,
+    mut cookies: Cookies,
+) -> Result<Redirect, ApiError> {
+    let login_attempt = match LoginAttempt::new() {
+        Ok(login_attempt) => login_attempt,
+        Err(err) => {
+            log::error!("github_login_start.fail. Cause: {}", err);
+            return Err(ApiError::new(
+                Status::InternalServerError,
+                "could not start login",
+            ));
+        }
+    };
+    let authorize_url = match login_attempt
+        .authorize_url(&api.config.oauth_client_id, &api.config.oauth_redirect_url)
+    {
+        Ok(url) => url,
+        Err(err) => {
+            log::error!("github_login_start.fail. Cause: {}", err);
+            return Err(ApiError::new(
+                Status::InternalServerError,
+                "could not start login",
+            ));
+        }
+    };
+    let state_cookie = Cookie::build(OAUTH_STATE_COOKIE, login_attempt.to_cookie_value())
+        .same_site(SameSite::None)
+        .path("/v0/github")
+        .secure(true)
+        .http_only(true)
+        .finish();
+    cookies.add_private(state_cookie);
+    Ok(Redirect::to(authorize_url))
+}
+
+#[get("/github/login?<code>&<state>")]
 fn github_login<'a>(
     api: State<handlers::Api>,
     code: Option<String>,
+    state: Option<String>,
     conn: handlers::MyPgDatabase,
     mut cookies: Cookies,
-) -> Result<rocket::Response<'a>, ResponseBodyError> {
+) -> Result<rocket::Response<'a>, ApiError> {
     info!("github_login");
-    let _gcode = match code {
-        Some(res) => res,
-        None => {
-            return Err(ResponseBodyError {
-                status: Status::Unauthorized,
-                message: json!({"message":"unautorized"}),
-            })
+    let login_attempt = cookies
+        .get_private(OAUTH_STATE_COOKIE)
+        .and_then(|cookie| LoginAttempt::from_cookie_value(cookie.value()));
+    // A login attempt can only be used once
+    cookies.remove_private(
+        Cookie::build(OAUTH_STATE_COOKIE, "")
+            .path("/v0/github")
+            .finish(),
+    );
+
+    let access_token = match verify_login(&api, login_attempt, code, state) {
+        Ok(access_token) => access_token,
+        Err(err) => {
+            info!("github_login.fail. Cause: {:?}", err);
+            return Err(ApiError::new(Status::Unauthorized, &err.message()));
         }
     };
-    info!("code: {}", _gcode);
-    let github_post = Oauth {
-        client_id: api.config.oauth_client_id.clone(),
-        client_secret: api.config.oauth_client_secret.clone(),
-        code: _gcode.clone(),
-    };
+
     let req_client = reqwest::blocking::Client::new();
-    let res = match req_client
-        .post("https://github.com/login/oauth/access_token")
-        .header(ACCEPT, "application/json")
-        .json(&github_post)
-        .send()
-    {
-        Ok(res) => res,
+    let gh = handlers::github_client(&api, &access_token.access_token);
+    let gh_user = match gh.user(req_client) {
+        Ok(gh_user) => gh_user,
         Err(err) => {
-            info!("error when retrieving access token: {}", err.to_string());
-            return Err(ResponseBodyError {
-                status: Status::Unauthorized,
-                message: json!({"message":"unautorized"}),
-            });
+            info!("github_login.user.fail. Cause: {:?}", err);
+            return Err(ApiError::new(
+                Status::Unauthorized,
+                "could not read github user",
+            ));
         }
     };
+    info!("github user: {:?}", gh_user);
 
-    let resp_code = res.status();
-    info!("response code: {}", resp_code);
-    if resp_code != reqwest::StatusCode::OK {
-        info!("access token response code: {}", resp_code.to_string());
-        return Err(ResponseBodyError {
-            status: Status::Unauthorized,
-            message: json!({"message":"unautorized"}),
-        });
-    }
-    let access_token_response_res = res.json::<AccessTokenResponse>();
-    info!("bytes: {:?}", access_token_response_res);
-    let access_token = match access_token_response_res {
+    let tokens = match access_token.tokens(&SecretBox::new(&api.config.token_encryption_key)) {
         Ok(res) => res,
         Err(err) => {
-            return Err(ResponseBodyError {
-                status: Status::Unauthorized,
-                message: json!({"message":"unautorized"}),
-            })
+            log::error!("github_login.encrypt.fail. Cause: {}", err);
+            return Err(ApiError::new(
+                Status::InternalServerError,
+                "could not store access token",
+            ));
         }
     };
-
-    //test request
-    let gh = api::Config::new(&access_token.access_token);
-    let gh_user = gh.user(req_client).unwrap();
-    info!("github user: {:?}", gh_user);
-
-    let user = user::user::User::new(&gh_user.login, &gh_user.name);
-    let create_res = user.create_user_if_not_exist(&conn, &access_token.access_token);
-    match create_res {
-        Ok(_) => (),
+    let mut user = user::user::User::new(&gh_user.login, &gh_user.name);
+    let create_res = user.create_user_if_not_exist(&conn, &tokens.access_token);
+    let user_id = match create_res {
+        Ok(user_id) => user_id,
         Err(err) => {
             log::info!("{}", err);
-            return Err(ResponseBodyError {
-                status: Status::Unauthorized,
-                message: json!({"message":"unautorized"}),
-            });
+            return Err(ApiError::new(
+                Status::InternalServerError,
+                "could not create user",
+            ));
+        }
+    };
+    if let Err(err) = user.update_tokens(&conn, tokens) {
+        log::error!("github_login.tokens.fail. Cause: {}", err);
+        return Err(ApiError::new(
+            Status::InternalServerError,
+            "could not store access token",
+        ));
+    }
+
+    let session_ttl = api.config.session_ttl_seconds;
+    let session = match Session::create(&conn, user_id, session_ttl as i64) {
+        Ok(session) => session,
+        Err(err) => {
+            log::error!("github_login.session.fail. Cause: {}", err);
+            return Err(ApiError::new(
+                Status::InternalServerError,
+                "could not create session",
+            ));
         }
     };
-    let auth_cookie = Cookie::build("authorization", access_token.access_token)
+    let jwt = match Jwt::new(&api.config.shared_key).new_token(
+        &user_id.to_string(),
+        &session.id,
+        Duration::new(session_ttl, 0),
+    ) {
+        Ok(jwt) => jwt,
+        Err(err) => {
+            log::error!("github_login.jwt.fail. Cause: {}", err);
+            return Err(ApiError::new(
+                Status::InternalServerError,
+                "could not create session",
+            ));
+        }
+    };
+    let session_cookie = Cookie::build(SESSION_COOKIE, jwt)
         .same_site(SameSite::None)
         .path("/v0")
         .secure(true)
         .http_only(true)
         .finish();
-    cookies.add_private(auth_cookie);
+    cookies.add_private(session_cookie);
     Ok(Response::build().status(Status::Ok).finalize())
 }
 
-fn main() {
-    env_logger::init();
-    log::info!("[root]");
-    let cfg = handlers::Config::init_from_env().unwrap();
+// build reads the configuration and connects to the dependencies of the
+// backend. Every failure is reported with what needs fixing
+fn build() -> Result<rocket::Rocket, String> {
+    let cfg = match handlers::Config::init_from_env() {
+        Ok(cfg) => cfg,
+        Err(err) => return Err(format!("invalid configuration: {}", err)),
+    };
 
-    let migration_result = lib::migrate(&cfg.database_url);
-    match migration_result {
-        Ok(_) => (),
-        Err(err) => return log::error!("{}", err),
+    if let Err(err) = lib::migrate(&cfg.database_url) {
+        return Err(format!("could not migrate the database: {}", err));
     }
 
-    let mut pem_file = File::open(&cfg.cert_pem_path).unwrap();
-    let mut buffer = Vec::new();
-    match pem_file.read_to_end(&mut buffer) {
-        Ok(_) => (),
-        Err(err) => return log::error!("{}", err),
-    }
-    let buffer: Vec<u8> = buffer;
-    let github_app_client = app::Config::new("114926".to_string(), buffer);
+    let github_apps = match GithubApps::load(&cfg) {
+        Ok(github_apps) => github_apps,
+        Err(err) => return Err(err),
+    };
+    let metrics = match metrics::metrics::Metrics::new() {
+        Ok(metrics) => metrics,
+        Err(err) => return Err(format!("could not register metrics: {}", err)),
+    };
+    let sdk = match sdk::Sdk::new(&cfg, metrics.solana_transactions.clone()) {
+        Ok(sdk) => sdk,
+        Err(err) => return Err(format!("could not set up solana: {}", err.message)),
+    };
+    let stats_changed = Arc::new(AtomicBool::new(true));
+    stats::stats::refresh_in_background(cfg.database_url.clone(), stats_changed.clone());
+    let role_cache_ttl = cfg.role_cache_ttl_seconds;
     let api = handlers::Api {
         config: cfg,
-        github_app_client: github_app_client,
+        github_apps: github_apps,
+        sdk: sdk,
+        policy_cache: policy::policy::PolicyCache::new(),
+        role_cache: permission::permission::RoleCache::new(role_cache_ttl),
+        metrics: metrics,
+        gauge_refresh: Throttle::new(Duration::from_secs(GAUGE_REFRESH_SECONDS)),
+        stats_changed,
     };
 
-    let cors = rocket_cors::CorsOptions {
+    let cors = match (rocket_cors::CorsOptions {
         allowed_origins: AllowedOrigins::some_exact(&["http://localhost:5000"]),
         allowed_headers: AllowedHeaders::some(&[
             "Authorization",
@@ -327,22 +1496,80 @@ fn main() {
         ]),
         allow_credentials: true,
         ..Default::default()
-    }
+    })
     .to_cors()
-    .expect("error creating CORS fairing");
-    rocket::ignite()
+    {
+        Ok(cors) => cors,
+        Err(err) => return Err(format!("could not create the CORS fairing: {}", err)),
+    };
+    Ok(rocket::ignite()
         .manage(api)
         .attach(cors)
         .attach(handlers::MyPgDatabase::fairing())
         .attach(middleware::middleware::Middleware::new())
+        .register(catchers![
+            api_error::bad_request,
+            api_error::unauthorized,
+            api_error::forbidden,
+            api_error::not_found,
+            api_error::unprocessable_entity,
+            api_error::internal_error,
+        ])
+        .mount("/", routes![get_metrics, healthz, readyz])
         .mount(
             "/v0",
             routes![
+                github_login_start,
                 github_login,
                 get_user,
+                logout,
                 github_app_post_status,
                 github_webhook,
+                get_repository_funding,
+                set_repository_funding,
+                create_api_key,
+                list_api_keys,
+                revoke_api_key,
+                link_wallet,
+                get_wallet_balance,
+                get_my_rewards,
+                get_my_pending_rewards,
+                get_my_reward_totals,
+                claim_my_rewards,
+                get_stats,
+                get_repository_stats,
+                create_donation,
+                get_donation,
+                get_repository_pool,
+                create_bounty,
+                get_bounties,
+                refund_bounty,
+                set_pull_request_reward,
+                get_pull_request_scores,
+                retry_payouts,
+                get_held_payouts,
+                release_payout,
+                reject_payout,
+                get_proposals,
+                get_proposal,
+                set_proposal_payout,
+                approve_proposal,
+                reject_proposal,
             ],
-        )
-        .launch();
+        ))
+}
+
+fn main() {
+    lib::trace::trace::init_logger();
+    log::info!("[root]");
+    let rocket = match build() {
+        Ok(rocket) => rocket,
+        Err(err) => {
+            log::error!("startup.fail. Cause: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let err = rocket.launch();
+    log::error!("startup.launch.fail. Cause: {}", err);
+    std::process::exit(1);
 }
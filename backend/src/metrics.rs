@@ -0,0 +1,116 @@
+pub mod metrics {
+    use prometheus::{
+        Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+        TextEncoder,
+    };
+    use rithub::api::api::{CallInfo, CallObserver};
+    use std::sync::Arc;
+
+    // Metrics are the Prometheus metrics of the backend. The metric
+    // handles are cheap to clone and share their values
+    #[derive(Clone)]
+    pub struct Metrics {
+        registry: Registry,
+        pub webhook_deliveries: IntCounterVec,
+        pub handler_duration: HistogramVec,
+        pub github_calls: IntCounterVec,
+        pub github_rate_limit_remaining: IntGauge,
+        pub solana_transactions: IntCounterVec,
+        pub payouts: IntCounterVec,
+        pub oct_paid_out: IntCounterVec,
+        pub payout_queue_depth: IntGauge,
+        pub treasury_balance: Gauge,
+    }
+
+    fn counter(
+        name: &str,
+        help: &str,
+        labels: &[&str],
+    ) -> Result<IntCounterVec, prometheus::Error> {
+        IntCounterVec::new(Opts::new(name, help), labels)
+    }
+
+    impl Metrics {
+        pub fn new() -> Result<Metrics, prometheus::Error> {
+            let metrics = Metrics {
+                registry: Registry::new_custom(Some(String::from("oct")), None)?,
+                webhook_deliveries: counter(
+                    "webhook_deliveries_total",
+                    "GitHub webhook deliveries by event and outcome",
+                    &["event", "outcome"],
+                )?,
+                handler_duration: HistogramVec::new(
+                    HistogramOpts::new(
+                        "handler_duration_seconds",
+                        "Time spent handling webhook events",
+                    )
+                    .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+                    &["handler"],
+                )?,
+                github_calls: counter(
+                    "github_api_calls_total",
+                    "Calls to the GitHub API by endpoint and status",
+                    &["endpoint", "status"],
+                )?,
+                github_rate_limit_remaining: IntGauge::new(
+                    "github_rate_limit_remaining",
+                    "GitHub API calls left in the rate limit window of the last call",
+                )?,
+                solana_transactions: counter(
+                    "solana_transactions_total",
+                    "Solana transactions sent by outcome",
+                    &["outcome"],
+                )?,
+                payouts: counter("payouts_total", "Payouts by status", &["status"])?,
+                oct_paid_out: counter(
+                    "paid_out_base_units_total",
+                    "OCT paid out in base units of the mint",
+                    &["repository"],
+                )?,
+                payout_queue_depth: IntGauge::new(
+                    "payout_queue_depth",
+                    "Payouts waiting to be sent or retried",
+                )?,
+                treasury_balance: Gauge::new(
+                    "treasury_balance",
+                    "OCT held by the treasury token account",
+                )?,
+            };
+            let registry = &metrics.registry;
+            registry.register(Box::new(metrics.webhook_deliveries.clone()))?;
+            registry.register(Box::new(metrics.handler_duration.clone()))?;
+            registry.register(Box::new(metrics.github_calls.clone()))?;
+            registry.register(Box::new(metrics.github_rate_limit_remaining.clone()))?;
+            registry.register(Box::new(metrics.solana_transactions.clone()))?;
+            registry.register(Box::new(metrics.payouts.clone()))?;
+            registry.register(Box::new(metrics.oct_paid_out.clone()))?;
+            registry.register(Box::new(metrics.payout_queue_depth.clone()))?;
+            registry.register(Box::new(metrics.treasury_balance.clone()))?;
+            Ok(metrics)
+        }
+
+        // github_observer records the calls made by a GitHub client
+        pub fn github_observer(&self) -> CallObserver {
+            let github_calls = self.github_calls.clone();
+            let rate_limit_remaining = self.github_rate_limit_remaining.clone();
+            CallObserver(Arc::new(move |call: &CallInfo| {
+                github_calls
+                    .with_label_values(&[call.endpoint, &call.status.to_string()])
+                    .inc();
+                if let Some(remaining) = call.rate_limit_remaining {
+                    rate_limit_remaining.set(remaining);
+                }
+            }))
+        }
+
+        // encode renders all metrics in the Prometheus text format
+        pub fn encode(&self) -> Result<String, prometheus::Error> {
+            let mut buffer = vec![];
+            TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+            match String::from_utf8(buffer) {
+                Ok(text) => Ok(text),
+                Err(err) => Err(prometheus::Error::Msg(err.to_string())),
+            }
+        }
+    }
+}
@@ -1,32 +1,62 @@
 pub mod middleware {
-    extern crate authorization;
+    use crate::lib::trace::trace::{
+        redact_header, request_id_for, set_request_id, REQUEST_ID_HEADER,
+    };
     use rocket::fairing::{Fairing, Info, Kind};
-    use rocket::http::hyper::header::Authorization;
     use rocket::{Data, Request, Response};
+    use std::time::Instant;
+
+    // RequestStart is when the request reached the backend
+    struct RequestStart(Instant);
+
+    // Middleware assigns every request an id, returns it in the
+    // X-Request-Id header and logs the request once it is answered
     pub struct Middleware {}
 
     impl Fairing for Middleware {
         fn info(&self) -> Info {
             Info {
-                name: "GET/POST Counter",
+                name: "Request tracing",
                 kind: Kind::Request | Kind::Response,
             }
         }
-        fn on_request(&self, request: &mut Request, data: &Data) {
-            log::info!("Request ");
-            log::info!("uri: {}:", request.uri().path());
-            log::info!("format {:?}", request.format());
-            if data.peek_complete() {
-                log::info!("all of the data: {:?}", data.peek());
+
+        fn on_request(&self, request: &mut Request, _data: &Data) {
+            request.local_cache(|| RequestStart(Instant::now()));
+            set_request_id(Some(request_id_for(request)));
+            if log::log_enabled!(log::Level::Debug) {
+                let headers: serde_json::Map<String, serde_json::Value> = request
+                    .headers()
+                    .iter()
+                    .map(|header| {
+                        (
+                            header.name().to_string(),
+                            serde_json::Value::from(redact_header(header.name(), header.value())),
+                        )
+                    })
+                    .collect();
+                log::debug!(
+                    "{}",
+                    json!({ "event": "http.request.headers", "headers": headers })
+                );
             }
-            request
-                .headers()
-                .get("Authorization")
-                .for_each(|x| println!("ok {}", x));
         }
+
         fn on_response(&self, request: &Request, response: &mut Response) {
-            log::info!("Response ");
-            log::info!("uri: {}:", request.uri().path());
+            let request_id = request_id_for(request);
+            let started_at = request.local_cache(|| RequestStart(Instant::now()));
+            response.set_raw_header(REQUEST_ID_HEADER, request_id);
+            log::info!(
+                "{}",
+                json!({
+                    "event": "http.request",
+                    "method": request.method().as_str(),
+                    "path": request.uri().path(),
+                    "status": response.status().code,
+                    "latency_ms": started_at.0.elapsed().as_secs_f64() * 1000.0,
+                })
+            );
+            set_request_id(None);
         }
     }
 
@@ -0,0 +1,20 @@
+CREATE TABLE bounty
+(
+    ID Serial PRIMARY KEY,
+    Repository VARCHAR(512) NOT NULL,
+    Issue INTEGER NOT NULL,
+    Amount BIGINT NOT NULL,
+    Funder VARCHAR(256) NOT NULL,
+    Status VARCHAR(32) NOT NULL,
+    ExpiresAt TIMESTAMPTZ,
+    PullRequest INTEGER,
+    CreatedAt TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ClosedAt TIMESTAMPTZ
+);
+
+CREATE INDEX bounty_issue_idx ON bounty (Repository, Issue, Status);
+
+ALTER TABLE payout ADD COLUMN BountyID INTEGER REFERENCES bounty (ID);
+
+ALTER TABLE donation_intent ADD COLUMN Issue INTEGER;
+ALTER TABLE donation_intent ADD COLUMN BountyExpiresAt TIMESTAMPTZ;
@@ -0,0 +1,22 @@
+CREATE TABLE pull_request_score
+(
+    ID Serial PRIMARY KEY,
+    Repository VARCHAR(512) NOT NULL,
+    PullRequest INTEGER NOT NULL,
+    Event VARCHAR(64) NOT NULL,
+    Username VARCHAR(256) NOT NULL,
+    Role VARCHAR(16) NOT NULL,
+    Score DOUBLE PRECISION NOT NULL,
+    Reward BIGINT NOT NULL,
+    CreatedAt TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX pull_request_score_idx ON pull_request_score (Repository, PullRequest);
+
+CREATE TABLE pull_request_comment
+(
+    Repository VARCHAR(512) NOT NULL,
+    PullRequest INTEGER NOT NULL,
+    CommentID BIGINT NOT NULL,
+    PRIMARY KEY (Repository, PullRequest)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE payout ADD COLUMN HoldReason VARCHAR(512);
+
+CREATE INDEX payout_reviews_idx ON payout (Username, Author);
@@ -0,0 +1,15 @@
+CREATE TABLE payout_proposal
+(
+    ID Serial PRIMARY KEY,
+    Repository VARCHAR(512) NOT NULL,
+    PullRequest INTEGER NOT NULL,
+    Status VARCHAR(16) NOT NULL,
+    DecidedBy VARCHAR(256),
+    DecidedAt TIMESTAMPTZ,
+    CreatedAt TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX payout_proposal_repository_idx ON payout_proposal (Repository, PullRequest);
+
+ALTER TABLE payout ADD COLUMN ProposalID INTEGER REFERENCES payout_proposal (ID);
+ALTER TABLE payout ADD COLUMN ProposedAmount BIGINT;
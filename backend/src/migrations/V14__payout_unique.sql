@@ -0,0 +1,3 @@
+-- A user is rewarded once per merged pull request and once per bounty, so
+-- a redelivered merge webhook cannot pay anyone twice
+CREATE UNIQUE INDEX payout_reward_idx ON payout (Repository, PullRequest, Username, COALESCE(BountyID, 0));
@@ -0,0 +1 @@
+ALTER TABLE payout ADD COLUMN FundingMode VARCHAR(16);
@@ -0,0 +1,17 @@
+CREATE TABLE payout
+(
+    ID Serial PRIMARY KEY,
+    Repository VARCHAR(512) NOT NULL,
+    PullRequest INTEGER NOT NULL,
+    Username VARCHAR(256) NOT NULL,
+    Recipient VARCHAR(64) NOT NULL,
+    TokenAccount VARCHAR(64) NOT NULL,
+    Amount BIGINT NOT NULL,
+    RentLamports BIGINT NOT NULL DEFAULT 0,
+    Signature VARCHAR(128),
+    Status VARCHAR(32) NOT NULL,
+    CreatedAt TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX payout_repository_idx ON payout (Repository, PullRequest);
+CREATE INDEX payout_username_idx ON payout (Username);
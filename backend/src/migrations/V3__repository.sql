@@ -0,0 +1,8 @@
+CREATE TABLE repository
+(
+    ID Serial PRIMARY KEY,
+    FullName VARCHAR(512) NOT NULL UNIQUE,
+    InstallationID BIGINT NOT NULL,
+    FundingMode VARCHAR(32) NOT NULL DEFAULT 'treasury',
+    SponsorAccount VARCHAR(64)
+);
@@ -0,0 +1,13 @@
+ALTER TABLE github_user ADD PRIMARY KEY (ID);
+ALTER TABLE github_user ADD COLUMN AccessToken BYTEA;
+
+CREATE TABLE session
+(
+    ID VARCHAR(64) PRIMARY KEY,
+    UserID INTEGER NOT NULL REFERENCES github_user (ID),
+    CreatedAt TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ExpiresAt TIMESTAMPTZ NOT NULL,
+    RevokedAt TIMESTAMPTZ
+);
+
+CREATE INDEX session_user_idx ON session (UserID);
@@ -0,0 +1,3 @@
+ALTER TABLE github_user ADD COLUMN AccessTokenExpiresAt TIMESTAMPTZ;
+ALTER TABLE github_user ADD COLUMN RefreshToken BYTEA;
+ALTER TABLE github_user ADD COLUMN RefreshTokenExpiresAt TIMESTAMPTZ;
@@ -0,0 +1,26 @@
+CREATE TABLE api_key
+(
+    ID Serial PRIMARY KEY,
+    UserID INTEGER NOT NULL REFERENCES github_user (ID),
+    Name VARCHAR(256) NOT NULL,
+    Prefix VARCHAR(16) NOT NULL,
+    KeyHash VARCHAR(64) NOT NULL UNIQUE,
+    Scopes TEXT[] NOT NULL,
+    CreatedAt TIMESTAMPTZ NOT NULL DEFAULT now(),
+    LastUsedAt TIMESTAMPTZ,
+    RevokedAt TIMESTAMPTZ
+);
+
+CREATE INDEX api_key_user_idx ON api_key (UserID);
+
+CREATE TABLE pull_request_reward
+(
+    Repository VARCHAR(512) NOT NULL,
+    PullRequest INTEGER NOT NULL,
+    MaxReward DOUBLE PRECISION NOT NULL,
+    SetBy INTEGER NOT NULL REFERENCES github_user (ID),
+    UpdatedAt TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (Repository, PullRequest)
+);
+
+ALTER TABLE payout ADD COLUMN Author VARCHAR(256) NOT NULL DEFAULT '';
@@ -0,0 +1 @@
+ALTER TABLE repository ADD COLUMN AppID VARCHAR(64) NOT NULL DEFAULT '';
@@ -0,0 +1,38 @@
+ALTER TABLE payout ADD COLUMN Score DOUBLE PRECISION NOT NULL DEFAULT 0;
+
+CREATE TABLE pull_request_stat
+(
+    Repository VARCHAR(512) NOT NULL,
+    PullRequest INTEGER NOT NULL,
+    Author VARCHAR(256) NOT NULL,
+    OpenedAt TIMESTAMPTZ NOT NULL,
+    FirstReviewAt TIMESTAMPTZ,
+    MergedAt TIMESTAMPTZ,
+    Reward BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (Repository, PullRequest)
+);
+
+CREATE MATERIALIZED VIEW contributor_daily_stat AS
+SELECT Repository,
+    Username,
+    Username = Author AS IsAuthor,
+    CAST(date_trunc('day', CreatedAt) AS DATE) AS Day,
+    CAST(COALESCE(SUM(Amount) FILTER (WHERE Status = 'sent'), 0) AS BIGINT) AS Earned,
+    SUM(Score) AS Score,
+    COUNT(*) AS Payouts
+FROM payout
+GROUP BY Repository, Username, IsAuthor, Day;
+
+CREATE UNIQUE INDEX contributor_daily_stat_idx ON contributor_daily_stat (Repository, Username, IsAuthor, Day);
+
+CREATE MATERIALIZED VIEW pull_request_daily_stat AS
+SELECT Repository,
+    CAST(date_trunc('day', MergedAt) AS DATE) AS Day,
+    COUNT(*) AS PullRequests,
+    CAST(SUM(Reward) AS BIGINT) AS Reward,
+    array_remove(array_agg(CAST(EXTRACT(EPOCH FROM FirstReviewAt - OpenedAt) AS DOUBLE PRECISION)), NULL) AS ReviewLatencies
+FROM pull_request_stat
+WHERE MergedAt IS NOT NULL
+GROUP BY Repository, Day;
+
+CREATE UNIQUE INDEX pull_request_daily_stat_idx ON pull_request_daily_stat (Repository, Day);
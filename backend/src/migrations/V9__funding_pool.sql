@@ -0,0 +1,29 @@
+CREATE TABLE donation_intent
+(
+    ID Serial PRIMARY KEY,
+    Repository VARCHAR(512) NOT NULL,
+    Reference VARCHAR(64) NOT NULL UNIQUE,
+    Amount BIGINT NOT NULL,
+    Memo VARCHAR(256),
+    Donor VARCHAR(256),
+    Status VARCHAR(32) NOT NULL,
+    Signature VARCHAR(128) UNIQUE,
+    CreditedAmount BIGINT,
+    CreatedAt TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CreditedAt TIMESTAMPTZ
+);
+
+CREATE INDEX donation_intent_open_idx ON donation_intent (Repository, Status);
+
+CREATE TABLE pool_entry
+(
+    ID Serial PRIMARY KEY,
+    Repository VARCHAR(512) NOT NULL,
+    Amount BIGINT NOT NULL,
+    Kind VARCHAR(32) NOT NULL,
+    Reference INTEGER NOT NULL,
+    CreatedAt TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (Kind, Reference)
+);
+
+CREATE INDEX pool_entry_repository_idx ON pool_entry (Repository);
@@ -0,0 +1,381 @@
+pub mod oauth {
+    use crate::handlers::Config;
+    use crate::lib::crypto::crypto::{random_token, SecretBox};
+    use crate::session::session::Session;
+    use crate::user::user::{Tokens, User};
+    use openssl::base64::encode_block;
+    use openssl::sha::sha256;
+    use reqwest::header::ACCEPT;
+    use rocket_contrib::databases::postgres;
+    use serde::{Deserialize, Serialize};
+    use std::io;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    pub const OAUTH_STATE_COOKIE: &str = "oauth_state";
+    // LOGIN_ATTEMPT_TTL_SECONDS is how long a user has to complete the
+    // login on GitHub after it was started
+    pub const LOGIN_ATTEMPT_TTL_SECONDS: u64 = 10 * 60;
+    const AUTHORIZE_URL: &str = "https://github.com/login/oauth/authorize";
+    const ACCESS_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+
+    // REFRESH_MARGIN_SECONDS is how long before expiry an access token
+    // is refreshed so it does not expire in the middle of a request
+    const REFRESH_MARGIN_SECONDS: i64 = 5 * 60;
+
+    #[derive(Debug, Serialize)]
+    struct RefreshTokenRequest {
+        client_id: String,
+        client_secret: String,
+        grant_type: String,
+        refresh_token: String,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Oauth {
+        client_id: String,
+        client_secret: String,
+        code: String,
+        code_verifier: String,
+    }
+
+    fn empty_string() -> String {
+        "".to_string()
+    }
+
+    fn empty_usize() -> usize {
+        0
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct AccessTokenResponse {
+        #[serde(default = "empty_string")]
+        pub access_token: String,
+        #[serde(default = "empty_usize")]
+        pub expires_in: usize,
+        #[serde(default = "empty_string")]
+        pub refresh_token: String,
+        #[serde(default = "empty_usize")]
+        pub refresh_token_expires_in: usize,
+        #[serde(default = "empty_string")]
+        error: String,
+        #[serde(default = "empty_string")]
+        error_description: String,
+    }
+
+    // LoginError tells the user which step of the login failed
+    #[derive(Debug)]
+    pub enum LoginError {
+        MissingCode,
+        MissingState,
+        NotStarted,
+        Expired,
+        StateMismatch,
+        GithubUnavailable(String),
+        CodeRejected(String),
+        NoToken,
+        TokenExpired,
+    }
+
+    impl LoginError {
+        pub fn message(&self) -> String {
+            match self {
+                LoginError::MissingCode => String::from("missing code"),
+                LoginError::MissingState => String::from("missing state"),
+                LoginError::NotStarted => String::from("login was not started"),
+                LoginError::Expired => String::from("login attempt expired"),
+                LoginError::StateMismatch => String::from("state does not match"),
+                LoginError::GithubUnavailable(cause) => {
+                    format!("could not reach github: {}", cause)
+                }
+                LoginError::CodeRejected(cause) => format!("github rejected the code: {}", cause),
+                LoginError::NoToken => String::from("no github access token for user"),
+                LoginError::TokenExpired => {
+                    String::from("github authorization expired, please log in again")
+                }
+            }
+        }
+    }
+
+    impl AccessTokenResponse {
+        // tokens encrypts the tokens in the response for storage. An
+        // expiry of 0 means the token does not expire
+        pub fn tokens(&self, secret_box: &SecretBox) -> Result<Tokens, io::Error> {
+            let expires_in = |secs: usize| if secs == 0 { None } else { Some(secs as i64) };
+            let refresh_token = if self.refresh_token == "" {
+                None
+            } else {
+                Some(secret_box.encrypt(&self.refresh_token)?)
+            };
+            Ok(Tokens {
+                access_token: secret_box.encrypt(&self.access_token)?,
+                access_token_expires_in: expires_in(self.expires_in),
+                refresh_token,
+                refresh_token_expires_in: expires_in(self.refresh_token_expires_in),
+            })
+        }
+    }
+
+    // post_access_token posts to the access token endpoint. GitHub reports
+    // a bad code or refresh token with status 200 and an error body
+    fn post_access_token<T: Serialize>(body: &T) -> Result<AccessTokenResponse, LoginError> {
+        let req_client = reqwest::blocking::Client::new();
+        let res = match req_client
+            .post(ACCESS_TOKEN_URL)
+            .header(ACCEPT, "application/json")
+            .json(body)
+            .send()
+        {
+            Ok(res) => res,
+            Err(err) => return Err(LoginError::GithubUnavailable(err.to_string())),
+        };
+
+        let resp_code = res.status();
+        if resp_code != reqwest::StatusCode::OK {
+            return Err(LoginError::GithubUnavailable(format!(
+                "access token response code {}",
+                resp_code
+            )));
+        }
+        let access_token = match res.json::<AccessTokenResponse>() {
+            Ok(res) => res,
+            Err(err) => return Err(LoginError::GithubUnavailable(err.to_string())),
+        };
+        if access_token.error != "" {
+            return Err(LoginError::CodeRejected(format!(
+                "{} {}",
+                access_token.error, access_token.error_description
+            )));
+        }
+        if access_token.access_token == "" {
+            return Err(LoginError::CodeRejected(String::from(
+                "no access token in response",
+            )));
+        }
+        Ok(access_token)
+    }
+
+    // end_github_authorization forgets the tokens of the user and ends
+    // all sessions so the user has to log in again
+    fn end_github_authorization(db: &postgres::Connection, user: &mut User) -> LoginError {
+        if let Err(err) = user.clear_tokens(db) {
+            log::error!("oauth.clear_tokens.fail. Cause: {}", err);
+        }
+        if let Err(err) = Session::revoke_all_for_user(db, user.id) {
+            log::error!("oauth.revoke_sessions.fail. Cause: {}", err);
+        }
+        LoginError::TokenExpired
+    }
+
+    // needs_refresh checks if the access token of the user is about to
+    // expire
+    fn needs_refresh(user: &User) -> bool {
+        match user.access_token_expires_in {
+            Some(expires_in) => {
+                user.access_token().is_some() && expires_in <= REFRESH_MARGIN_SECONDS
+            }
+            None => false,
+        }
+    }
+
+    // stored_access_token decrypts the access token stored for the user
+    fn stored_access_token(secret_box: &SecretBox, user: &User) -> Result<String, LoginError> {
+        let sealed_access_token = match user.access_token() {
+            Some(token) => token,
+            None => return Err(LoginError::NoToken),
+        };
+        match secret_box.decrypt(sealed_access_token) {
+            Ok(token) => Ok(token),
+            Err(_) => Err(LoginError::NoToken),
+        }
+    }
+
+    // user_access_token returns a GitHub access token for calls made on
+    // behalf of the user. Tokens about to expire are refreshed first.
+    // When the refresh token is expired or revoked the sessions of the
+    // user are dropped
+    pub fn user_access_token(
+        config: &Config,
+        db: &postgres::Connection,
+        user: &mut User,
+    ) -> Result<String, LoginError> {
+        let secret_box = SecretBox::new(&config.token_encryption_key);
+        if !needs_refresh(user) {
+            return stored_access_token(&secret_box, user);
+        }
+
+        // Refresh tokens are single use. Refreshes of the same user are
+        // serialized on the user row, otherwise the request that loses
+        // the race sees its refresh token rejected and ends the sessions
+        // of the user. The transaction commits when it is dropped
+        let tx = match db.transaction() {
+            Ok(tx) => tx,
+            Err(err) => {
+                log::error!("oauth.refresh.lock.fail. Cause: {}", err);
+                return Err(LoginError::NoToken);
+            }
+        };
+        tx.set_commit();
+        if let Err(err) = tx.execute(
+            "SELECT ID FROM github_user WHERE ID=$1 FOR UPDATE",
+            &[&user.id],
+        ) {
+            log::error!("oauth.refresh.lock.fail. Cause: {}", err);
+            return Err(LoginError::NoToken);
+        }
+        // Another request may have refreshed the tokens while this one
+        // waited for the lock
+        *user = match User::get_by_id(db, user.id) {
+            Ok(stored) => stored,
+            Err(err) => {
+                log::error!("oauth.refresh.reload.fail. Cause: {}", err);
+                return Err(LoginError::NoToken);
+            }
+        };
+        if !needs_refresh(user) {
+            return stored_access_token(&secret_box, user);
+        }
+
+        let sealed_refresh_token = user.refresh_token().map(|token| token.to_vec());
+        let refresh_token = match (sealed_refresh_token, user.refresh_token_expires_in) {
+            (Some(_), Some(expires_in)) if expires_in <= 0 => {
+                return Err(end_github_authorization(db, user))
+            }
+            (Some(token), _) => match secret_box.decrypt(&token) {
+                Ok(token) => token,
+                Err(_) => return Err(end_github_authorization(db, user)),
+            },
+            (None, _) => return Err(end_github_authorization(db, user)),
+        };
+
+        let refresh_request = RefreshTokenRequest {
+            client_id: config.oauth_client_id.clone(),
+            client_secret: config.oauth_client_secret.clone(),
+            grant_type: String::from("refresh_token"),
+            refresh_token,
+        };
+        let access_token = match post_access_token(&refresh_request) {
+            Ok(access_token) => access_token,
+            Err(LoginError::CodeRejected(cause)) => {
+                log::info!("oauth.refresh.rejected. Cause: {}", cause);
+                return Err(end_github_authorization(db, user));
+            }
+            Err(err) => return Err(err),
+        };
+        let tokens = match access_token.tokens(&secret_box) {
+            Ok(tokens) => tokens,
+            Err(err) => return Err(LoginError::GithubUnavailable(err.to_string())),
+        };
+        if let Err(err) = user.update_tokens(db, tokens) {
+            log::error!("oauth.update_tokens.fail. Cause: {}", err);
+        }
+        Ok(access_token.access_token)
+    }
+
+    fn now() -> u64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(res) => res.as_secs(),
+            Err(_) => 0,
+        }
+    }
+
+    // base64_url encodes without padding as required by PKCE
+    fn base64_url(bytes: &[u8]) -> String {
+        encode_block(bytes)
+            .replace('+', "-")
+            .replace('/', "_")
+            .trim_end_matches('=')
+            .to_string()
+    }
+
+    // LoginAttempt is the state and PKCE verifier of a login that was
+    // started but not yet completed. It is kept in a private cookie
+    pub struct LoginAttempt {
+        pub state: String,
+        code_verifier: String,
+        started_at: u64,
+    }
+
+    impl LoginAttempt {
+        pub fn new() -> Result<LoginAttempt, io::Error> {
+            Ok(LoginAttempt {
+                state: random_token(16)?,
+                code_verifier: random_token(32)?,
+                started_at: now(),
+            })
+        }
+
+        pub fn code_challenge(&self) -> String {
+            base64_url(&sha256(self.code_verifier.as_bytes()))
+        }
+
+        pub fn to_cookie_value(&self) -> String {
+            format!("{}.{}.{}", self.state, self.code_verifier, self.started_at)
+        }
+
+        pub fn from_cookie_value(value: &str) -> Option<LoginAttempt> {
+            let parts: Vec<&str> = value.split('.').collect();
+            if parts.len() != 3 {
+                return None;
+            }
+            let started_at = parts[2].parse::<u64>().ok()?;
+            Some(LoginAttempt {
+                state: parts[0].to_string(),
+                code_verifier: parts[1].to_string(),
+                started_at,
+            })
+        }
+
+        // verify checks that the login was started recently and that the
+        // state returned by GitHub is the one that was sent
+        pub fn verify(&self, state: &str) -> Result<(), LoginError> {
+            if now() > self.started_at + LOGIN_ATTEMPT_TTL_SECONDS {
+                return Err(LoginError::Expired);
+            }
+            if self.state.len() == state.len()
+                && openssl::memcmp::eq(self.state.as_bytes(), state.as_bytes())
+            {
+                return Ok(());
+            }
+            Err(LoginError::StateMismatch)
+        }
+
+        pub fn authorize_url(
+            &self,
+            client_id: &str,
+            redirect_uri: &Option<String>,
+        ) -> Result<String, io::Error> {
+            let code_challenge = self.code_challenge();
+            let mut params = vec![
+                ("client_id", client_id),
+                ("scope", "read:user"),
+                ("state", self.state.as_str()),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ];
+            if let Some(redirect_uri) = redirect_uri {
+                params.push(("redirect_uri", redirect_uri.as_str()));
+            }
+            match reqwest::Url::parse_with_params(AUTHORIZE_URL, &params) {
+                Ok(url) => Ok(url.to_string()),
+                Err(err) => Err(io::Error::new(io::ErrorKind::Other, err.to_string())),
+            }
+        }
+
+        // exchange_code exchanges the authorization code for an access
+        // token, proving possession of the PKCE verifier
+        pub fn exchange_code(
+            &self,
+            client_id: &str,
+            client_secret: &str,
+            code: &str,
+        ) -> Result<AccessTokenResponse, LoginError> {
+            let github_post = Oauth {
+                client_id: client_id.to_string(),
+                client_secret: client_secret.to_string(),
+                code: code.to_string(),
+                code_verifier: self.code_verifier.clone(),
+            };
+            post_access_token(&github_post)
+        }
+    }
+}
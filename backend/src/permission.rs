@@ -0,0 +1,233 @@
+pub mod permission {
+    use crate::api_key::api_key::Principal;
+    use crate::handlers;
+    use crate::lib::web_error::WebError::WebError;
+    use crate::repository::repository::Repository;
+    use rithub::api::api;
+    use rithub::error::errors::Error;
+    use rocket::http::Status;
+    use rocket::request::{FromRequest, Outcome, Request};
+    use rocket::State;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    // Role is the permission of a user on a repository as reported by
+    // GitHub, ordered from least to most privileged
+    #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+    pub enum Role {
+        None,
+        Read,
+        Triage,
+        Write,
+        Maintain,
+        Admin,
+    }
+
+    impl Role {
+        // from_github maps the role name of a collaborator permission.
+        // Custom roles fall back to the base permission
+        pub fn from_github(permission: &api::CollaboratorPermission) -> Role {
+            match permission.role_name.as_str() {
+                "admin" => Role::Admin,
+                "maintain" => Role::Maintain,
+                "write" => Role::Write,
+                "triage" => Role::Triage,
+                "read" => Role::Read,
+                _ => match permission.permission.as_str() {
+                    "admin" => Role::Admin,
+                    "write" => Role::Write,
+                    "read" => Role::Read,
+                    _ => Role::None,
+                },
+            }
+        }
+
+        // is_maintainer checks if the role may change the reward policy,
+        // fund pools and approve manual payouts of the repository
+        pub fn is_maintainer(&self) -> bool {
+            *self >= Role::Maintain
+        }
+    }
+
+    // RoleCache keeps the roles fetched from GitHub for a while so a
+    // burst of requests does not hit the GitHub rate limit
+    pub struct RoleCache {
+        ttl: Duration,
+        roles: Mutex<HashMap<String, (Role, Instant)>>,
+    }
+
+    impl RoleCache {
+        pub fn new(ttl_seconds: u64) -> RoleCache {
+            RoleCache {
+                ttl: Duration::from_secs(ttl_seconds),
+                roles: Mutex::new(HashMap::new()),
+            }
+        }
+
+        fn key(full_name: &str, username: &str) -> String {
+            format!("{}#{}", full_name, username).to_lowercase()
+        }
+
+        pub fn get(&self, full_name: &str, username: &str) -> Option<Role> {
+            let roles = match self.roles.lock() {
+                Ok(roles) => roles,
+                Err(_) => return None,
+            };
+            match roles.get(&RoleCache::key(full_name, username)) {
+                Some((role, fetched_at)) if fetched_at.elapsed() < self.ttl => Some(*role),
+                _ => None,
+            }
+        }
+
+        pub fn insert(&self, full_name: &str, username: &str, role: Role) {
+            if let Ok(mut roles) = self.roles.lock() {
+                let ttl = self.ttl;
+                roles.retain(|_, (_, fetched_at)| fetched_at.elapsed() < ttl);
+                roles.insert(RoleCache::key(full_name, username), (role, Instant::now()));
+            }
+        }
+    }
+
+    // repository_role returns the role of the user on the repository,
+    // asking GitHub through the app installation when it is not cached
+    pub fn repository_role(
+        api: &handlers::Api,
+        repository: &Repository,
+        username: &str,
+    ) -> Result<Role, Error> {
+        if let Some(role) = api.role_cache.get(&repository.full_name, username) {
+            return Ok(role);
+        }
+        let (owner, name) = match repository.full_name.split_once('/') {
+            Some(parts) => parts,
+            None => {
+                return Err(Error::new(
+                    500,
+                    format!("invalid repository name {}", repository.full_name),
+                ))
+            }
+        };
+        let github_app = match api.github_apps.get(&repository.app_id) {
+            Some(github_app) => github_app,
+            None => {
+                return Err(Error::new(
+                    403,
+                    format!(
+                        "{} is installed with GitHub App {} which is not configured",
+                        repository.full_name, repository.app_id
+                    ),
+                ))
+            }
+        };
+        let access_token = match github_app
+            .client
+            .authenticate_app(repository.installation_id.to_string())
+        {
+            Ok(token) => token,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        let github_client = handlers::github_client(api, &access_token.token);
+        let permission = github_client.get_collaborator_permission(owner, name, username)?;
+        let role = Role::from_github(&permission);
+        api.role_cache.insert(&repository.full_name, username, role);
+        Ok(role)
+    }
+
+    // Maintainer is the request guard for routes under
+    // /repos/<owner>/<name> that only maintainers of the repository may
+    // call. Scopes of API keys are still checked by the route
+    pub struct Maintainer {
+        pub principal: Principal,
+        pub repository: Repository,
+        pub role: Role,
+    }
+
+    fn failure(status: Status, message: &str) -> Outcome<Maintainer, WebError> {
+        Outcome::Failure((
+            status,
+            WebError::new(status.code as usize, message.to_string()),
+        ))
+    }
+
+    impl<'r, 'a> FromRequest<'r, 'a> for Maintainer {
+        type Error = WebError;
+        fn from_request(req: &'r Request<'a>) -> Outcome<Self, Self::Error> {
+            let principal = match req.guard::<Principal>() {
+                Outcome::Success(principal) => principal,
+                Outcome::Failure(failure) => return Outcome::Failure(failure),
+                Outcome::Forward(forward) => return Outcome::Forward(forward),
+            };
+            let full_name = match (req.get_param::<String>(0), req.get_param::<String>(1)) {
+                (Some(Ok(owner)), Some(Ok(name))) => format!("{}/{}", owner, name),
+                _ => return failure(Status::NotFound, "repository not found"),
+            };
+            let api = match req.guard::<State<handlers::Api>>() {
+                Outcome::Success(api) => api,
+                _ => return failure(Status::InternalServerError, "missing api state"),
+            };
+            let db = match req.guard::<handlers::MyPgDatabase>() {
+                Outcome::Success(db) => db,
+                _ => return failure(Status::ServiceUnavailable, "database unavailable"),
+            };
+            let repository = match Repository::get(&db, &full_name) {
+                Ok(repository) => repository,
+                Err(_) => return failure(Status::NotFound, "repository not found"),
+            };
+
+            let role = match repository_role(&api, &repository, principal.user().user_name()) {
+                Ok(role) => role,
+                Err(err) => {
+                    log::error!("permission.repository_role.fail. Cause: {}", err.message);
+                    return failure(Status::ServiceUnavailable, "could not check permission");
+                }
+            };
+            if !role.is_maintainer() {
+                return failure(Status::Forbidden, "only maintainers may do this");
+            }
+            Outcome::Success(Maintainer {
+                principal,
+                repository,
+                role,
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn role(permission: &str, role_name: &str) -> Role {
+            Role::from_github(&api::CollaboratorPermission {
+                permission: permission.to_string(),
+                role_name: role_name.to_string(),
+            })
+        }
+
+        #[test]
+        fn from_github_reads_the_role_name() {
+            assert_eq!(role("admin", "admin"), Role::Admin);
+            assert_eq!(role("write", "maintain"), Role::Maintain);
+            assert_eq!(role("write", "write"), Role::Write);
+            assert_eq!(role("read", "triage"), Role::Triage);
+            assert_eq!(role("read", "read"), Role::Read);
+            assert_eq!(role("none", "none"), Role::None);
+        }
+
+        #[test]
+        fn from_github_falls_back_to_the_base_permission() {
+            assert_eq!(role("admin", "security-manager"), Role::Admin);
+            assert_eq!(role("write", ""), Role::Write);
+            assert_eq!(role("read", "custom"), Role::Read);
+            assert_eq!(role("none", ""), Role::None);
+        }
+
+        #[test]
+        fn only_maintain_and_admin_are_maintainers() {
+            assert!(Role::Admin.is_maintainer());
+            assert!(Role::Maintain.is_maintainer());
+            assert!(!Role::Write.is_maintainer());
+            assert!(!Role::None.is_maintainer());
+        }
+    }
+}
@@ -0,0 +1,404 @@
+pub mod policy {
+    use crate::repository::repository::FundingMode;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::Mutex;
+
+    // POLICY_PATH is where a repository keeps its reward policy
+    pub const POLICY_PATH: &str = ".github/oct.yml";
+
+    // CAP_OVERFLOW_TRUNCATE and CAP_OVERFLOW_HOLD are what happens to the
+    // part of a reward over a payout cap
+    pub const CAP_OVERFLOW_TRUNCATE: &str = "truncate";
+    pub const CAP_OVERFLOW_HOLD: &str = "hold";
+
+    // Weights are the scorer weights used when estimating the value
+    // of pull requests and reviews
+    #[derive(Deserialize, Debug, Clone)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct Weights {
+        pub additions: f64,
+        pub deletions: f64,
+        pub changed_files: f64,
+        pub pull_request_scale: f64,
+        pub review_comment: f64,
+        pub review_chars_per_point: usize,
+        pub review_scale: f64,
+    }
+
+    impl Default for Weights {
+        fn default() -> Weights {
+            Weights {
+                additions: 1.2,
+                deletions: 0.8,
+                changed_files: 1.1,
+                pull_request_scale: 100.0,
+                review_comment: 1.0,
+                review_chars_per_point: 30,
+                review_scale: 100.0,
+            }
+        }
+    }
+
+    // Policy is the reward policy of a repository as configured in
+    // .github/oct.yml on the default branch
+    #[derive(Deserialize, Debug, Clone)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct Policy {
+        // max_reward is R_max in OCT for a single pull request
+        pub max_reward: f64,
+        pub author_share: f64,
+        // author_reward_cap is the most OCT an author receives for a
+        // single pull request
+        pub author_reward_cap: Option<f64>,
+        // exclude_self_merges stops rewarding maintainers that merge
+        // their own pull requests
+        pub exclude_self_merges: bool,
+        pub reviewer_share: f64,
+        // bounty_reviewer_share is the part of an issue bounty split
+        // between the reviewers, the author of the pull request gets the rest
+        pub bounty_reviewer_share: f64,
+        pub weights: Weights,
+        pub excluded_paths: Vec<String>,
+        pub excluded_users: Vec<String>,
+        pub exclude_bots: bool,
+        // exclude_site_admins stops rewarding GitHub staff accounts
+        pub exclude_site_admins: bool,
+        // Rewards of accounts younger than min_account_age_days, of
+        // accounts never paid before when hold_inactive_accounts is set,
+        // and of users that reviewed each other's pull requests at least
+        // review_ring_threshold times wait for a maintainer. 0 disables
+        pub min_account_age_days: u32,
+        pub hold_inactive_accounts: bool,
+        pub review_ring_threshold: u32,
+        // max_reward_per_reviewer, max_reward_per_user_per_day and
+        // max_reward_per_repository_per_week cap the OCT paid out by the
+        // repository. cap_overflow is truncate to pay up to the cap or hold
+        // to wait for a maintainer
+        pub max_reward_per_reviewer: Option<f64>,
+        pub max_reward_per_user_per_day: Option<f64>,
+        pub max_reward_per_repository_per_week: Option<f64>,
+        pub cap_overflow: String,
+        // require_payout_approval turns the rewards of a merged pull
+        // request into a payout proposal a maintainer approves
+        pub require_payout_approval: bool,
+        pub min_review_length: usize,
+        pub eligible_labels: Vec<String>,
+        pub funding_mode: Option<String>,
+    }
+
+    impl Default for Policy {
+        fn default() -> Policy {
+            Policy {
+                max_reward: 10.0,
+                author_share: 0.0,
+                author_reward_cap: None,
+                exclude_self_merges: false,
+                reviewer_share: 1.0,
+                bounty_reviewer_share: 0.0,
+                weights: Weights::default(),
+                excluded_paths: vec![],
+                excluded_users: vec![],
+                exclude_bots: true,
+                exclude_site_admins: false,
+                min_account_age_days: 0,
+                hold_inactive_accounts: false,
+                review_ring_threshold: 0,
+                max_reward_per_reviewer: None,
+                max_reward_per_user_per_day: None,
+                max_reward_per_repository_per_week: None,
+                cap_overflow: String::from(CAP_OVERFLOW_TRUNCATE),
+                require_payout_approval: false,
+                min_review_length: 0,
+                eligible_labels: vec![],
+                funding_mode: None,
+            }
+        }
+    }
+
+    impl Policy {
+        // parse reads and validates a policy file. limit is the largest
+        // reward in OCT the deployment allows for a pull request
+        pub fn parse(content: &str, limit: f64) -> Result<Policy, String> {
+            if content.trim().is_empty() {
+                return Ok(Policy::default());
+            }
+            let policy: Policy = match serde_yaml::from_str(content) {
+                Ok(policy) => policy,
+                Err(err) => return Err(err.to_string()),
+            };
+            policy.validate(limit)?;
+            Ok(policy)
+        }
+
+        fn validate(&self, limit: f64) -> Result<(), String> {
+            // Rewards and caps are converted to base units, an infinite or
+            // unbounded amount would not fit
+            let in_range = |amount: f64| amount.is_finite() && (0.0..=limit).contains(&amount);
+            if !in_range(self.max_reward) {
+                return Err(format!("max_reward must be between 0 and {}", limit));
+            }
+            if !(0.0..=1.0).contains(&self.author_share) {
+                return Err(String::from("author_share must be between 0 and 1"));
+            }
+            if let Some(cap) = self.author_reward_cap {
+                if !in_range(cap) {
+                    return Err(format!("author_reward_cap must be between 0 and {}", limit));
+                }
+            }
+            let caps = [
+                self.max_reward_per_reviewer,
+                self.max_reward_per_user_per_day,
+                self.max_reward_per_repository_per_week,
+            ];
+            if caps.iter().flatten().any(|cap| !in_range(*cap)) {
+                return Err(format!("payout caps must be between 0 and {}", limit));
+            }
+            if self.cap_overflow != CAP_OVERFLOW_TRUNCATE && self.cap_overflow != CAP_OVERFLOW_HOLD
+            {
+                return Err(String::from("cap_overflow must be truncate or hold"));
+            }
+            if !(0.0..=1.0).contains(&self.reviewer_share) {
+                return Err(String::from("reviewer_share must be between 0 and 1"));
+            }
+            if !(0.0..=1.0).contains(&self.bounty_reviewer_share) {
+                return Err(String::from(
+                    "bounty_reviewer_share must be between 0 and 1",
+                ));
+            }
+            if self.author_share + self.reviewer_share > 1.0 {
+                return Err(String::from(
+                    "author_share and reviewer_share must not add up to more than 1",
+                ));
+            }
+            let weights = &self.weights;
+            let factors = [
+                weights.additions,
+                weights.deletions,
+                weights.changed_files,
+                weights.review_comment,
+            ];
+            if factors
+                .iter()
+                .any(|weight| !weight.is_finite() || *weight < 0.0)
+            {
+                return Err(String::from("weights must be finite and not negative"));
+            }
+            let scales = [weights.pull_request_scale, weights.review_scale];
+            if scales
+                .iter()
+                .any(|scale| !scale.is_finite() || *scale <= 0.0)
+            {
+                return Err(String::from("scales must be finite and positive"));
+            }
+            if weights.review_chars_per_point == 0 {
+                return Err(String::from("review_chars_per_point must be positive"));
+            }
+            self.funding_mode()?;
+            Ok(())
+        }
+
+        pub fn funding_mode(&self) -> Result<Option<FundingMode>, String> {
+            match &self.funding_mode {
+                Some(mode) => match FundingMode::from_str(mode) {
+                    Ok(mode) => Ok(Some(mode)),
+                    Err(err) => Err(err.to_string()),
+                },
+                None => Ok(None),
+            }
+        }
+
+        // is_excluded_user checks if the user never receives rewards
+        pub fn is_excluded_user(&self, login: &str) -> bool {
+            if self.exclude_bots && login.ends_with("[bot]") {
+                return true;
+            }
+            self.excluded_users
+                .iter()
+                .any(|user| user.eq_ignore_ascii_case(login))
+        }
+
+        pub fn is_excluded_path(&self, path: &str) -> bool {
+            self.excluded_paths
+                .iter()
+                .any(|pattern| glob_match(pattern, path))
+        }
+
+        // is_eligible checks if a pull request with the given labels
+        // is eligible for rewards
+        pub fn is_eligible(&self, labels: &[String]) -> bool {
+            if self.eligible_labels.is_empty() {
+                return true;
+            }
+            labels
+                .iter()
+                .any(|label| self.eligible_labels.contains(label))
+        }
+    }
+
+    // glob_match matches a path against a pattern where `*` matches
+    // within a path segment and `**` matches across segments
+    fn glob_match(pattern: &str, path: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let path: Vec<char> = path.chars().collect();
+        glob_match_from(&pattern, &path)
+    }
+
+    fn glob_match_from(pattern: &[char], path: &[char]) -> bool {
+        match pattern.first() {
+            None => path.is_empty(),
+            Some('*') => {
+                if pattern.get(1) == Some(&'*') {
+                    let rest = &pattern[2..];
+                    let rest = if rest.first() == Some(&'/') {
+                        &rest[1..]
+                    } else {
+                        rest
+                    };
+                    (0..=path.len()).any(|i| glob_match_from(rest, &path[i..]))
+                } else {
+                    let rest = &pattern[1..];
+                    for i in 0..=path.len() {
+                        if glob_match_from(rest, &path[i..]) {
+                            return true;
+                        }
+                        if i < path.len() && path[i] == '/' {
+                            break;
+                        }
+                    }
+                    false
+                }
+            }
+            Some(c) => match path.first() {
+                Some(p) if p == c => glob_match_from(&pattern[1..], &path[1..]),
+                _ => false,
+            },
+        }
+    }
+
+    // PolicyCache keeps parsed policies per repository and commit sha
+    // so the file is only fetched once for every change to it
+    pub struct PolicyCache {
+        policies: Mutex<HashMap<String, Result<Policy, String>>>,
+    }
+
+    impl PolicyCache {
+        pub fn new() -> PolicyCache {
+            PolicyCache {
+                policies: Mutex::new(HashMap::new()),
+            }
+        }
+
+        fn key(full_name: &str, sha: &str) -> String {
+            format!("{}@{}", full_name, sha)
+        }
+
+        pub fn get(&self, full_name: &str, sha: &str) -> Option<Result<Policy, String>> {
+            let policies = match self.policies.lock() {
+                Ok(policies) => policies,
+                Err(_) => return None,
+            };
+            policies.get(&PolicyCache::key(full_name, sha)).cloned()
+        }
+
+        pub fn insert(&self, full_name: &str, sha: &str, policy: Result<Policy, String>) {
+            if let Ok(mut policies) = self.policies.lock() {
+                // Older commits of the same repository are never asked for again
+                let prefix = format!("{}@", full_name);
+                policies.retain(|key, _| !key.starts_with(&prefix));
+                policies.insert(PolicyCache::key(full_name, sha), policy);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn parse(content: &str) -> Result<Policy, String> {
+            Policy::parse(content, 1000.0)
+        }
+
+        #[test]
+        fn parse_empty_is_default() {
+            let policy = parse("").unwrap();
+            assert_eq!(policy.max_reward, Policy::default().max_reward);
+            assert!(policy.exclude_bots);
+        }
+
+        #[test]
+        fn parse_reads_fields() {
+            let policy = parse(
+                "max_reward: 20\nauthor_share: 0.3\nreviewer_share: 0.7\nexcluded_paths: [\"docs/**\"]",
+            )
+            .unwrap();
+            assert_eq!(policy.max_reward, 20.0);
+            assert_eq!(policy.author_share, 0.3);
+            assert!(policy.is_excluded_path("docs/guide/intro.md"));
+        }
+
+        #[test]
+        fn parse_rejects_unknown_fields() {
+            assert!(parse("max_rewards: 20").is_err());
+        }
+
+        #[test]
+        fn validate_rejects_out_of_range_values() {
+            assert!(parse("max_reward: -1").is_err());
+            assert!(parse("author_share: 1.5").is_err());
+            assert!(parse("reviewer_share: -0.1").is_err());
+            assert!(parse("author_share: 0.6\nreviewer_share: 0.6").is_err());
+            assert!(parse("bounty_reviewer_share: 2").is_err());
+            assert!(parse("author_reward_cap: -5").is_err());
+            assert!(parse("weights:\n  review_scale: 0").is_err());
+            assert!(parse("weights:\n  review_chars_per_point: 0").is_err());
+            assert!(parse("max_reward_per_user_per_day: -1").is_err());
+            assert!(parse("cap_overflow: drop").is_err());
+            assert!(parse("funding_mode: nobody").is_err());
+        }
+
+        #[test]
+        fn validate_rejects_unbounded_amounts() {
+            assert!(parse("max_reward: .inf").is_err());
+            assert!(parse("max_reward: .nan").is_err());
+            assert!(parse("max_reward: 1e12").is_err());
+            assert!(parse("author_reward_cap: .inf").is_err());
+            assert!(parse("max_reward_per_reviewer: .nan").is_err());
+            assert!(parse("max_reward_per_user_per_day: 1e12").is_err());
+            assert!(parse("max_reward_per_repository_per_week: .inf").is_err());
+            assert!(parse("weights:\n  additions: .inf").is_err());
+            assert!(parse("weights:\n  review_comment: .nan").is_err());
+            assert!(parse("weights:\n  pull_request_scale: .inf").is_err());
+            assert!(parse("max_reward: 1000").is_ok());
+        }
+
+        #[test]
+        fn validate_accepts_valid_values() {
+            assert!(parse("author_share: 0.4\nreviewer_share: 0.6").is_ok());
+            assert!(parse("cap_overflow: hold").is_ok());
+        }
+
+        #[test]
+        fn glob_match_single_star_stays_in_segment() {
+            assert!(glob_match("*.md", "README.md"));
+            assert!(!glob_match("*.md", "docs/README.md"));
+            assert!(glob_match("src/*.rs", "src/main.rs"));
+            assert!(!glob_match("src/*.rs", "src/lib/mod.rs"));
+        }
+
+        #[test]
+        fn glob_match_double_star_crosses_segments() {
+            assert!(glob_match("docs/**", "docs/a/b/c.md"));
+            assert!(glob_match("**/*.lock", "Cargo.lock"));
+            assert!(glob_match("**/*.lock", "backend/Cargo.lock"));
+            assert!(!glob_match("docs/**", "src/docs.rs"));
+        }
+
+        #[test]
+        fn glob_match_literal() {
+            assert!(glob_match("Cargo.toml", "Cargo.toml"));
+            assert!(!glob_match("Cargo.toml", "Cargo.lock"));
+        }
+    }
+}
@@ -0,0 +1,312 @@
+pub mod pool {
+    use crate::bounty::bounty;
+    use rocket_contrib::databases::postgres;
+    use std::io::{Error, ErrorKind};
+
+    pub const DONATION_OPEN: &str = "open";
+    pub const DONATION_CREDITED: &str = "credited";
+
+    // ENTRY_DONATION, ENTRY_PAYOUT, ENTRY_BOUNTY, ENTRY_BOUNTY_REFUND and
+    // ENTRY_BOUNTY_SHARE_REFUND are the kinds of pool entries. The
+    // reference of an entry is the id of the donation intent, payout or
+    // bounty. A bounty share refund is the escrow of a rejected payout
+    const ENTRY_DONATION: &str = "donation";
+    const ENTRY_PAYOUT: &str = "payout";
+    pub const ENTRY_BOUNTY: &str = "bounty";
+    pub const ENTRY_BOUNTY_REFUND: &str = "bounty_refund";
+    pub const ENTRY_BOUNTY_SHARE_REFUND: &str = "bounty_share_refund";
+
+    // MAX_MEMO_LENGTH is the longest memo a donor may attach
+    const MAX_MEMO_LENGTH: usize = 256;
+
+    // DonationIntent is a donation to the pool of a repository that the
+    // donor pays on chain. The transfer must include the reference as an
+    // account so it can be matched to the intent. Donations for an issue
+    // fund a bounty on it once they arrive
+    pub struct DonationIntent {
+        pub id: i32,
+        pub repository: String,
+        pub reference: String,
+        pub amount: i64,
+        pub memo: Option<String>,
+        pub donor: Option<String>,
+        pub status: String,
+        pub signature: Option<String>,
+        pub credited_amount: Option<i64>,
+        pub issue: Option<i32>,
+        pub bounty_expires_at: Option<String>,
+    }
+
+    impl DonationIntent {
+        pub fn new(
+            repository: &str,
+            reference: &str,
+            amount: u64,
+            memo: Option<String>,
+            donor: Option<String>,
+            issue: Option<i32>,
+            bounty_expires_at: Option<String>,
+        ) -> DonationIntent {
+            DonationIntent {
+                id: 0,
+                repository: repository.to_string(),
+                reference: reference.to_string(),
+                amount: amount as i64,
+                memo,
+                donor,
+                status: String::from(DONATION_OPEN),
+                signature: None,
+                credited_amount: None,
+                issue,
+                bounty_expires_at,
+            }
+        }
+
+        fn from_row(row: &postgres::rows::Row) -> DonationIntent {
+            DonationIntent {
+                id: row.get("ID"),
+                repository: row.get("Repository"),
+                reference: row.get("Reference"),
+                amount: row.get("Amount"),
+                memo: row.get("Memo"),
+                donor: row.get("Donor"),
+                status: row.get("Status"),
+                signature: row.get("Signature"),
+                credited_amount: row.get("CreditedAmount"),
+                issue: row.get("Issue"),
+                bounty_expires_at: row.get("BountyExpiresAt"),
+            }
+        }
+
+        // insert stores the intent and returns its id
+        pub fn insert(&mut self, db: &postgres::Connection) -> Result<i32, Error> {
+            if self.amount <= 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    String::from("amount must be positive"),
+                ));
+            }
+            if let Some(memo) = &self.memo {
+                if memo.chars().count() > MAX_MEMO_LENGTH {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("memo must be at most {} characters", MAX_MEMO_LENGTH),
+                    ));
+                }
+            }
+            let rows = match db.query(
+                "
+            INSERT INTO donation_intent (Repository, Reference, Amount, Memo, Donor, Status, Issue, BountyExpiresAt)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, CAST($8::TEXT AS TIMESTAMPTZ))
+            RETURNING ID
+            ",
+                &[
+                    &self.repository,
+                    &self.reference,
+                    &self.amount,
+                    &self.memo,
+                    &self.donor,
+                    &self.status,
+                    &self.issue,
+                    &self.bounty_expires_at,
+                ],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                self.id = row.get("ID");
+            }
+            Ok(self.id)
+        }
+
+        pub fn get(db: &postgres::Connection, id: i32) -> Result<DonationIntent, Error> {
+            let rows = match db.query(
+                "
+            SELECT ID, Repository, Reference, Amount, Memo, Donor, Status, Signature, CreditedAmount, Issue,
+                to_char(BountyExpiresAt AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS BountyExpiresAt
+            FROM donation_intent
+            WHERE ID=$1
+            ",
+                &[&id],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                return Ok(DonationIntent::from_row(&row));
+            }
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!("donation {} not found", id),
+            ))
+        }
+
+        // list_open lists the intents of a repository that have not been
+        // paid yet. Intents older than a week are no longer watched
+        pub fn list_open(
+            db: &postgres::Connection,
+            repository: &str,
+        ) -> Result<Vec<DonationIntent>, Error> {
+            let rows = match db.query(
+                "
+            SELECT ID, Repository, Reference, Amount, Memo, Donor, Status, Signature, CreditedAmount, Issue,
+                to_char(BountyExpiresAt AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS BountyExpiresAt
+            FROM donation_intent
+            WHERE Repository=$1 AND Status=$2 AND CreatedAt > now() - INTERVAL '7 days'
+            ORDER BY ID
+            ",
+                &[&repository, &DONATION_OPEN],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            Ok(rows
+                .iter()
+                .map(|row| DonationIntent::from_row(&row))
+                .collect())
+        }
+
+        // credit marks the intent as paid by the transaction and adds the
+        // amount that arrived on chain to the pool of the repository. The
+        // amount is escrowed right away for donations to an issue
+        pub fn credit(
+            &mut self,
+            db: &postgres::Connection,
+            signature: &str,
+            amount: u64,
+        ) -> Result<(), Error> {
+            let amount = amount as i64;
+            let result = db.transaction().and_then(|tx| {
+                let updated = tx.execute(
+                    "
+                UPDATE donation_intent
+                SET Status=$1, Signature=$2, CreditedAmount=$3, CreditedAt=now()
+                WHERE ID=$4 AND Status=$5
+                ",
+                    &[
+                        &DONATION_CREDITED,
+                        &signature,
+                        &amount,
+                        &self.id,
+                        &DONATION_OPEN,
+                    ],
+                )?;
+                if updated == 1 {
+                    tx.execute(
+                        "
+                    INSERT INTO pool_entry (Repository, Amount, Kind, Reference)
+                    VALUES ($1, $2, $3, $4)
+                    ",
+                        &[&self.repository, &amount, &ENTRY_DONATION, &self.id],
+                    )?;
+                    if let Some(issue) = self.issue {
+                        bounty::escrow(
+                            &tx,
+                            &self.repository,
+                            issue,
+                            amount,
+                            self.donor.as_deref().unwrap_or(""),
+                            self.bounty_expires_at.as_deref(),
+                            false,
+                        )?;
+                    }
+                }
+                tx.commit()
+            });
+            match result {
+                Ok(_) => {
+                    self.status = String::from(DONATION_CREDITED);
+                    self.signature = Some(signature.to_string());
+                    self.credited_amount = Some(amount);
+                    Ok(())
+                }
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+    }
+
+    // balance returns the pool balance of a repository in base units
+    pub fn balance(db: &postgres::Connection, repository: &str) -> Result<i64, Error> {
+        let rows = match db.query(
+            "
+        SELECT CAST(COALESCE(SUM(Amount), 0) AS BIGINT) AS Balance
+        FROM pool_entry
+        WHERE Repository=$1
+        ",
+            &[&repository],
+        ) {
+            Ok(rows) => rows,
+            Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        };
+        let mut balance = 0;
+        for row in &rows {
+            balance = row.get("Balance");
+        }
+        Ok(balance)
+    }
+
+    // reserve takes payouts out of the pool of the repository before they
+    // are sent, given as payout id and amount. It returns false and takes
+    // nothing when the pool does not hold the total, including repositories
+    // that never received a donation. The treasury holds the pools of all
+    // repositories and never pays beyond the pool of one
+    pub fn reserve(
+        db: &postgres::Connection,
+        repository: &str,
+        payouts: &[(i32, i64)],
+    ) -> Result<bool, Error> {
+        let total: i64 = payouts.iter().map(|(_, amount)| amount).sum();
+        let result = db.transaction().and_then(|tx| {
+            // Serialize spending from the pool of the repository
+            tx.execute("SELECT pg_advisory_xact_lock(hashtext($1))", &[&repository])?;
+            let rows = tx.query(
+                "
+            SELECT CAST(COALESCE(SUM(Amount), 0) AS BIGINT) AS Balance
+            FROM pool_entry
+            WHERE Repository=$1
+            ",
+                &[&repository],
+            )?;
+            let balance: i64 = match rows.iter().next() {
+                Some(row) => row.get("Balance"),
+                None => 0,
+            };
+            if balance < total {
+                return Ok(false);
+            }
+            for (payout_id, amount) in payouts {
+                tx.execute(
+                    "
+                INSERT INTO pool_entry (Repository, Amount, Kind, Reference)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (Kind, Reference) DO NOTHING
+                ",
+                    &[&repository, &-amount, &ENTRY_PAYOUT, payout_id],
+                )?;
+            }
+            tx.commit()?;
+            Ok(true)
+        });
+        match result {
+            Ok(reserved) => Ok(reserved),
+            Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        }
+    }
+
+    // release puts the reserved amount of a payout that was not sent back
+    // into the pool
+    pub fn release(db: &postgres::Connection, payout_id: i32) -> Result<(), Error> {
+        let query_res = db.execute(
+            "
+        DELETE FROM pool_entry
+        WHERE Kind=$1 AND Reference=$2
+        ",
+            &[&ENTRY_PAYOUT, &payout_id],
+        );
+        match query_res {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        }
+    }
+}
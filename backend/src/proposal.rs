@@ -0,0 +1,150 @@
+pub mod proposal {
+    use rocket_contrib::databases::postgres;
+    use std::io::{Error, ErrorKind};
+
+    pub const PROPOSAL_OPEN: &str = "open";
+    pub const PROPOSAL_APPROVED: &str = "approved";
+    pub const PROPOSAL_REJECTED: &str = "rejected";
+
+    // PROPOSAL_COLUMNS are the columns read by from_row
+    const PROPOSAL_COLUMNS: &str = "ID, Repository, PullRequest, Status, DecidedBy,
+            to_char(DecidedAt AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS DecidedAt,
+            to_char(CreatedAt AT TIME ZONE 'UTC', 'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"') AS CreatedAt";
+
+    // Proposal holds the rewards of a merged pull request in repositories
+    // that require payout approval. Nothing is sent until a maintainer
+    // approves it
+    pub struct Proposal {
+        pub id: i32,
+        pub repository: String,
+        pub pull_request: i32,
+        pub status: String,
+        // decided_by is the maintainer that approved or rejected it
+        pub decided_by: Option<String>,
+        pub decided_at: Option<String>,
+        pub created_at: String,
+    }
+
+    impl Proposal {
+        fn from_row(row: &postgres::rows::Row) -> Proposal {
+            Proposal {
+                id: row.get("ID"),
+                repository: row.get("Repository"),
+                pull_request: row.get("PullRequest"),
+                status: row.get("Status"),
+                decided_by: row.get("DecidedBy"),
+                decided_at: row.get("DecidedAt"),
+                created_at: row.get("CreatedAt"),
+            }
+        }
+
+        pub fn create(
+            db: &dyn postgres::GenericConnection,
+            repository: &str,
+            pull_request: i32,
+        ) -> Result<Proposal, Error> {
+            let rows = match db.query(
+                &format!(
+                    "
+            INSERT INTO payout_proposal (Repository, PullRequest, Status)
+            VALUES ($1, $2, $3)
+            RETURNING {}
+            ",
+                    PROPOSAL_COLUMNS
+                ),
+                &[&repository, &pull_request, &PROPOSAL_OPEN],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                return Ok(Proposal::from_row(&row));
+            }
+            Err(Error::new(
+                ErrorKind::Other,
+                String::from("payout proposal was not created"),
+            ))
+        }
+
+        pub fn get(db: &postgres::Connection, id: i32) -> Result<Proposal, Error> {
+            let rows = match db.query(
+                &format!(
+                    "SELECT {} FROM payout_proposal WHERE ID=$1",
+                    PROPOSAL_COLUMNS
+                ),
+                &[&id],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                return Ok(Proposal::from_row(&row));
+            }
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!("payout proposal {} not found", id),
+            ))
+        }
+
+        // list lists the proposals of a repository, optionally of a single
+        // pull request or status, newest first
+        pub fn list(
+            db: &postgres::Connection,
+            repository: &str,
+            pull_request: Option<i32>,
+            status: Option<String>,
+        ) -> Result<Vec<Proposal>, Error> {
+            let rows = match db.query(
+                &format!(
+                    "
+            SELECT {}
+            FROM payout_proposal
+            WHERE Repository=$1
+                AND ($2::INTEGER IS NULL OR PullRequest=$2)
+                AND ($3::VARCHAR IS NULL OR Status=$3)
+            ORDER BY ID DESC
+            ",
+                    PROPOSAL_COLUMNS
+                ),
+                &[&repository, &pull_request, &status],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            Ok(rows.iter().map(|row| Proposal::from_row(&row)).collect())
+        }
+
+        // decide approves or rejects an open proposal on behalf of the
+        // maintainer. A proposal is decided only once
+        pub fn decide(
+            &mut self,
+            db: &postgres::Connection,
+            status: &str,
+            maintainer: &str,
+        ) -> Result<(), Error> {
+            let rows = match db.query(
+                &format!(
+                    "
+            UPDATE payout_proposal
+            SET Status=$1, DecidedBy=$2, DecidedAt=now()
+            WHERE ID=$3 AND Status=$4
+            RETURNING {}
+            ",
+                    PROPOSAL_COLUMNS
+                ),
+                &[&status, &maintainer, &self.id, &PROPOSAL_OPEN],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                *self = Proposal::from_row(&row);
+                return Ok(());
+            }
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("payout proposal {} is not open", self.id),
+            ))
+        }
+    }
+}
@@ -0,0 +1,161 @@
+pub mod repository {
+    use rocket_contrib::databases::postgres;
+    use std::io::{Error, ErrorKind};
+    use std::str::FromStr;
+
+    // FundingMode decides where the rewards of a repository are paid from
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum FundingMode {
+        // Treasury is the pool of the repository in the project treasury,
+        // funded by donations. Nothing is paid beyond the pool
+        Treasury,
+        // Author is the pull request author's allowance approved to the
+        // backend with approve_checked
+        Author,
+        // Sponsor is a sponsor pool token account that approved the backend
+        Sponsor,
+    }
+
+    impl FundingMode {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                FundingMode::Treasury => "treasury",
+                FundingMode::Author => "author",
+                FundingMode::Sponsor => "sponsor",
+            }
+        }
+    }
+
+    impl FromStr for FundingMode {
+        type Err = Error;
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "treasury" => Ok(FundingMode::Treasury),
+                "author" => Ok(FundingMode::Author),
+                "sponsor" => Ok(FundingMode::Sponsor),
+                _ => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown funding mode {}", s),
+                )),
+            }
+        }
+    }
+
+    // Repository is a repository where the GitHub app is installed
+    pub struct Repository {
+        pub full_name: String,
+        // app_id is the GitHub App the repository is installed with
+        pub app_id: String,
+        pub installation_id: i64,
+        pub funding_mode: FundingMode,
+        pub sponsor_account: Option<String>,
+    }
+
+    impl Repository {
+        pub fn new(full_name: &str, app_id: &str, installation_id: usize) -> Repository {
+            Repository {
+                full_name: full_name.to_string(),
+                app_id: app_id.to_string(),
+                installation_id: installation_id as i64,
+                funding_mode: FundingMode::Treasury,
+                sponsor_account: None,
+            }
+        }
+
+        // create_or_update_installation stores the repository and keeps
+        // the installation id up to date. A repository stays bound to the
+        // first configured app it was installed with, deliveries of other
+        // apps return false and change nothing. It is bound anew when its
+        // app is no longer among app_ids. The funding settings are left
+        // untouched for repositories that already exist
+        pub fn create_or_update_installation(
+            &self,
+            db: &postgres::Connection,
+            app_ids: &[&str],
+        ) -> Result<bool, Error> {
+            let query_result = db.execute(
+                "
+            INSERT INTO repository (FullName, AppID, InstallationID, FundingMode)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (FullName) DO UPDATE
+            SET AppID = EXCLUDED.AppID, InstallationID = EXCLUDED.InstallationID
+            WHERE repository.AppID = EXCLUDED.AppID OR repository.AppID <> ALL($5)
+            ",
+                &[
+                    &self.full_name,
+                    &self.app_id,
+                    &self.installation_id,
+                    &self.funding_mode.as_str(),
+                    &app_ids,
+                ],
+            );
+
+            match query_result {
+                Ok(updated) => Ok(updated == 1),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        pub fn get(db: &postgres::Connection, full_name: &str) -> Result<Repository, Error> {
+            let rows = match db.query(
+                "
+            SELECT FullName, AppID, InstallationID, FundingMode, SponsorAccount
+            FROM repository
+            WHERE FullName=$1
+            ",
+                &[&full_name],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+
+            for row in &rows {
+                let funding_mode: String = row.get("FundingMode");
+                return Ok(Repository {
+                    full_name: row.get("FullName"),
+                    app_id: row.get("AppID"),
+                    installation_id: row.get("InstallationID"),
+                    funding_mode: FundingMode::from_str(&funding_mode)?,
+                    sponsor_account: row.get("SponsorAccount"),
+                });
+            }
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!("repository {} is not installed", full_name),
+            ))
+        }
+
+        pub fn set_funding(
+            &mut self,
+            db: &postgres::Connection,
+            funding_mode: FundingMode,
+            sponsor_account: Option<String>,
+        ) -> Result<(), Error> {
+            if funding_mode == FundingMode::Sponsor && sponsor_account.is_none() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    String::from("sponsor funding requires a sponsor account"),
+                ));
+            }
+            self.funding_mode = funding_mode;
+            self.sponsor_account = sponsor_account;
+            let query_res = db.execute(
+                "
+            UPDATE repository
+            SET FundingMode=$1, SponsorAccount=$2
+            WHERE FullName=$3
+            ",
+                &[
+                    &self.funding_mode.as_str(),
+                    &self.sponsor_account,
+                    &self.full_name,
+                ],
+            );
+
+            match query_res {
+                Ok(_) => return Ok(()),
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+    }
+}
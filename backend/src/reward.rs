@@ -0,0 +1,119 @@
+pub mod reward {
+    use rocket_contrib::databases::postgres;
+    use std::io::{Error, ErrorKind};
+
+    // PullRequestReward overrides the max reward of the repository policy
+    // for a single pull request
+    pub struct PullRequestReward {
+        pub repository: String,
+        pub pull_request: i32,
+        pub max_reward: f64,
+    }
+
+    impl PullRequestReward {
+        pub fn new(repository: &str, pull_request: i32, max_reward: f64) -> PullRequestReward {
+            PullRequestReward {
+                repository: repository.to_string(),
+                pull_request,
+                max_reward,
+            }
+        }
+
+        pub fn get(
+            db: &postgres::Connection,
+            repository: &str,
+            pull_request: i32,
+        ) -> Result<Option<PullRequestReward>, Error> {
+            let rows = match db.query(
+                "
+            SELECT MaxReward
+            FROM pull_request_reward
+            WHERE Repository=$1 AND PullRequest=$2
+            ",
+                &[&repository, &pull_request],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                return Ok(Some(PullRequestReward::new(
+                    repository,
+                    pull_request,
+                    row.get("MaxReward"),
+                )));
+            }
+            Ok(None)
+        }
+
+        // validate checks that the override is a finite amount between 0
+        // and the largest override the deployment allows
+        fn validate(&self, limit: f64) -> Result<(), Error> {
+            if !self.max_reward.is_finite() || self.max_reward < 0.0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    String::from("max_reward must be a non negative number"),
+                ));
+            }
+            if self.max_reward > limit {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("max_reward must not exceed {}", limit),
+                ));
+            }
+            Ok(())
+        }
+
+        // set stores the override, replacing an earlier one. The override
+        // may not exceed limit
+        pub fn set(&self, db: &postgres::Connection, set_by: i32, limit: f64) -> Result<(), Error> {
+            self.validate(limit)?;
+            let query_res = db.execute(
+                "
+            INSERT INTO pull_request_reward (Repository, PullRequest, MaxReward, SetBy)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (Repository, PullRequest)
+            DO UPDATE SET MaxReward=EXCLUDED.MaxReward, SetBy=EXCLUDED.SetBy, UpdatedAt=now()
+            ",
+                &[
+                    &self.repository,
+                    &self.pull_request,
+                    &self.max_reward,
+                    &set_by,
+                ],
+            );
+
+            match query_res {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn validate_accepts_amounts_up_to_the_limit() {
+            assert!(PullRequestReward::new("acme/widgets", 1, 0.0)
+                .validate(100.0)
+                .is_ok());
+            assert!(PullRequestReward::new("acme/widgets", 1, 100.0)
+                .validate(100.0)
+                .is_ok());
+        }
+
+        #[test]
+        fn validate_rejects_invalid_amounts() {
+            for max_reward in &[-1.0, 100.5, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+                assert!(
+                    PullRequestReward::new("acme/widgets", 1, *max_reward)
+                        .validate(100.0)
+                        .is_err(),
+                    "{}",
+                    max_reward
+                );
+            }
+        }
+    }
+}
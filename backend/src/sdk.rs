@@ -1,118 +1,821 @@
-use rithub::api::api::{self, ReviewComment};
-use rithub::app::app;
+use crate::handlers::Config;
+use prometheus::IntCounterVec;
 use rithub::error::errors::Error;
-use rocket_contrib::databases::postgres;
-use solana_sdk::instruction::AccountMeta;
-use std::borrow::Borrow;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-
-use crate::user::user::User;
-use rithub::webhook::webhook::WebhookRequest;
-use rocket_contrib::database;
 use solana_client::rpc_client::RpcClient;
+use solana_sdk::instruction::AccountMeta;
 use solana_sdk::{
-    commitment_config::CommitmentConfig,
+    commitment_config::{CommitmentConfig, CommitmentLevel},
     instruction::Instruction,
     message::Message,
-    native_token::*,
+    native_token::lamports_to_sol,
+    packet::PACKET_DATA_SIZE,
     program_option::COption,
     program_pack::Pack,
     pubkey::Pubkey,
-    signature::{Keypair, Signer},
-    system_instruction, system_program, sysvar,
+    signature::{Keypair, Signature, Signer},
+    system_program, sysvar,
     transaction::Transaction,
 };
-use spl_token::state::Account;
+use solana_transaction_status::{UiTransactionEncoding, UiTransactionTokenBalance};
+use spl_token::instruction::TokenInstruction;
+use spl_token::state::{Account, Mint};
+use std::fs::File;
+use std::io::BufReader;
+use std::str::FromStr;
 
-fn read_json_array(fname: &str) -> Vec<u8> {
-    let file = File::open(fname).expect("no such filename");
+// read_json_array reads a keypair file in the format written by
+// solana-keygen, a json array of bytes
+fn read_json_array(fname: &str) -> Result<Vec<u8>, Error> {
+    let file = match File::open(fname) {
+        Ok(file) => file,
+        Err(err) => return Err(Error::new(500, format!("{}: {}", fname, err))),
+    };
     let buf = BufReader::new(file);
-    let newbu: Vec<u8> = buf
-        .lines()
-        .map(|l| l.expect("could not parse line").parse::<u8>().unwrap())
-        .collect();
-    newbu
+    match serde_json::from_reader::<_, Vec<u8>>(buf) {
+        Ok(bytes) => Ok(bytes),
+        Err(err) => Err(Error::new(500, format!("{}: {}", fname, err))),
+    }
 }
 
-struct NewAccount;
+// parse_pubkey parses a base58 encoded address
+pub fn parse_pubkey(addr: &str) -> Result<Pubkey, Error> {
+    match Pubkey::from_str(addr) {
+        Ok(pubkey) => Ok(pubkey),
+        Err(err) => Err(Error::new(
+            400,
+            format!("invalid address {}: {}", addr, err),
+        )),
+    }
+}
+
+// new_reference returns a random address a transfer can include to be
+// found on chain later
+pub fn new_reference() -> String {
+    Keypair::new().pubkey().to_string()
+}
 
-// create_account creates a new account for a user
-// based on their wallet address
-pub fn create_account(owner_addr: &str, addr: &str) -> Result<(), Error> {
-    let client = RpcClient::new(String::from("http://localhost::8899"));
+// Receipt describes a submitted transfer. A transfer that was not
+// confirmed may still land on chain under its signature
+#[derive(Debug)]
+pub struct Receipt {
+    pub signature: String,
+    pub token_account: String,
+    pub rent_lamports: u64,
+    pub confirmed: bool,
+}
 
-    let mint_authority = "2J3WneHkTnoxac83xAazUXKZ5JbYmt3Be4CPbnEu2i1s";
-    let token_program_id = "DHZypXyN9vRh24S8UgP37DeQ9dpCndDVCTQMMDvuNX8g";
-    let owner_pubkey = Pubkey::new(owner_addr.as_bytes());
+// Deposit is a confirmed transfer of OCT into the treasury token account
+#[derive(Debug)]
+pub struct Deposit {
+    pub signature: String,
+    pub amount: u64,
+}
 
-    let create_account = system_instruction::create_account(
-        &owner_pubkey,
-        &Pubkey::new(addr.as_bytes()),
-        0,
-        Account::LEN as u64,
-        &Pubkey::new(token_program_id.as_bytes()),
-    );
+// token_amount returns the amount held by the account at the index of
+// the transaction before or after it ran
+fn token_amount(
+    balances: &Option<Vec<UiTransactionTokenBalance>>,
+    index: usize,
+    mint: &str,
+) -> u64 {
+    balances
+        .iter()
+        .flatten()
+        .find(|balance| balance.account_index as usize == index && balance.mint == mint)
+        .and_then(|balance| balance.ui_token_amount.amount.parse().ok())
+        .unwrap_or(0)
+}
 
-    let message = Message::new(&[create_account], Some(&owner_pubkey));
-    let transaction = Transaction::new_unsigned(message);
-    let signature = match client.send_transaction(&transaction) {
-        Ok(res) => res,
-        Err(err) => return Err(Error::new(501, err.to_string())),
-    };
+// Transfer is a single reward to be paid out in a batch
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub recipient: String,
+    pub amount: u64,
+}
 
-    println!("signature: {}", signature);
+// FundingSource is where the tokens of a payout come from.
+// The treasury always signs, either as owner of its own account
+// or as the approved delegate of someone else's account
+pub enum FundingSource {
+    // Treasury pays from its own associated token account
+    Treasury,
+    // Delegate pays from the owner's associated token account using
+    // the allowance approved to the treasury with approve_checked
+    Delegate(Pubkey),
+    // Account pays from a sponsor token account that approved the
+    // treasury as delegate
+    Account(Pubkey),
+}
 
-    Ok(())
+// PackedTransfer holds the instructions of one transfer while
+// transactions are being packed
+struct PackedTransfer {
+    index: usize,
+    instructions: Vec<Instruction>,
+    token_account: Pubkey,
+    rent_lamports: u64,
 }
 
-#[derive(Debug, Clone)]
-struct TransferChecked {
-    /// The amount of tokens to transfer.
-    amount: u64,
-    /// Expected number of base 10 digits to the right of the decimal place.
+// fits_in_transaction checks that the instructions can be sent as a
+// single transaction paid by the fee payer without exceeding the packet
+// size
+fn fits_in_transaction(instructions: &[Instruction], fee_payer: &Pubkey) -> bool {
+    let message = Message::new(instructions, Some(fee_payer));
+    let transaction = Transaction::new_unsigned(message);
+    match bincode::serialized_size(&transaction) {
+        Ok(size) => size as usize <= PACKET_DATA_SIZE,
+        Err(_) => false,
+    }
+}
+
+// pack_transfers packs the transfers in order into as few transactions
+// as fit. A transfer too large for a transaction of its own still gets
+// one, sending it fails
+fn pack_transfers(pending: Vec<PackedTransfer>, fee_payer: &Pubkey) -> Vec<Vec<PackedTransfer>> {
+    let mut batches: Vec<Vec<PackedTransfer>> = vec![];
+    let mut batch: Vec<PackedTransfer> = vec![];
+    for packed in pending {
+        let mut instructions: Vec<Instruction> =
+            batch.iter().flat_map(|p| p.instructions.clone()).collect();
+        instructions.extend(packed.instructions.clone());
+        if !batch.is_empty() && !fits_in_transaction(&instructions, fee_payer) {
+            batches.push(batch);
+            batch = vec![];
+        }
+        batch.push(packed);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+// Sdk holds the cluster connection and the OCT mint settings
+// used when moving tokens on behalf of the backend
+pub struct Sdk {
+    client: RpcClient,
+    pub ws_url: String,
+    mint: Pubkey,
+    token_program_id: Pubkey,
+    treasury: Keypair,
     decimals: u8,
+    transactions: IntCounterVec,
 }
 
-pub fn transfer_token(from_addr: &str, to_addr: &str, amount: u64) -> Result<(), Error> {
-    let client = RpcClient::new(String::from("http://localhost::8899"));
-    let from_pubkey = Pubkey::new(from_addr.as_bytes());
-    let to_pubkey = Pubkey::new(to_addr.as_bytes());
+impl Sdk {
+    // new connects to the configured cluster and validates that the
+    // OCT mint exists and has the configured number of decimals. Sent
+    // transactions are counted by outcome in the given counter
+    pub fn new(cfg: &Config, transactions: IntCounterVec) -> Result<Sdk, Error> {
+        let commitment = match CommitmentLevel::from_str(&cfg.solana_commitment) {
+            Ok(level) => CommitmentConfig { commitment: level },
+            Err(_) => {
+                return Err(Error::new(
+                    500,
+                    format!("invalid commitment level {}", cfg.solana_commitment),
+                ))
+            }
+        };
+        let client = RpcClient::new_with_commitment(cfg.solana_rpc_url.clone(), commitment);
+        let mint = parse_pubkey(&cfg.oct_mint_address)?;
+        let token_program_id = parse_pubkey(&cfg.oct_token_program_id)?;
+        let treasury_bytes = read_json_array(&cfg.treasury_keypair_path)?;
+        let treasury = match Keypair::from_bytes(&treasury_bytes) {
+            Ok(keypair) => keypair,
+            Err(err) => {
+                return Err(Error::new(
+                    500,
+                    format!("invalid treasury keypair: {}", err),
+                ))
+            }
+        };
 
-    let from_balance = match client.get_balance(&from_pubkey) {
-        Ok(res) => res,
-        Err(err) => return Err(Error::new(500, err.to_string())),
-    };
+        let sdk = Sdk {
+            client,
+            ws_url: cfg.solana_ws_url.clone(),
+            mint,
+            token_program_id,
+            treasury,
+            decimals: cfg.oct_decimals,
+            transactions,
+        };
+        sdk.validate_mint()?;
+        Ok(sdk)
+    }
 
-    if from_addr.eq(to_addr) {
-        return Err(Error::new(
-            500,
-            String::from("should avoid transfer to itself"),
-        ));
+    // validate_mint makes sure the mint is owned by the token program
+    // and that the decimals match the configuration. It fails when the
+    // RPC node cannot be reached
+    pub fn validate_mint(&self) -> Result<(), Error> {
+        let account = match self.client.get_account(&self.mint) {
+            Ok(account) => account,
+            Err(err) => {
+                return Err(Error::new(
+                    500,
+                    format!("could not fetch mint {}: {}", self.mint, err),
+                ))
+            }
+        };
+        if account.owner != self.token_program_id {
+            return Err(Error::new(
+                500,
+                format!(
+                    "mint {} is owned by {}, expected {}",
+                    self.mint, account.owner, self.token_program_id
+                ),
+            ));
+        }
+        let mint = match Mint::unpack(&account.data) {
+            Ok(mint) => mint,
+            Err(err) => {
+                return Err(Error::new(
+                    500,
+                    format!("could not decode mint {}: {}", self.mint, err),
+                ))
+            }
+        };
+        if mint.decimals != self.decimals {
+            return Err(Error::new(
+                500,
+                format!(
+                    "mint {} has {} decimals, configured {}",
+                    self.mint, mint.decimals, self.decimals
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn treasury_pubkey(&self) -> Pubkey {
+        self.treasury.pubkey()
     }
 
-    if from_balance < amount {
-        return Err(Error::new(500, String::from("not enough funds")));
+    // treasury_token_account is the token account donations are sent to
+    pub fn treasury_token_account(&self) -> Pubkey {
+        self.associated_token_address(&self.treasury.pubkey())
     }
 
-    let token_program_id = Pubkey::new("DHZypXyN9vRh24S8UgP37DeQ9dpCndDVCTQMMDvuNX8g".as_bytes());
+    pub fn mint(&self) -> Pubkey {
+        self.mint
+    }
+
+    // find_deposit looks for the first successful transaction that
+    // references the account and returns the OCT it added to the
+    // treasury token account
+    pub fn find_deposit(&self, reference: &Pubkey) -> Result<Option<Deposit>, Error> {
+        let statuses = match self.client.get_signatures_for_address(reference) {
+            Ok(statuses) => statuses,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        let treasury_account = self.treasury_token_account();
+        let mint = self.mint.to_string();
+        // Signatures are returned newest first
+        for status in statuses.iter().rev() {
+            if status.err.is_some() {
+                continue;
+            }
+            let signature = match Signature::from_str(&status.signature) {
+                Ok(signature) => signature,
+                Err(err) => return Err(Error::new(500, err.to_string())),
+            };
+            let transaction = match self
+                .client
+                .get_transaction(&signature, UiTransactionEncoding::Base64)
+            {
+                Ok(transaction) => transaction.transaction,
+                Err(err) => return Err(Error::new(500, err.to_string())),
+            };
+            let meta = match transaction.meta {
+                Some(meta) if meta.err.is_none() => meta,
+                _ => continue,
+            };
+            let index = match transaction.transaction.decode().and_then(|decoded| {
+                decoded
+                    .message
+                    .account_keys
+                    .iter()
+                    .position(|key| *key == treasury_account)
+            }) {
+                Some(index) => index,
+                None => continue,
+            };
+            let before = token_amount(&meta.pre_token_balances, index, &mint);
+            let after = token_amount(&meta.post_token_balances, index, &mint);
+            if after > before {
+                return Ok(Some(Deposit {
+                    signature: status.signature.clone(),
+                    amount: after - before,
+                }));
+            }
+        }
+        Ok(None)
+    }
 
-    let mut accounts = Vec::new();
-    let account_meta = AccountMeta::new(to_pubkey, false);
-    accounts.push(account_meta);
-    let amount: u64 = 10;
-    let decimals: u8 = 0;
+    // to_base_units converts an amount of OCT to the smallest unit of the mint
+    pub fn to_base_units(&self, amount: f64) -> u64 {
+        spl_token::ui_amount_to_amount(amount, self.decimals)
+    }
 
-    let data = TransferChecked { amount, decimals };
-    let mut buf = Vec::with_capacity(std::mem::size_of::<TransferChecked>());
-    buf.push(12);
-    buf.extend_from_slice(&data.amount.to_le_bytes());
-    buf.push(decimals);
+    // associated_token_address derives the associated token account
+    // of a wallet for the OCT mint
+    pub fn associated_token_address(&self, wallet: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                &wallet.to_bytes(),
+                &self.token_program_id.to_bytes(),
+                &self.mint.to_bytes(),
+            ],
+            &spl_associated_token_account::id(),
+        )
+        .0
+    }
 
-    let instruction = Instruction::new_with_bincode(token_program_id, &buf, accounts);
-    let message = Message::new(&[instruction], Some(&from_pubkey));
-    let transaction = Transaction::new_unsigned(message);
-    client.send_transaction(&transaction);
+    // create_associated_token_account_instruction creates the associated
+    // token account of the wallet, funded by the treasury
+    fn create_associated_token_account_instruction(&self, wallet: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: spl_associated_token_account::id(),
+            accounts: vec![
+                AccountMeta::new(self.treasury.pubkey(), true),
+                AccountMeta::new(self.associated_token_address(wallet), false),
+                AccountMeta::new_readonly(*wallet, false),
+                AccountMeta::new_readonly(self.mint, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(self.token_program_id, false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ],
+            data: vec![],
+        }
+    }
+
+    // recipient_token_account resolves the associated token account of
+    // the recipient. If it does not exist yet the instruction creating it
+    // is returned together with the rent the treasury pays for it
+    fn recipient_token_account(
+        &self,
+        wallet: &Pubkey,
+    ) -> Result<(Pubkey, Option<Instruction>, u64), Error> {
+        let token_account = self.associated_token_address(wallet);
+        let account = match self
+            .client
+            .get_account_with_commitment(&token_account, self.client.commitment())
+        {
+            Ok(res) => res.value,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+
+        let needs_funding = match account {
+            Some(account) => {
+                if account.owner == system_program::id() {
+                    true
+                } else if account.owner == self.token_program_id {
+                    false
+                } else {
+                    return Err(Error::new(
+                        500,
+                        format!("unsupported recipient address: {}", wallet),
+                    ));
+                }
+            }
+            None => true,
+        };
+        if !needs_funding {
+            return Ok((token_account, None, 0));
+        }
+
+        let rent = match self
+            .client
+            .get_minimum_balance_for_rent_exemption(Account::LEN)
+        {
+            Ok(rent) => rent,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        log::info!(
+            "sdk.recipient_token_account.fund: {} ({} SOL)",
+            token_account,
+            lamports_to_sol(rent)
+        );
+        Ok((
+            token_account,
+            Some(self.create_associated_token_account_instruction(wallet)),
+            rent,
+        ))
+    }
 
-    Ok(())
+    // transfer_checked_instruction builds a TransferChecked instruction
+    // against the configured token program
+    fn transfer_checked_instruction(
+        &self,
+        source: &Pubkey,
+        destination: &Pubkey,
+        authority: &Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: self.token_program_id,
+            accounts: vec![
+                AccountMeta::new(*source, false),
+                AccountMeta::new_readonly(self.mint, false),
+                AccountMeta::new(*destination, false),
+                AccountMeta::new_readonly(*authority, true),
+            ],
+            data: TokenInstruction::TransferChecked {
+                amount,
+                decimals: self.decimals,
+            }
+            .pack(),
+        }
+    }
+
+    // token_balance returns the token amount held by a token account
+    fn token_balance(&self, token_account: &Pubkey) -> Result<u64, Error> {
+        let balance = match self.client.get_token_account_balance(token_account) {
+            Ok(res) => res,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        match balance.amount.parse::<u64>() {
+            Ok(amount) => Ok(amount),
+            Err(err) => Err(Error::new(500, err.to_string())),
+        }
+    }
+
+    // wallet_balance returns the OCT held by a wallet. A wallet without
+    // an associated token account holds nothing
+    pub fn wallet_balance(&self, wallet: &Pubkey) -> Result<u64, Error> {
+        let token_account = self.associated_token_address(wallet);
+        let account = match self
+            .client
+            .get_account_with_commitment(&token_account, self.client.commitment())
+        {
+            Ok(res) => res.value,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        match account {
+            Some(account) if account.owner == self.token_program_id => {
+                self.token_balance(&token_account)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    // to_ui_amount converts base units of the mint to OCT
+    pub fn to_ui_amount(&self, amount: u64) -> f64 {
+        spl_token::amount_to_ui_amount(amount, self.decimals)
+    }
+
+    // send signs the instructions with the treasury as fee payer and
+    // waits for confirmation. It returns the signature and whether the
+    // transaction was confirmed, one that was not may still land later
+    fn send(
+        &self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(String, bool), Error> {
+        let (recent_blockhash, _) = match self.client.get_recent_blockhash() {
+            Ok(res) => res,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        let message = Message::new(instructions, Some(&self.treasury.pubkey()));
+        let mut transaction = Transaction::new_unsigned(message);
+        if let Err(err) = transaction.try_sign(&signers.to_vec(), recent_blockhash) {
+            return Err(Error::new(500, err.to_string()));
+        }
+        let signature = transaction.signatures[0].to_string();
+        match self.client.send_and_confirm_transaction(&transaction) {
+            Ok(_) => {
+                self.transactions.with_label_values(&["success"]).inc();
+                Ok((signature, true))
+            }
+            Err(err) => {
+                self.transactions.with_label_values(&["fail"]).inc();
+                log::error!(
+                    "sdk.send.unconfirmed. Signature: {} Cause: {}",
+                    signature,
+                    err
+                );
+                Ok((signature, false))
+            }
+        }
+    }
+
+    // transaction_status tells whether the transaction with the signature
+    // succeeded on chain. None means the cluster does not know it
+    pub fn transaction_status(&self, signature: &str) -> Result<Option<bool>, Error> {
+        let signature = match Signature::from_str(signature) {
+            Ok(signature) => signature,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        match self
+            .client
+            .get_signature_status_with_commitment_and_history(
+                &signature,
+                self.client.commitment(),
+                true,
+            ) {
+            Ok(status) => Ok(status.map(|result| result.is_ok())),
+            Err(err) => Err(Error::new(500, err.to_string())),
+        }
+    }
+
+    // source_token_account resolves the token account the funding source
+    // pays from and checks that it holds enough tokens for the total.
+    // Delegated sources must have approved the treasury for at least
+    // the total amount
+    fn source_token_account(&self, source: &FundingSource, total: u64) -> Result<Pubkey, Error> {
+        let token_account = match source {
+            FundingSource::Treasury => {
+                let token_account = self.treasury_token_account();
+                if self.token_balance(&token_account)? < total {
+                    return Err(Error::new(500, String::from("not enough funds")));
+                }
+                return Ok(token_account);
+            }
+            FundingSource::Delegate(owner) => self.associated_token_address(owner),
+            FundingSource::Account(token_account) => *token_account,
+        };
+
+        let account = match self.client.get_account(&token_account) {
+            Ok(account) => account,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        let account = match Account::unpack(&account.data) {
+            Ok(account) => account,
+            Err(err) => return Err(Error::new(500, err.to_string())),
+        };
+        if account.mint != self.mint {
+            return Err(Error::new(
+                500,
+                format!("{} does not hold OCT", token_account),
+            ));
+        }
+        if account.delegate != COption::Some(self.treasury.pubkey()) {
+            return Err(Error::new(
+                403,
+                format!(
+                    "{} has not approved {} as delegate",
+                    token_account,
+                    self.treasury.pubkey()
+                ),
+            ));
+        }
+        if account.delegated_amount < total {
+            return Err(Error::new(
+                403,
+                format!(
+                    "delegated amount {} is less than {}",
+                    account.delegated_amount, total
+                ),
+            ));
+        }
+        if account.amount < total {
+            return Err(Error::new(500, String::from("not enough funds")));
+        }
+        Ok(token_account)
+    }
+
+    // transfer_token sends OCT from the funding source to the associated
+    // token account of a wallet. The recipient's account is created in
+    // the same transaction when missing
+    pub fn transfer_token(
+        &self,
+        source: &FundingSource,
+        to_addr: &str,
+        amount: u64,
+    ) -> Result<Receipt, Error> {
+        let transfer = Transfer {
+            recipient: to_addr.to_string(),
+            amount,
+        };
+        match self.transfer_batch(source, &[transfer]).pop() {
+            Some(result) => result,
+            None => Err(Error::new(500, String::from("no transfer was made"))),
+        }
+    }
+
+    // transfer_batch packs the transfers into as few transactions as
+    // possible. Each transaction holds as many create account and
+    // transfer_checked instructions as fit in a packet. The result is
+    // returned per transfer in the same order as the input
+    pub fn transfer_batch(
+        &self,
+        source: &FundingSource,
+        transfers: &[Transfer],
+    ) -> Vec<Result<Receipt, Error>> {
+        let mut results: Vec<Result<Receipt, Error>> = transfers
+            .iter()
+            .map(|_| Err(Error::new(500, String::from("transfer not attempted"))))
+            .collect();
+
+        let total = match transfer_total(transfers) {
+            Some(total) => total,
+            None => return fail_all(transfers, "transfer total overflows"),
+        };
+        let source_account = match self.source_token_account(source, total) {
+            Ok(token_account) => token_account,
+            Err(err) => return fail_all(transfers, &err.message),
+        };
+
+        // Instructions for each transfer are kept together so a recipient's
+        // account creation never ends up in another transaction
+        let mut pending: Vec<PackedTransfer> = vec![];
+        for (index, transfer) in transfers.iter().enumerate() {
+            let to_pubkey = match parse_pubkey(&transfer.recipient) {
+                Ok(pubkey) => pubkey,
+                Err(err) => {
+                    results[index] = Err(err);
+                    continue;
+                }
+            };
+            let (destination, create_instruction, rent_lamports) =
+                match self.recipient_token_account(&to_pubkey) {
+                    Ok(res) => res,
+                    Err(err) => {
+                        results[index] = Err(err);
+                        continue;
+                    }
+                };
+            if destination == source_account {
+                results[index] = Err(Error::new(
+                    500,
+                    String::from("should avoid transfer to itself"),
+                ));
+                continue;
+            }
+            let mut instructions = vec![];
+            if let Some(create_instruction) = create_instruction {
+                instructions.push(create_instruction);
+            }
+            instructions.push(self.transfer_checked_instruction(
+                &source_account,
+                &destination,
+                &self.treasury.pubkey(),
+                transfer.amount,
+            ));
+            pending.push(PackedTransfer {
+                index,
+                instructions,
+                token_account: destination,
+                rent_lamports,
+            });
+        }
+
+        for batch in pack_transfers(pending, &self.treasury.pubkey()) {
+            self.send_batch(&batch, &mut results);
+        }
+        results
+    }
+
+    // send_batch sends one packed transaction and records the outcome
+    // for every transfer in it
+    fn send_batch(&self, batch: &[PackedTransfer], results: &mut Vec<Result<Receipt, Error>>) {
+        let instructions: Vec<Instruction> =
+            batch.iter().flat_map(|p| p.instructions.clone()).collect();
+        if !fits_in_transaction(&instructions, &self.treasury.pubkey()) {
+            for packed in batch {
+                results[packed.index] = Err(Error::new(
+                    500,
+                    String::from("transfer does not fit in a transaction"),
+                ));
+            }
+            return;
+        }
+
+        match self.send(&instructions, &[&self.treasury]) {
+            Ok((signature, confirmed)) => {
+                log::info!(
+                    "sdk.transfer_batch.signature: {} transfers: {} confirmed: {}",
+                    signature,
+                    batch.len(),
+                    confirmed
+                );
+                for packed in batch {
+                    results[packed.index] = Ok(Receipt {
+                        signature: signature.clone(),
+                        token_account: packed.token_account.to_string(),
+                        rent_lamports: packed.rent_lamports,
+                        confirmed,
+                    });
+                }
+            }
+            Err(err) => {
+                log::error!("sdk.transfer_batch.fail. Cause: {}", err.message);
+                for packed in batch {
+                    results[packed.index] = Err(Error::new(500, err.message.clone()));
+                }
+            }
+        }
+    }
+}
+
+// transfer_total sums the amounts of the transfers, None when the sum
+// does not fit in a u64
+fn transfer_total(transfers: &[Transfer]) -> Option<u64> {
+    transfers
+        .iter()
+        .try_fold(0u64, |total, transfer| total.checked_add(transfer.amount))
+}
+
+fn fail_all(transfers: &[Transfer], message: &str) -> Vec<Result<Receipt, Error>> {
+    transfers
+        .iter()
+        .map(|_| Err(Error::new(500, message.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // transfer is a transfer to a new token account, with the instruction
+    // creating it when create is set
+    fn transfer(index: usize, create: bool) -> PackedTransfer {
+        let destination = Pubkey::new_unique();
+        let mut instructions = vec![];
+        if create {
+            instructions.push(Instruction {
+                program_id: Pubkey::new_unique(),
+                accounts: vec![
+                    AccountMeta::new(Pubkey::new_unique(), true),
+                    AccountMeta::new(destination, false),
+                    AccountMeta::new_readonly(Pubkey::new_unique(), false),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                    AccountMeta::new_readonly(sysvar::rent::id(), false),
+                ],
+                data: vec![],
+            });
+        }
+        instructions.push(Instruction {
+            program_id: spl_token::id(),
+            accounts: vec![
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new(destination, false),
+            ],
+            data: vec![0; 10],
+        });
+        PackedTransfer {
+            index,
+            instructions,
+            token_account: destination,
+            rent_lamports: 0,
+        }
+    }
+
+    fn indexes(batches: &[Vec<PackedTransfer>]) -> Vec<Vec<usize>> {
+        batches
+            .iter()
+            .map(|batch| batch.iter().map(|packed| packed.index).collect())
+            .collect()
+    }
+
+    #[test]
+    fn fits_in_transaction_checks_the_packet_size() {
+        let fee_payer = Pubkey::new_unique();
+        assert!(fits_in_transaction(
+            &transfer(0, true).instructions,
+            &fee_payer
+        ));
+        let instructions: Vec<Instruction> = (0..40)
+            .flat_map(|index| transfer(index, false).instructions)
+            .collect();
+        assert!(!fits_in_transaction(&instructions, &fee_payer));
+    }
+
+    #[test]
+    fn pack_transfers_fills_transactions_in_order() {
+        let fee_payer = Pubkey::new_unique();
+        let pending: Vec<PackedTransfer> = (0..40)
+            .map(|index| transfer(index, index % 3 == 0))
+            .collect();
+        let batches = pack_transfers(pending, &fee_payer);
+        assert!(batches.len() > 1);
+        let flattened: Vec<usize> = indexes(&batches).into_iter().flatten().collect();
+        assert_eq!(flattened, (0..40).collect::<Vec<usize>>());
+        for batch in &batches {
+            let instructions: Vec<Instruction> =
+                batch.iter().flat_map(|p| p.instructions.clone()).collect();
+            assert!(fits_in_transaction(&instructions, &fee_payer));
+        }
+    }
+
+    #[test]
+    fn pack_transfers_keeps_small_batches_together() {
+        let fee_payer = Pubkey::new_unique();
+        let pending: Vec<PackedTransfer> = (0..2).map(|index| transfer(index, true)).collect();
+        assert_eq!(
+            indexes(&pack_transfers(pending, &fee_payer)),
+            vec![vec![0, 1]]
+        );
+        assert!(pack_transfers(vec![], &fee_payer).is_empty());
+    }
+
+    #[test]
+    fn transfer_total_rejects_overflow() {
+        let transfer = |amount| Transfer {
+            recipient: String::from(""),
+            amount,
+        };
+        assert_eq!(transfer_total(&[]), Some(0));
+        assert_eq!(transfer_total(&[transfer(2), transfer(3)]), Some(5));
+        assert_eq!(transfer_total(&[transfer(u64::MAX), transfer(1)]), None);
+    }
 }
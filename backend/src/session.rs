@@ -0,0 +1,160 @@
+pub mod session {
+    use crate::handlers;
+    use crate::lib::crypto::crypto::random_token;
+    use crate::lib::web_error::WebError::WebError;
+    use crate::user::user::User;
+    use authorization::jwt_authentication::Jwt;
+    use rocket::http::Status;
+    use rocket::request::{FromRequest, Outcome, Request};
+    use rocket::State;
+    use rocket_contrib::databases::postgres;
+    use std::io::{Error, ErrorKind};
+
+    pub const SESSION_COOKIE: &str = "session";
+
+    // Session is a server side login session. The session id is carried
+    // in the JWT so a session can be revoked before the token expires
+    pub struct Session {
+        pub id: String,
+        pub user_id: i32,
+    }
+
+    impl Session {
+        pub fn create(
+            db: &postgres::Connection,
+            user_id: i32,
+            ttl_seconds: i64,
+        ) -> Result<Session, Error> {
+            let id = random_token(32)?;
+            let query_res = db.execute(
+                "
+            INSERT INTO session (ID, UserID, ExpiresAt)
+            VALUES ($1, $2, now() + make_interval(secs => $3))
+            ",
+                &[&id, &user_id, &(ttl_seconds as f64)],
+            );
+
+            match query_res {
+                Ok(_) => Ok(Session { id, user_id }),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        // is_active checks that the session belongs to the user, has not
+        // expired and has not been revoked
+        pub fn is_active(db: &postgres::Connection, id: &str, user_id: i32) -> Result<bool, Error> {
+            let query_res = db.query(
+                "
+            SELECT ID
+            FROM session
+            WHERE ID=$1 AND UserID=$2 AND RevokedAt IS NULL AND ExpiresAt > now()
+            ",
+                &[&id, &user_id],
+            );
+
+            match query_res {
+                Ok(rows) => Ok(!rows.is_empty()),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        pub fn revoke(db: &postgres::Connection, id: &str) -> Result<(), Error> {
+            let query_res = db.execute(
+                "
+            UPDATE session
+            SET RevokedAt=now()
+            WHERE ID=$1 AND RevokedAt IS NULL
+            ",
+                &[&id],
+            );
+
+            match query_res {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+
+        // revoke_all_for_user ends every session of the user
+        pub fn revoke_all_for_user(db: &postgres::Connection, user_id: i32) -> Result<(), Error> {
+            let query_res = db.execute(
+                "
+            UPDATE session
+            SET RevokedAt=now()
+            WHERE UserID=$1 AND RevokedAt IS NULL
+            ",
+                &[&user_id],
+            );
+
+            match query_res {
+                Ok(_) => Ok(()),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            }
+        }
+    }
+
+    // SessionUser is the request guard for routes that need a logged in
+    // user. It validates the session JWT and loads the user
+    pub struct SessionUser {
+        pub user: User,
+        pub session_id: String,
+    }
+
+    fn unauthorized(message: &str) -> Outcome<SessionUser, WebError> {
+        Outcome::Failure((
+            Status::Unauthorized,
+            WebError::new(401, message.to_string()),
+        ))
+    }
+
+    impl<'r, 'a> FromRequest<'r, 'a> for SessionUser {
+        type Error = WebError;
+        fn from_request(req: &'r Request<'a>) -> Outcome<Self, Self::Error> {
+            let token = match req.cookies().get_private(SESSION_COOKIE) {
+                Some(cookie) => cookie.value().to_string(),
+                None => return unauthorized("unauthorized"),
+            };
+            let api = match req.guard::<State<handlers::Api>>() {
+                Outcome::Success(api) => api,
+                _ => {
+                    return Outcome::Failure((
+                        Status::InternalServerError,
+                        WebError::new(500, "missing api state".to_string()),
+                    ))
+                }
+            };
+            let db = match req.guard::<handlers::MyPgDatabase>() {
+                Outcome::Success(db) => db,
+                _ => {
+                    return Outcome::Failure((
+                        Status::ServiceUnavailable,
+                        WebError::new(503, "database unavailable".to_string()),
+                    ))
+                }
+            };
+
+            let claims = match Jwt::new(&api.config.shared_key).validate_token(&token) {
+                Ok(claims) => claims,
+                Err(_) => return unauthorized("invalid session token"),
+            };
+            let user_id = match claims.sub.parse::<i32>() {
+                Ok(user_id) => user_id,
+                Err(_) => return unauthorized("invalid session token"),
+            };
+            match Session::is_active(&db, &claims.sid, user_id) {
+                Ok(true) => (),
+                Ok(false) => return unauthorized("session expired"),
+                Err(err) => {
+                    log::error!("session.is_active.fail. Cause: {}", err);
+                    return unauthorized("unauthorized");
+                }
+            }
+            match User::get_by_id(&db, user_id) {
+                Ok(user) => Outcome::Success(SessionUser {
+                    user,
+                    session_id: claims.sid,
+                }),
+                Err(_) => unauthorized("unauthorized"),
+            }
+        }
+    }
+}
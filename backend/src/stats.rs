@@ -0,0 +1,278 @@
+pub mod stats {
+    use rocket_contrib::databases::postgres;
+    use std::io::{Error, ErrorKind};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    // MAX_WINDOW_DAYS is the longest window besides all time
+    const MAX_WINDOW_DAYS: i32 = 3650;
+    // REFRESH_INTERVAL is how often the statistics are recomputed while
+    // payouts change them
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+    // Window is the number of days the statistics look back, None for
+    // all time
+    pub struct Window(pub Option<i32>);
+
+    impl Window {
+        // parse reads windows like 7d or 30d and all
+        pub fn parse(window: &str) -> Result<Window, Error> {
+            if window == "all" {
+                return Ok(Window(None));
+            }
+            match window.strip_suffix('d').and_then(|days| days.parse().ok()) {
+                Some(days) if days > 0 && days <= MAX_WINDOW_DAYS => Ok(Window(Some(days))),
+                _ => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    String::from("window must be all or a number of days like 30d"),
+                )),
+            }
+        }
+    }
+
+    // Ranking is what a leaderboard is sorted by
+    pub enum Ranking {
+        Earned,
+        Score,
+    }
+
+    impl Ranking {
+        pub fn parse(ranking: &str) -> Result<Ranking, Error> {
+            match ranking {
+                "earned" => Ok(Ranking::Earned),
+                "score" => Ok(Ranking::Score),
+                _ => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    String::from("rank_by must be earned or score"),
+                )),
+            }
+        }
+
+        fn column(&self) -> &'static str {
+            match self {
+                Ranking::Earned => "Earned",
+                Ranking::Score => "Score",
+            }
+        }
+    }
+
+    // LeaderboardEntry is what a contributor earned in the window. Earned
+    // is in base units of the mint
+    pub struct LeaderboardEntry {
+        pub username: String,
+        pub earned: i64,
+        pub score: f64,
+        pub payouts: i64,
+    }
+
+    // PullRequestStats summarize the merged pull requests of the window
+    pub struct PullRequestStats {
+        pub pull_requests: i64,
+        pub reward: i64,
+        pub median_review_latency_seconds: Option<f64>,
+    }
+
+    // record_review keeps the time of the first review of a pull request
+    pub fn record_review(
+        db: &postgres::Connection,
+        repository: &str,
+        pull_request: i32,
+        author: &str,
+        opened_at: &str,
+        reviewed_at: &str,
+    ) -> Result<(), Error> {
+        let query_res = db.execute(
+            "
+        INSERT INTO pull_request_stat (Repository, PullRequest, Author, OpenedAt, FirstReviewAt)
+        VALUES ($1, $2, $3,
+            COALESCE(CAST(NULLIF($4::TEXT, '') AS TIMESTAMPTZ), now()),
+            COALESCE(CAST(NULLIF($5::TEXT, '') AS TIMESTAMPTZ), now()))
+        ON CONFLICT (Repository, PullRequest)
+        DO UPDATE SET FirstReviewAt=LEAST(pull_request_stat.FirstReviewAt, EXCLUDED.FirstReviewAt)
+        ",
+            &[
+                &repository,
+                &pull_request,
+                &author,
+                &opened_at,
+                &reviewed_at,
+            ],
+        );
+        match query_res {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        }
+    }
+
+    // record_merge stores when a pull request was merged and the reward
+    // its reviewers received in base units
+    pub fn record_merge(
+        db: &postgres::Connection,
+        repository: &str,
+        pull_request: i32,
+        author: &str,
+        opened_at: &str,
+        merged_at: &str,
+        reward: i64,
+    ) -> Result<(), Error> {
+        let query_res = db.execute(
+            "
+        INSERT INTO pull_request_stat (Repository, PullRequest, Author, OpenedAt, MergedAt, Reward)
+        VALUES ($1, $2, $3,
+            COALESCE(CAST(NULLIF($4::TEXT, '') AS TIMESTAMPTZ), now()),
+            COALESCE(CAST(NULLIF($5::TEXT, '') AS TIMESTAMPTZ), now()),
+            $6)
+        ON CONFLICT (Repository, PullRequest)
+        DO UPDATE SET MergedAt=EXCLUDED.MergedAt, Reward=EXCLUDED.Reward
+        ",
+            &[
+                &repository,
+                &pull_request,
+                &author,
+                &opened_at,
+                &merged_at,
+                &reward,
+            ],
+        );
+        match query_res {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        }
+    }
+
+    // refresh recomputes the aggregates the statistics are read from
+    pub fn refresh(db: &postgres::Connection) -> Result<(), Error> {
+        match db.batch_execute(
+            "
+        REFRESH MATERIALIZED VIEW CONCURRENTLY contributor_daily_stat;
+        REFRESH MATERIALIZED VIEW CONCURRENTLY pull_request_daily_stat;
+        ",
+        ) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        }
+    }
+
+    // leaderboard ranks the reviewers, or the authors, of a repository or
+    // of all repositories when repository is None
+    pub fn leaderboard(
+        db: &postgres::Connection,
+        repository: Option<&str>,
+        authors: bool,
+        ranking: &Ranking,
+        window: &Window,
+        limit: i64,
+    ) -> Result<Vec<LeaderboardEntry>, Error> {
+        let rows = match db.query(
+            &format!(
+                "
+        SELECT Username,
+            CAST(SUM(Earned) AS BIGINT) AS Earned,
+            SUM(Score) AS Score,
+            CAST(SUM(Payouts) AS BIGINT) AS Payouts
+        FROM contributor_daily_stat
+        WHERE IsAuthor=$1
+            AND ($2::VARCHAR IS NULL OR Repository=$2)
+            AND ($3::INTEGER IS NULL OR Day >= CURRENT_DATE - $3::INTEGER)
+        GROUP BY Username
+        ORDER BY {} DESC, Username
+        LIMIT $4
+        ",
+                ranking.column()
+            ),
+            &[&authors, &repository, &window.0, &limit],
+        ) {
+            Ok(rows) => rows,
+            Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        };
+        Ok(rows
+            .iter()
+            .map(|row| LeaderboardEntry {
+                username: row.get("Username"),
+                earned: row.get("Earned"),
+                score: row.get("Score"),
+                payouts: row.get("Payouts"),
+            })
+            .collect())
+    }
+
+    // pull_request_stats counts the merged pull requests of a repository,
+    // or of all repositories, with their reward and review latency
+    pub fn pull_request_stats(
+        db: &postgres::Connection,
+        repository: Option<&str>,
+        window: &Window,
+    ) -> Result<PullRequestStats, Error> {
+        let rows = match db.query(
+            "
+        SELECT CAST(COALESCE(SUM(PullRequests), 0) AS BIGINT) AS PullRequests,
+            CAST(COALESCE(SUM(Reward), 0) AS BIGINT) AS Reward,
+            (
+                SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY Latency)
+                FROM pull_request_daily_stat latency_stat, unnest(latency_stat.ReviewLatencies) AS Latency
+                WHERE ($1::VARCHAR IS NULL OR latency_stat.Repository=$1)
+                    AND ($2::INTEGER IS NULL OR latency_stat.Day >= CURRENT_DATE - $2::INTEGER)
+            ) AS MedianReviewLatency
+        FROM pull_request_daily_stat
+        WHERE ($1::VARCHAR IS NULL OR Repository=$1)
+            AND ($2::INTEGER IS NULL OR Day >= CURRENT_DATE - $2::INTEGER)
+        ",
+            &[&repository, &window.0],
+        ) {
+            Ok(rows) => rows,
+            Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+        };
+        for row in &rows {
+            return Ok(PullRequestStats {
+                pull_requests: row.get("PullRequests"),
+                reward: row.get("Reward"),
+                median_review_latency_seconds: row.get("MedianReviewLatency"),
+            });
+        }
+        Err(Error::new(
+            ErrorKind::Other,
+            String::from("pull request stats returned no row"),
+        ))
+    }
+
+    // refresh_in_background recomputes the statistics on a timer once
+    // changed is set, so neither reads nor the webhooks recording payouts
+    // wait for it
+    pub fn refresh_in_background(database_url: String, changed: Arc<AtomicBool>) {
+        thread::spawn(move || loop {
+            thread::sleep(REFRESH_INTERVAL);
+            if !changed.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+            let result =
+                postgres::Connection::connect(database_url.as_str(), postgres::TlsMode::None)
+                    .map_err(|err| Error::new(ErrorKind::Other, format!("{}", err)))
+                    .and_then(|conn| refresh(&conn));
+            if let Err(err) = result {
+                log::error!("stats.refresh.fail. Cause: {}", err);
+                changed.store(true, Ordering::SeqCst);
+            }
+        });
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn window_parse_reads_days_and_all() {
+            assert_eq!(Window::parse("30d").unwrap().0, Some(30));
+            assert_eq!(Window::parse("1d").unwrap().0, Some(1));
+            assert_eq!(Window::parse("all").unwrap().0, None);
+        }
+
+        #[test]
+        fn window_parse_rejects_invalid_windows() {
+            for window in &["", "30", "0d", "-7d", "3651d", "d", "7w", "All"] {
+                assert!(Window::parse(window).is_err(), "{}", window);
+            }
+        }
+    }
+}
@@ -3,86 +3,227 @@ pub mod user {
     use std::io::{Error, ErrorKind};
 
     pub struct User {
+        pub id: i32,
         user_name: String,
         name: String,
+        access_token: Option<Vec<u8>>,
+        // access_token_expires_in is the number of seconds left before the
+        // access token expires. None means the token does not expire
+        pub access_token_expires_in: Option<i64>,
+        refresh_token: Option<Vec<u8>>,
+        pub refresh_token_expires_in: Option<i64>,
+    }
+
+    // Tokens are the encrypted GitHub tokens of a user and the number of
+    // seconds until each of them expires
+    pub struct Tokens {
+        pub access_token: Vec<u8>,
+        pub access_token_expires_in: Option<i64>,
+        pub refresh_token: Option<Vec<u8>>,
+        pub refresh_token_expires_in: Option<i64>,
     }
 
     impl User {
         pub fn new(user_name: &str, name: &str) -> User {
             User {
+                id: 0,
                 user_name: user_name.to_string(),
                 name: name.to_string(),
+                access_token: None,
+                access_token_expires_in: None,
+                refresh_token: None,
+                refresh_token_expires_in: None,
             }
         }
 
-        pub fn create_user_if_not_exist(
-            &self,
+        pub fn user_name(&self) -> &str {
+            &self.user_name
+        }
+
+        pub fn name(&self) -> &str {
+            &self.name
+        }
+
+        // access_token is the encrypted GitHub OAuth token of the user
+        pub fn access_token(&self) -> Option<&[u8]> {
+            self.access_token.as_deref()
+        }
+
+        pub fn refresh_token(&self) -> Option<&[u8]> {
+            self.refresh_token.as_deref()
+        }
+
+        // update_tokens stores new encrypted tokens and their absolute expiry
+        pub fn update_tokens(
+            &mut self,
             db: &postgres::Connection,
-            token: &str,
+            tokens: Tokens,
         ) -> Result<(), Error> {
-            let query_result = db.query(
+            let access_token_expires_in = tokens.access_token_expires_in.map(|secs| secs as f64);
+            let refresh_token_expires_in = tokens.refresh_token_expires_in.map(|secs| secs as f64);
+            let query_res = db.execute(
                 "
-            INSERT INTO github_user (Username, Name)
-            SELECT CAST($1 as varchar),$2
-            WHERE NOT EXISTS (
-                SELECT id FROM github_user where username=$1
+            UPDATE github_user
+            SET AccessToken=$1,
+                AccessTokenExpiresAt=now() + make_interval(secs => $2),
+                RefreshToken=$3,
+                RefreshTokenExpiresAt=now() + make_interval(secs => $4)
+            WHERE ID=$5
+            ",
+                &[
+                    &tokens.access_token,
+                    &access_token_expires_in,
+                    &tokens.refresh_token,
+                    &refresh_token_expires_in,
+                    &self.id,
+                ],
             );
+
+            if let Err(err) = query_res {
+                return Err(Error::new(ErrorKind::Other, format!("{}", err)));
+            }
+            self.access_token = Some(tokens.access_token);
+            self.access_token_expires_in = tokens.access_token_expires_in;
+            self.refresh_token = tokens.refresh_token;
+            self.refresh_token_expires_in = tokens.refresh_token_expires_in;
+            Ok(())
+        }
+
+        // clear_tokens forgets the GitHub tokens of the user, for example
+        // when the refresh token has been revoked
+        pub fn clear_tokens(&mut self, db: &postgres::Connection) -> Result<(), Error> {
+            let query_res = db.execute(
+                "
+            UPDATE github_user
+            SET AccessToken=NULL, AccessTokenExpiresAt=NULL, RefreshToken=NULL, RefreshTokenExpiresAt=NULL
+            WHERE ID=$1
             ",
-                &[&self.user_name, &self.name],
+                &[&self.id],
             );
 
-            match query_result {
-                Ok(_) => return Ok(()),
+            if let Err(err) = query_res {
+                return Err(Error::new(ErrorKind::Other, format!("{}", err)));
+            }
+            self.access_token = None;
+            self.access_token_expires_in = None;
+            self.refresh_token = None;
+            self.refresh_token_expires_in = None;
+            Ok(())
+        }
+
+        // create_user_if_not_exist stores the user, or updates the name
+        // and encrypted access token of an existing user, and sets the id
+        pub fn create_user_if_not_exist(
+            &mut self,
+            db: &postgres::Connection,
+            token: &[u8],
+        ) -> Result<i32, Error> {
+            let query_result = db.query(
+                "
+            INSERT INTO github_user (Username, Name, AccessToken)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (Username) DO UPDATE SET Name=EXCLUDED.Name, AccessToken=EXCLUDED.AccessToken
+            RETURNING ID
+            ",
+                &[&self.user_name, &self.name, &token],
+            );
+
+            let rows = match query_result {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+            for row in &rows {
+                self.id = row.get("ID");
+            }
+            self.access_token = Some(token.to_vec());
+            Ok(self.id)
+        }
+
+        pub fn get_by_id(db: &postgres::Connection, id: i32) -> Result<User, Error> {
+            let rows = match db.query(
+                "
+            SELECT ID, Username, Name, AccessToken, RefreshToken,
+                CAST(EXTRACT(EPOCH FROM AccessTokenExpiresAt - now()) AS BIGINT) AS AccessTokenExpiresIn,
+                CAST(EXTRACT(EPOCH FROM RefreshTokenExpiresAt - now()) AS BIGINT) AS RefreshTokenExpiresIn
+            FROM github_user
+            WHERE ID=$1
+            ",
+                &[&id],
+            ) {
+                Ok(rows) => rows,
                 Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
+
+            for row in &rows {
+                return Ok(User {
+                    id: row.get("ID"),
+                    user_name: row.get("Username"),
+                    name: row.get("Name"),
+                    access_token: row.get("AccessToken"),
+                    access_token_expires_in: row.get("AccessTokenExpiresIn"),
+                    refresh_token: row.get("RefreshToken"),
+                    refresh_token_expires_in: row.get("RefreshTokenExpiresIn"),
+                });
             }
+            Err(Error::new(
+                ErrorKind::NotFound,
+                format!("user {} does not exist", id),
+            ))
         }
 
+        // add_address_to_user links the wallet the rewards of the user are
+        // sent to
         pub fn add_address_to_user(
             &self,
             db: &postgres::Connection,
             address: &str,
         ) -> Result<(), Error> {
-            let query_res = db.query(
+            let query_res = db.execute(
                 "
-            UPDATE github_user(Eaddress) 
-            VALUES ($1)
+            UPDATE github_user
+            SET Eaddress=$1
             WHERE Username=$2
             ",
                 &[&address, &self.user_name],
             );
 
             match query_res {
-                Ok(_) => return Ok(()),
-                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+                Ok(0) => Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("user {} does not exist", self.user_name),
+                )),
+                Ok(_) => Ok(()),
+                Err(err) => Err(Error::new(ErrorKind::Other, format!("{}", err))),
             }
         }
 
+        // get_address_from_username returns the wallet the user linked,
+        // None when the user has not linked one
         pub fn get_address_from_username(
             &self,
             db: &postgres::Connection,
-        ) -> Result<String, Error> {
-            let mut address = String::from("");
-            for row in &db
-                .query(
-                    "
-            SELECT Eaddress 
+        ) -> Result<Option<String>, Error> {
+            let rows = match db.query(
+                "
+            SELECT Eaddress
             FROM github_user
             WHERE Username=$1
             ",
-                    &[&self.user_name],
-                )
-                .unwrap()
-            {
-                address = row.get("Eaddress");
-            }
+                &[&self.user_name],
+            ) {
+                Ok(rows) => rows,
+                Err(err) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+            };
 
-            if address == "" {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("could not retrieve address"),
-                ));
+            let mut address: Option<String> = None;
+            for row in &rows {
+                address = match row.get_opt("Eaddress") {
+                    Some(Ok(address)) => address,
+                    Some(Err(err)) => return Err(Error::new(ErrorKind::Other, format!("{}", err))),
+                    None => None,
+                };
             }
-            Ok(address)
+            Ok(address.filter(|address| !address.is_empty()))
         }
     }
 }